@@ -115,6 +115,9 @@ fn main_possibly_errors() -> io::Result<()> {
 
             extern_crate_lines.push(format!("extern crate {} as {};",
                                             crate_name.replace("-", "_"), imported_name));
+            // Plugin crates are named `zaldinar-plugin-<file name>`.
+            let plugin_name = crate_name.trim_left_matches("zaldinar-plugin-");
+            register_lines.push(format!("register.begin_plugin(\"{}\");", plugin_name));
             register_lines.push(format!("{}::register(register);", imported_name));
         }
 
@@ -1,8 +1,10 @@
 extern crate zaldinar_core;
 
 use std::fmt;
+use std::time::Duration;
 
 use zaldinar_core::client::PluginRegister;
+use zaldinar_core::commands::CommandDescriptor;
 use zaldinar_core::events::CommandEvent;
 
 const MAX_ITERATIONS: u32 = 134217728u32;
@@ -109,7 +111,7 @@ fn brainfuck(event: &CommandEvent) {
     let instructions = match parse_instructions(event) {
         Ok(instructions) => instructions,
         Err(e) => {
-            event.client.send_message(event.channel(), format!("Error: {}", e));
+            event.reply_error(format!("Error: {}", e));
             return;
         }
     };
@@ -146,8 +148,7 @@ fn brainfuck(event: &CommandEvent) {
                 output.push(memory[memory_position as usize] as char);
 
                 if output.len() > MAX_OUTPUT {
-                    event.client.send_message(event.channel(),
-                            "Reached maximum output length. (256)");
+                    event.reply("Reached maximum output length. (256)");
                     done = true;
                     break;
                 }
@@ -169,18 +170,25 @@ fn brainfuck(event: &CommandEvent) {
     }
 
     if !done {
-        event.client.send_message(event.channel(), "Reached maximum iterations. (134217728)");
+        event.reply("Reached maximum iterations. (134217728)");
     }
 
     if output.is_empty() {
-        event.client.send_message(event.channel(), "No output produced.");
+        event.reply("No output produced.");
     } else {
-        event.client.send_message(event.channel(), format!("Output: {}", escape_output(&output)));
+        event.reply(format!("Output: {}", escape_output(&output)));
     }
 }
 
 pub fn register(register: &mut PluginRegister) {
-    register.register_command("brainfuck", brainfuck);
+    register.register_described_command(CommandDescriptor::new("brainfuck")
+        .summary("Runs a brainfuck program and says its output.")
+        .usage("<program>")
+        .example("++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.")
+        .alias("bf")
+        // Programs may run for up to MAX_ITERATIONS steps, which takes a while.
+        .heavy()
+        .time_budget(Duration::from_secs(30)), brainfuck);
 }
 
 fn escape_output(input: &str) -> String {
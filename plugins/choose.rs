@@ -8,6 +8,8 @@ extern crate rand;
 use rand::Rng;
 
 use zaldinar_core::client::PluginRegister;
+use zaldinar_core::commands::CommandDescriptor;
+use zaldinar_core::args::{ArgSpec, ArgKind};
 use zaldinar_core::events::CommandEvent;
 
 macro_rules! regex {
@@ -15,46 +17,49 @@ macro_rules! regex {
 }
 
 fn choose(event: &CommandEvent) {
-    let content = event.args.join(" ");
+    let content = event.parsed.text("options").unwrap();
     let mut rng = rand::thread_rng();
     let split = if content.contains(",") {
-        regex!(r"\s*,\s*").split(&content).collect::<Vec<&str>>()
+        regex!(r"\s*,\s*").split(content).collect::<Vec<&str>>()
     } else {
-        regex!(r"\s+").split(&content).collect::<Vec<&str>>()
+        regex!(r"\s+").split(content).collect::<Vec<&str>>()
     };
     let message = match rng.choose(&split) {
         Some(v) => *v,
         None => "I don't have anything to choose from.",
     };
-    event.client.send_message(event.channel(), message);
+    event.reply(message);
 }
 
 fn coin(event: &CommandEvent) {
     let mut rng = rand::thread_rng();
-    let message = format!("\x01ACTION flips a coin... \x02{}\x02\x01", rng.choose(&["heads",
-                                                                            "tails"]).unwrap());
-    event.client.send_message(event.channel(), message);
+    let message = format!("flips a coin... \x02{}\x02", rng.choose(&["heads", "tails"]).unwrap());
+    event.reply_action(message);
 }
 
 fn rand_command(event: &CommandEvent) {
-    if event.args.len() != 1 {
-        event.client.send_message(event.channel(), "Please specify exactly one argument.");
+    let max = event.parsed.integer("max").unwrap();
+    if max < 1 {
+        event.reply_error("Please give a maximum of at least 1.");
         return;
     }
-    let max = match event.args[0].parse::<u64>() {
-        Ok(v) => v,
-        Err(_) => {
-            event.client.send_message(event.channel(),
-                                        format!("Invalid number '{}'", event.args[0]));
-            return;
-        },
-    };
     let mut rng = rand::thread_rng();
-    event.client.send_message(event.channel(), format!("{}", rng.gen_range(0, max) + 1));
+    event.reply(format!("{}", rng.gen_range(0, max) + 1));
 }
 
 pub fn register(register: &mut PluginRegister) {
-    register.register_command("choose", choose);
-    register.register_command("coin", coin);
-    register.register_command("rand", rand_command);
+    register.register_described_command(CommandDescriptor::new("choose")
+        .summary("Picks one of the given options.")
+        .args(ArgSpec::new().required("options", ArgKind::Rest))
+        .usage("<option>[, <option>...]")
+        .example("tea, coffee, hot chocolate")
+        .category("random"), choose);
+    register.register_described_command(CommandDescriptor::new("coin")
+        .summary("Flips a coin.")
+        .category("random"), coin);
+    register.register_described_command(CommandDescriptor::new("rand")
+        .summary("Picks a random number from 1 to max.")
+        .args(ArgSpec::new().required("max", ArgKind::Integer))
+        .example("6")
+        .category("random"), rand_command);
 }
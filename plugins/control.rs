@@ -3,93 +3,139 @@ extern crate zaldinar_core;
 use zaldinar_core::client::PluginRegister;
 use zaldinar_core::events::CommandEvent;
 use zaldinar_core::client::ExecutingState;
+use zaldinar_core::commands::CommandDescriptor;
+use zaldinar_core::args::{ArgSpec, ArgKind};
+use zaldinar_core::permissions::ADMIN_PERMISSION;
 
-fn action(event: &CommandEvent) {
-    if event.args[0].starts_with("#") {
-        event.client.send_ctcp(&*event.args[0], "ACTION", event.args[1..].join(" "));
-    } else {
-        event.client.send_ctcp(event.channel(), "ACTION", event.args.join(" "));
+/// Gets the channel `admin say` or `admin action` should send to. The permission to use the
+/// command was only checked in the channel it was used in, so if another channel is given, it is
+/// checked there as well.
+fn target_channel<'a>(event: &'a CommandEvent, name: &str) -> Result<&'a str, String> {
+    let channel = match event.parsed.text("channel") {
+        Some(v) => v,
+        None => return Ok(event.channel()),
+    };
+    let plugins = event.client.plugins().read().unwrap();
+    let allowed = match plugins.commands.get(&format!("admin {}", name)) {
+        Some(command) => event.client.can_use_command(event.mask(), Some(channel), &plugins,
+            &command.descriptor),
+        None => false,
+    };
+    if !allowed {
+        return Err(format!("You don't have permission to use this in {}.", channel));
     }
+    return Ok(channel);
 }
 
-fn say(event: &CommandEvent) {
-    if !event.client.is_admin(event) {
-        return;
-    }
-    let (channel, message) = if event.args[0].starts_with("#") {
-        (&*event.args[0], event.args[1..].join(" "))
-    } else {
-        (event.channel(), event.args.join(" "))
-    };
-    event.client.send_message(channel, message);
+fn action(event: &CommandEvent) -> Result<(), String> {
+    let channel = try!(target_channel(event, "action"));
+    event.client.send_ctcp(channel, "ACTION", event.parsed.text("action").unwrap());
+    return Ok(());
+}
+
+fn say(event: &CommandEvent) -> Result<(), String> {
+    let channel = try!(target_channel(event, "say"));
+    event.client.send_message(channel, event.parsed.text("message").unwrap());
     event.client.reply_notice(event, format!("Sent message to {}.", channel));
+    return Ok(());
 }
 
 fn quit(event: &CommandEvent) {
     event.client.reply_notice(event, format!("Leaving server."));
-    if event.args.len() != 0 {
-        event.client.quit(Some(event.args.join(" ")), ExecutingState::Done);
-    } else {
-        event.client.quit::<&str>(None, ExecutingState::Done);
-    }
+    event.client.quit(event.parsed.text("reason"), ExecutingState::Done);
 }
 
 fn restart(event: &CommandEvent) {
     event.client.reply_notice(event, format!("Restarting."));
-    if event.args.len() != 0 {
-        event.client.quit(Some(event.args.join(" ")), ExecutingState::RestartTryExec);
-    } else {
-        event.client.quit(Some("Restarting"), ExecutingState::RestartTryExec);
-    }
+    let reason = event.parsed.text("reason").unwrap_or("Restarting");
+    event.client.quit(Some(reason), ExecutingState::RestartTryExec);
 }
 
 fn raw(event: &CommandEvent) {
-    event.client.send_raw(event.args.join(" "));
+    event.client.send_raw(event.parsed.text("line").unwrap().to_string());
     event.client.reply_notice(event, "Sent raw message.");
 }
 
 fn join(event: &CommandEvent) {
-    if event.args.is_empty() {
-        event.client.reply_notice(event, "Please specify a channel to join.");
-        return;
-    }
-    event.client.join(&*event.args[0]);
-    event.client.reply_notice(event, format!("Joined {}.", event.args[0]));
+    let channel = event.parsed.text("channel").unwrap();
+    event.client.join(channel);
+    event.client.reply_notice(event, format!("Joined {}.", channel));
 }
 
 fn part(event: &CommandEvent) {
-    let (channel, reason) = if event.args.is_empty() {
-        (event.channel(), None)
-    } else if event.args[0].starts_with("#") {
-        if event.args.len() > 1 {
-            (&*event.args[0], Some(event.args[1..].join(" ")))
-        } else {
-            (&*event.args[0], None)
-        }
-    } else {
-        (event.channel(), Some(event.args.join(" ")))
-    };
-    event.client.part(channel, reason);
+    let channel = event.parsed.text("channel").unwrap_or(event.channel());
+    event.client.part(channel, event.parsed.text("reason"));
     event.client.reply_notice(event, format!("Parted {}.", channel));
 }
 
 fn message(event: &CommandEvent) {
-    if event.args.len() < 2 {
-        event.client.reply_notice(event, "Please specify both a channel and a message to send");
-        return;
+    let target = event.parsed.text("target").unwrap();
+    event.client.send_message(target, event.parsed.text("message").unwrap());
+    event.client.reply_notice(event, format!("Sent message to {}.", target));
+}
+
+fn enable(event: &CommandEvent) -> Result<(), String> {
+    let plugin = event.parsed.text("plugin").unwrap();
+    if !event.client.circuit_breaker().lock().unwrap().reset(plugin) {
+        return Err(format!("The {} plugin isn't disabled.", plugin));
     }
-    event.client.send_message(&*event.args[0], event.args[1..].join(" "));
-    event.client.reply_notice(event, format!("Sent message to {}.", event.args[0]));
+    event.client.reply_notice(event, format!("Enabled the {} plugin again.", plugin));
+    return Ok(());
+}
+
+fn roles(event: &CommandEvent) {
+    // `roles` is channel only, so this is always a channel.
+    let roles = event.client.roles(event.mask(), Some(event.channel()));
+    if roles.is_empty() {
+        event.reply("You don't hold any roles here.");
+    } else {
+        event.reply(format!("Your roles here: {}", roles.join(", ")));
+    }
+}
+
+/// Describes a subcommand of `admin`. These need the `admin` permission through the group,
+/// unless the configuration says otherwise.
+fn admin_command(name: &str, summary: &str) -> CommandDescriptor {
+    return CommandDescriptor::new(&format!("admin {}", name)).summary(summary);
 }
 
 pub fn register(register: &mut PluginRegister) {
-    register.register_admin_command("say", say);
-    register.register_admin_command("message", message);
-    register.register_admin_command("action", action);
-    register.register_admin_command("raw", raw);
-    register.register_admin_command("join", join);
-    register.register_admin_command("part", part);
-    register.register_admin_command("quit", quit);
-    register.register_admin_command("restart", restart);
-    // register.register_command("nick", nick);
+    register.register_group(CommandDescriptor::new("admin")
+        .summary("Commands for controlling the bot.")
+        .permission(ADMIN_PERMISSION));
+    register.register_described_command(admin_command("say", "Says a message.")
+        .args(ArgSpec::new()
+            .optional("channel", ArgKind::Channel)
+            .required("message", ArgKind::Rest)), say);
+    register.register_described_command(admin_command("message", "Sends a private message.")
+        .args(ArgSpec::new()
+            .required("target", ArgKind::Word)
+            .required("message", ArgKind::Rest))
+        .alias("msg"), message);
+    register.register_described_command(admin_command("action", "Sends a /me action.")
+        .args(ArgSpec::new()
+            .optional("channel", ArgKind::Channel)
+            .required("action", ArgKind::Rest))
+        .alias("me"), action);
+    register.register_described_command(admin_command("raw", "Sends a raw IRC line.")
+        .args(ArgSpec::new().required("line", ArgKind::Rest))
+        .example("MODE #zaldinar +o Dabo")
+        .private_only(), raw);
+    register.register_described_command(admin_command("join", "Joins a channel.")
+        .args(ArgSpec::new().required("channel", ArgKind::Channel)), join);
+    register.register_described_command(admin_command("part", "Leaves a channel.")
+        .args(ArgSpec::new()
+            .optional("channel", ArgKind::Channel)
+            .optional("reason", ArgKind::Rest)), part);
+    register.register_described_command(admin_command("quit", "Disconnects and exits.")
+        .args(ArgSpec::new().optional("reason", ArgKind::Rest)), quit);
+    register.register_described_command(admin_command("restart", "Restarts the bot.")
+        .args(ArgSpec::new().optional("reason", ArgKind::Rest)), restart);
+    register.register_described_command(admin_command("enable", "Enables a plugin which was \
+        disabled after failing repeatedly.")
+        .args(ArgSpec::new().required("plugin", ArgKind::Word)), enable);
+    register.register_described_command(CommandDescriptor::new("roles")
+        .summary("Lists the roles you hold in this channel.")
+        .channel_only(), roles);
+    // register.register_command("admin nick", nick);
 }
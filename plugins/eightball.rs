@@ -1,26 +1,62 @@
 //! depends: rand = "0.3"
+//! depends: serde = "1.0"
+//! depends: serde_derive = "1.0"
 extern crate zaldinar_core;
 extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 use rand::Rng;
 
 use zaldinar_core::client::PluginRegister;
+use zaldinar_core::commands::CommandDescriptor;
+use zaldinar_core::config::PluginConfig;
 use zaldinar_core::events::CommandEvent;
 
 const MESSAGES: &'static str = include_str!("resources/eightball/responses.txt");
 
-fn eightball(event: &CommandEvent) {
+/// Responses start with `<yes>` or `<no>` to be coloured as a positive or negative answer.
+#[derive(Deserialize)]
+struct EightballConfig {
+    /// Replaces the built-in responses.
+    #[serde(default = "default_responses")]
+    responses: Vec<String>,
+    /// Added to the responses.
+    #[serde(default)]
+    extra_responses: Vec<String>,
+}
+
+fn default_responses() -> Vec<String> {
+    return MESSAGES.split('\n').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
+}
+
+fn validate(config: &EightballConfig) -> Result<(), String> {
+    if config.responses.is_empty() && config.extra_responses.is_empty() {
+        return Err("There must be at least one response.".to_string());
+    }
+    return Ok(());
+}
+
+fn eightball(event: &CommandEvent, config: &PluginConfig<EightballConfig>) {
     if event.args.is_empty() {
-        event.client.send_message(event.channel(), "I can't answer if you don't ask.");
+        event.reply_error("I can't answer if you don't ask.");
         return;
     }
-    let messages = MESSAGES.split('\n').collect::<Vec<&str>>();
+    let config = config.get();
+    let messages = config.responses.iter().chain(config.extra_responses.iter())
+        .collect::<Vec<&String>>();
     let mut rng = rand::thread_rng();
     let message = rng.choose(&messages).unwrap()
                     .replace("<yes>", "\x0305").replace("<no>", "\x0303");
-    event.client.send_message(event.channel(), format!("\x01ACTION shakes the magic 8 ball... \x02{}\x01", message));
+    event.reply_action(format!("shakes the magic 8 ball... \x02{}", message));
 }
 
 pub fn register(register: &mut PluginRegister) {
-    register.register_command("8ball", eightball);
+    let config = register.validated_config(validate);
+    register.register_described_command(CommandDescriptor::new("8ball")
+        .summary("Answers a yes or no question.")
+        .usage("<question>")
+        .example("Will it rain tomorrow?")
+        .alias("eightball"), move |event| eightball(event, &config));
 }
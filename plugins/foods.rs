@@ -5,6 +5,7 @@ extern crate zaldinar_core;
 use rand::Rng;
 
 use zaldinar_core::client::PluginRegister;
+use zaldinar_core::commands::CommandDescriptor;
 use zaldinar_core::events::CommandEvent;
 
 // This file is taken periodically from: http://en.wikipedia.org/wiki/List_of_cakes
@@ -19,7 +20,7 @@ fn cake(event: &CommandEvent) {
     let cake = match rng.choose(CAKES) {
         Some(cake) => cake,
         None => {
-            event.client.send_message(event.channel(), "No cakes found!");
+            event.reply("No cakes found!");
             return;
         },
     };
@@ -27,9 +28,11 @@ fn cake(event: &CommandEvent) {
         "unknown" => format!(r#"The "{}" cake: {}!"#, cake.name, cake.info),
         _ => format!(r#"The "{}" cake: {} - founded in {}!"#, cake.name, cake.info, cake.location),
     };
-    event.client.send_message(event.channel(), message);
+    event.reply(message);
 }
 
 pub fn register(register: &mut PluginRegister) {
-    register.register_command("randomcake", cake);
+    register.register_described_command(CommandDescriptor::new("randomcake")
+        .summary("Describes a random cake.")
+        .alias("cake"), cake);
 }
@@ -1,6 +1,7 @@
 extern crate zaldinar_core;
 
 use zaldinar_core::client::PluginRegister;
+use zaldinar_core::commands::CommandDescriptor;
 use zaldinar_core::events::CommandEvent;
 
 fn command(event: &CommandEvent) {
@@ -8,5 +9,7 @@ fn command(event: &CommandEvent) {
 }
 
 pub fn register(register: &mut PluginRegister) {
-    register.register_command("", command);
+    register.register_described_command(CommandDescriptor::new("")
+        .summary("")
+        .usage(""), command);
 }
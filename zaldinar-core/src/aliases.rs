@@ -0,0 +1,263 @@
+use std::ascii::AsciiExt;
+use std::collections;
+use std::path;
+
+use config;
+use data;
+use errors::ThrowInitError;
+
+/// How many times aliases may expand into other aliases before a command is given up on. This
+/// stops aliases which refer to each other from looping forever.
+pub const MAX_ALIAS_DEPTH: usize = 8;
+
+/// An alias created at runtime with the `alias` command.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Alias {
+    pub name: String,
+    pub expansion: String,
+    pub added_by: String,
+}
+
+/// What a command turned out to be after following aliases, from `AliasTable::resolve`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Resolved {
+    /// A real command, with its arguments.
+    Command(String, Vec<String>),
+    /// Neither a command nor an alias.
+    Unknown(String),
+    /// An alias which expanded to nothing.
+    Empty,
+    /// An alias which was still expanding into other aliases after `MAX_ALIAS_DEPTH` expansions.
+    TooDeep(String),
+}
+
+/// Command aliases, from `aliases` in the configuration and the `alias` command, which is stored
+/// in `aliases.json` in the data directory.
+///
+/// An alias expands to a command and its arguments, as in `randomcake` or
+/// `admin say $channel Hello, $1!`. Aliases created at runtime take priority over configured ones
+/// of the same name.
+pub struct AliasTable {
+    path: path::PathBuf,
+    /// Configured expansions by lowercase name.
+    configured: collections::BTreeMap<String, String>,
+    /// Runtime aliases by lowercase name.
+    runtime: collections::BTreeMap<String, Alias>,
+}
+
+impl AliasTable {
+    pub fn load(config: &config::ClientConfiguration, path: &path::Path)
+            -> Result<AliasTable, ThrowInitError> {
+        let saved: Vec<Alias> = up!(data::load_json(path)).unwrap_or_else(Vec::new);
+        return Ok(AliasTable {
+            path: path.to_path_buf(),
+            configured: config.aliases.iter()
+                .map(|(name, expansion)| (name.to_ascii_lowercase(), expansion.clone()))
+                .collect(),
+            runtime: saved.into_iter().map(|alias| (alias.name.to_ascii_lowercase(), alias))
+                .collect(),
+        });
+    }
+
+    pub fn save(&self) -> Result<(), ThrowInitError> {
+        let aliases = self.runtime.values().collect::<Vec<&Alias>>();
+        return data::save_json(&self.path, &aliases);
+    }
+
+    /// Gets the expansion of an alias.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        return match self.runtime.get(&name) {
+            Some(alias) => Some(&alias.expansion),
+            None => self.configured.get(&name).map(|s| &**s),
+        };
+    }
+
+    /// Checks whether an alias is from the configuration, and can't be removed at runtime.
+    pub fn is_configured(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        return !self.runtime.contains_key(&name) && self.configured.contains_key(&name);
+    }
+
+    /// Adds an alias, replacing any runtime alias of the same name.
+    pub fn add(&mut self, alias: Alias) {
+        self.runtime.insert(alias.name.to_ascii_lowercase(), alias);
+    }
+
+    /// Removes a runtime alias, returning it if there was one.
+    pub fn remove(&mut self, name: &str) -> Option<Alias> {
+        return self.runtime.remove(&name.to_ascii_lowercase());
+    }
+
+    /// Lists every alias by name, with its expansion.
+    pub fn aliases(&self) -> Vec<(&str, &str)> {
+        let mut names = self.configured.keys().chain(self.runtime.keys())
+            .map(|s| &**s).collect::<Vec<&str>>();
+        names.sort();
+        names.dedup();
+        return names.into_iter().map(|name| (name, self.get(name).unwrap())).collect();
+    }
+
+    /// Follows aliases from `command` used with `args`, by `nick` in `channel`, until reaching a
+    /// command for which `is_command` returns true.
+    pub fn resolve<F>(&self, command: &str, args: &[String], nick: &str, channel: &str,
+            is_command: F) -> Resolved where F: Fn(&str, &[String]) -> bool {
+        let mut command = command.to_string();
+        let mut args = args.to_vec();
+        for _ in 0..MAX_ALIAS_DEPTH {
+            if is_command(&command, &args) {
+                return Resolved::Command(command, args);
+            }
+            let expanded = match self.get(&command) {
+                Some(expansion) => expand(expansion, &args, nick, channel),
+                None => return Resolved::Unknown(command),
+            };
+            let mut words = expanded.split(' ').filter(|s| !s.is_empty()).map(|s| s.to_string());
+            command = match words.next() {
+                Some(v) => v,
+                None => return Resolved::Empty,
+            };
+            args = words.collect();
+        }
+        if is_command(&command, &args) {
+            return Resolved::Command(command, args);
+        } else if self.get(&command).is_none() {
+            return Resolved::Unknown(command);
+        } else {
+            return Resolved::TooDeep(command);
+        }
+    }
+}
+
+/// Expands an alias used with `args`, by `nick` in `channel`.
+///
+/// `$1` to `$9` are replaced by the argument at that position, or nothing if there isn't one,
+/// `$*` by all arguments, `$nick` and `$channel` by the user and channel, and `$$` by a single
+/// `$`. If the expansion uses no arguments, they are added to the end of it instead, so that
+/// `cake` expanding to `randomcake` passes on anything given to it.
+pub fn expand(expansion: &str, args: &[String], nick: &str, channel: &str) -> String {
+    let mut result = String::with_capacity(expansion.len());
+    let mut used_args = false;
+    let mut rest = expansion;
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        match rest.chars().next() {
+            Some(c @ '1'...'9') => {
+                if let Some(arg) = args.get(c as usize - '1' as usize) {
+                    result.push_str(arg);
+                }
+                used_args = true;
+                rest = &rest[1..];
+            },
+            Some('*') => {
+                result.push_str(&args.join(" "));
+                used_args = true;
+                rest = &rest[1..];
+            },
+            Some('$') => {
+                result.push('$');
+                rest = &rest[1..];
+            },
+            _ if rest.starts_with("nick") => {
+                result.push_str(nick);
+                rest = &rest["nick".len()..];
+            },
+            _ if rest.starts_with("channel") => {
+                result.push_str(channel);
+                rest = &rest["channel".len()..];
+            },
+            _ => result.push('$'),
+        }
+    }
+    result.push_str(rest);
+    if !used_args && !args.is_empty() {
+        result.push(' ');
+        result.push_str(&args.join(" "));
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections;
+    use std::path;
+
+    use super::{expand, Alias, AliasTable, Resolved, MAX_ALIAS_DEPTH};
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn table(configured: &[(&str, &str)]) -> AliasTable {
+        return AliasTable {
+            path: path::PathBuf::new(),
+            configured: configured.iter().map(|&(name, expansion)| {
+                (name.to_string(), expansion.to_string())
+            }).collect(),
+            runtime: collections::BTreeMap::new(),
+        };
+    }
+
+    #[test]
+    fn expand_substitutions() {
+        let args = strings(&["a", "b"]);
+        assert_eq!(expand("say $channel hi $1 ($nick) $*", &args, "n", "#c"),
+            "say #c hi a (n) a b");
+        assert_eq!(expand("x $3 $$1 $foo", &args, "n", "#c"), "x  $1 $foo");
+        assert_eq!(expand("x $", &[], "n", "#c"), "x $");
+    }
+
+    #[test]
+    fn expand_appends_unused_args() {
+        let args = strings(&["a", "b"]);
+        assert_eq!(expand("randomcake", &args, "n", "#c"), "randomcake a b");
+        assert_eq!(expand("randomcake $nick", &args, "n", "#c"), "randomcake n a b");
+        assert_eq!(expand("randomcake", &[], "n", "#c"), "randomcake");
+    }
+
+    #[test]
+    fn runtime_aliases_take_priority() {
+        let mut aliases = table(&[("cake", "randomcake")]);
+        assert!(aliases.is_configured("CAKE"));
+        aliases.add(Alias {
+            name: "Cake".to_string(),
+            expansion: "info".to_string(),
+            added_by: "n".to_string(),
+        });
+        assert_eq!(aliases.get("cake"), Some("info"));
+        assert!(!aliases.is_configured("cake"));
+        assert_eq!(aliases.aliases(), vec![("cake", "info")]);
+    }
+
+    #[test]
+    fn resolve_follows_aliases() {
+        let aliases = table(&[("hi", "greet $nick"), ("greet", "admin say $channel Hello, $1!")]);
+        let is_command = |command: &str, _: &[String]| command == "admin";
+        assert_eq!(aliases.resolve("hi", &[], "n", "#c", &is_command),
+            Resolved::Command("admin".to_string(), strings(&["say", "#c", "Hello,", "n!"])));
+        assert_eq!(aliases.resolve("nope", &[], "n", "#c", &is_command),
+            Resolved::Unknown("nope".to_string()));
+    }
+
+    #[test]
+    fn resolve_empty_expansion() {
+        let aliases = table(&[("blank", "$1")]);
+        assert_eq!(aliases.resolve("blank", &[], "n", "#c", |_, _| false), Resolved::Empty);
+    }
+
+    #[test]
+    fn resolve_recursion_limit() {
+        let aliases = table(&[("a", "b"), ("b", "a")]);
+        assert_eq!(aliases.resolve("a", &[], "n", "#c", |_, _| false),
+            Resolved::TooDeep("a".to_string()));
+
+        // A chain exactly MAX_ALIAS_DEPTH long still reaches the command.
+        let names = (0..MAX_ALIAS_DEPTH + 1).map(|i| format!("a{}", i)).collect::<Vec<_>>();
+        let chain = names.windows(2).map(|w| (&*w[0], &*w[1])).collect::<Vec<_>>();
+        let aliases = table(&chain);
+        let last = names.last().unwrap().clone();
+        assert_eq!(aliases.resolve("a0", &[], "n", "#c", |command, _| command == last),
+            Resolved::Command(last.clone(), Vec::new()));
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections;
+use std::fmt;
+use std::time;
+
+use duration;
+use isupport::ServerSupport;
+
+/// The type of a command argument.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ArgKind {
+    /// Any single word, or a string in double quotes.
+    Word,
+    /// A channel name, using the channel types the server supports.
+    Channel,
+    /// A syntactically valid nick.
+    Nick,
+    /// A whole number.
+    Integer,
+    /// A duration such as `30m` or `1d12h`.
+    Duration,
+    /// Everything left on the line, exactly as typed. Must be the last argument.
+    Rest,
+}
+
+impl fmt::Display for ArgKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(match self {
+            &ArgKind::Word => "word",
+            &ArgKind::Channel => "channel",
+            &ArgKind::Nick => "nick",
+            &ArgKind::Integer => "number",
+            &ArgKind::Duration => "duration",
+            &ArgKind::Rest => "text",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ArgDef {
+    name: String,
+    kind: ArgKind,
+    optional: bool,
+}
+
+#[derive(Clone, Debug)]
+struct FlagDef {
+    name: String,
+    /// The kind of the value following the flag, or None if the flag takes no value.
+    value: Option<ArgKind>,
+}
+
+/// A declarative description of the arguments a command takes.
+///
+/// Flags, such as `-all` or `-for 1h`, must come before any other arguments. Optional
+/// arguments are skipped if the next word isn't of their kind, or is needed by a later required
+/// argument. For example, `say` uses
+/// `ArgSpec::new().optional("channel", ArgKind::Channel).required("message", ArgKind::Rest)`.
+#[derive(Clone, Debug)]
+pub struct ArgSpec {
+    args: Vec<ArgDef>,
+    flags: Vec<FlagDef>,
+}
+
+impl ArgSpec {
+    pub fn new() -> ArgSpec {
+        return ArgSpec {
+            args: Vec::new(),
+            flags: Vec::new(),
+        };
+    }
+
+    pub fn required(mut self, name: &str, kind: ArgKind) -> ArgSpec {
+        self.args.push(ArgDef {
+            name: name.to_string(),
+            kind: kind,
+            optional: false,
+        });
+        return self;
+    }
+
+    pub fn optional(mut self, name: &str, kind: ArgKind) -> ArgSpec {
+        self.args.push(ArgDef {
+            name: name.to_string(),
+            kind: kind,
+            optional: true,
+        });
+        return self;
+    }
+
+    /// Adds a flag written as `-name`, which takes no value.
+    pub fn flag(mut self, name: &str) -> ArgSpec {
+        self.flags.push(FlagDef {
+            name: name.to_string(),
+            value: None,
+        });
+        return self;
+    }
+
+    /// Adds a flag written as `-name <value>`. Its value is read like an argument named `name`.
+    pub fn flag_with_value(mut self, name: &str, kind: ArgKind) -> ArgSpec {
+        self.flags.push(FlagDef {
+            name: name.to_string(),
+            value: Some(kind),
+        });
+        return self;
+    }
+
+    /// Gets a usage string, such as `[-all] [-for <duration>] <target> [reason...]`.
+    pub fn usage(&self) -> String {
+        let flags = self.flags.iter().map(|flag| match flag.value {
+            Some(kind) => format!("[-{} <{}>]", flag.name, kind),
+            None => format!("[-{}]", flag.name),
+        });
+        let args = self.args.iter().map(|arg| {
+            let rest = if arg.kind == ArgKind::Rest { "..." } else { "" };
+            if arg.optional {
+                format!("[{}{}]", arg.name, rest)
+            } else {
+                format!("<{}{}>", arg.name, rest)
+            }
+        });
+        return flags.chain(args).collect::<Vec<String>>().join(" ");
+    }
+
+    /// Parses a command's arguments according to this spec.
+    pub fn parse<S: AsRef<str>>(&self, args: &[S], support: &ServerSupport)
+            -> Result<ParsedArgs, ArgError> {
+        let line = args.iter().map(|s| s.as_ref()).collect::<Vec<&str>>().join(" ");
+        let tokens = try!(tokenize(&line));
+        let mut parsed = ParsedArgs::new();
+        let mut position = 0;
+
+        // Flags
+        while let Some(token) = tokens.get(position) {
+            if !token.text.starts_with("-") || token.quoted {
+                break;
+            }
+            let flag = match self.flags.iter().find(|f| f.name == token.text[1..]) {
+                Some(v) => v,
+                None => break,
+            };
+            position += 1;
+            match flag.value {
+                Some(kind) => {
+                    let value_token = match tokens.get(position) {
+                        Some(v) => v,
+                        None => return Err(ArgError::MissingFlagValue(flag.name.clone())),
+                    };
+                    match convert(kind, &value_token.text, support) {
+                        Some(value) => parsed.values.insert(flag.name.clone(), value),
+                        None => return Err(ArgError::Invalid {
+                            name: flag.name.clone(),
+                            value: value_token.text.clone(),
+                            kind: kind,
+                        }),
+                    };
+                    position += 1;
+                },
+                None => {
+                    parsed.flags.insert(flag.name.clone());
+                },
+            }
+        }
+
+        // Positional arguments
+        for (index, arg) in self.args.iter().enumerate() {
+            if arg.kind == ArgKind::Rest {
+                match tokens.get(position) {
+                    Some(token) => {
+                        parsed.values.insert(arg.name.clone(),
+                            ArgValue::Text(line[token.start..].to_string()));
+                        position = tokens.len();
+                    },
+                    None if arg.optional => (),
+                    None => return Err(ArgError::Missing(arg.name.clone())),
+                }
+                break;
+            }
+            let token = match tokens.get(position) {
+                Some(v) => v,
+                None if arg.optional => continue,
+                None => return Err(ArgError::Missing(arg.name.clone())),
+            };
+            let required_after = self.args[index + 1..].iter().filter(|a| !a.optional).count();
+            let needed_later = tokens.len() - position <= required_after;
+            match convert(arg.kind, &token.text, support) {
+                Some(value) => {
+                    if arg.optional && needed_later {
+                        continue;
+                    }
+                    parsed.values.insert(arg.name.clone(), value);
+                    position += 1;
+                },
+                None if arg.optional => continue,
+                None => return Err(ArgError::Invalid {
+                    name: arg.name.clone(),
+                    value: token.text.clone(),
+                    kind: arg.kind,
+                }),
+            }
+        }
+
+        if position < tokens.len() {
+            return Err(ArgError::TooMany);
+        }
+        return Ok(parsed);
+    }
+}
+
+/// A parsed argument value.
+#[derive(Clone, Debug)]
+pub enum ArgValue {
+    Text(String),
+    Integer(i64),
+    Duration(time::Duration),
+}
+
+/// Arguments parsed by an `ArgSpec`, looked up by name.
+#[derive(Clone, Debug, Default)]
+pub struct ParsedArgs {
+    values: collections::HashMap<String, ArgValue>,
+    flags: collections::HashSet<String>,
+}
+
+impl ParsedArgs {
+    pub fn new() -> ParsedArgs {
+        return ParsedArgs::default();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ArgValue> {
+        self.values.get(name)
+    }
+
+    /// Gets a word, channel, nick or rest-of-line argument.
+    pub fn text(&self, name: &str) -> Option<&str> {
+        match self.values.get(name) {
+            Some(&ArgValue::Text(ref s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        match self.values.get(name) {
+            Some(&ArgValue::Integer(i)) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self, name: &str) -> Option<time::Duration> {
+        match self.values.get(name) {
+            Some(&ArgValue::Duration(d)) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Checks whether a flag without a value was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ArgError {
+    /// A required argument is missing.
+    Missing(String),
+    /// An argument or flag value isn't of the right kind.
+    Invalid {
+        name: String,
+        value: String,
+        kind: ArgKind,
+    },
+    /// A flag which takes a value was given last.
+    MissingFlagValue(String),
+    TooMany,
+    UnclosedQuote,
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &ArgError::Missing(ref name) => write!(fmt, "Missing <{}>.", name),
+            &ArgError::Invalid { ref name, ref value, kind } => {
+                write!(fmt, "`{}` isn't a valid {} for <{}>.", value, kind, name)
+            },
+            &ArgError::MissingFlagValue(ref name) => write!(fmt, "-{} needs a value.", name),
+            &ArgError::TooMany => fmt.write_str("Too many arguments."),
+            &ArgError::UnclosedQuote => fmt.write_str("Unclosed quote."),
+        }
+    }
+}
+
+struct Token {
+    text: String,
+    /// Byte offset of the token in the line, including any opening quote.
+    start: usize,
+    quoted: bool,
+}
+
+/// Splits a line into words, treating text in double quotes as one word. Inside quotes, `\"`
+/// and `\\` are a literal quote and backslash.
+fn tokenize(line: &str) -> Result<Vec<Token>, ArgError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c == ' ' {
+            chars.next();
+            continue;
+        }
+        let mut text = String::new();
+        if c == '"' {
+            chars.next();
+            let mut closed = false;
+            while let Some((_, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        closed = true;
+                        break;
+                    },
+                    '\\' => match chars.peek() {
+                        Some(&(_, next)) if next == '"' || next == '\\' => {
+                            text.push(next);
+                            chars.next();
+                        },
+                        _ => text.push(c),
+                    },
+                    _ => text.push(c),
+                }
+            }
+            if !closed {
+                return Err(ArgError::UnclosedQuote);
+            }
+        } else {
+            while let Some(&(_, c)) = chars.peek() {
+                if c == ' ' {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(Token {
+            text: text,
+            start: start,
+            quoted: c == '"',
+        });
+    }
+    return Ok(tokens);
+}
+
+fn convert(kind: ArgKind, text: &str, support: &ServerSupport) -> Option<ArgValue> {
+    let valid = match kind {
+        ArgKind::Word | ArgKind::Rest => true,
+        ArgKind::Channel => support.is_channel(text),
+        ArgKind::Nick => is_valid_nick(text),
+        ArgKind::Integer => return text.parse().ok().map(ArgValue::Integer),
+        ArgKind::Duration => return duration::parse(text).map(ArgValue::Duration),
+    };
+    if valid {
+        return Some(ArgValue::Text(text.to_string()));
+    } else {
+        return None;
+    }
+}
+
+/// Checks whether `nick` is a valid nick according to RFC 2812.
+fn is_valid_nick(nick: &str) -> bool {
+    let is_special = |c: char| "[]\\`_^{|}".contains(c);
+    let mut chars = nick.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || is_special(c) => (),
+        _ => return false,
+    }
+    return chars.all(|c| c.is_ascii_alphanumeric() || is_special(c) || c == '-');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time;
+
+    use isupport::ServerSupport;
+    use super::{tokenize, ArgError, ArgKind, ArgSpec};
+
+    fn words(line: &str) -> Vec<&str> {
+        line.split(' ').filter(|w| !w.is_empty()).collect()
+    }
+
+    fn tokens(line: &str) -> Vec<(String, usize, bool)> {
+        tokenize(line).unwrap().into_iter().map(|t| (t.text, t.start, t.quoted)).collect()
+    }
+
+    #[test]
+    fn tokenize_quotes() {
+        assert_eq!(tokens("a  \"b c\" d"), vec![
+            ("a".to_string(), 0, false),
+            ("b c".to_string(), 3, true),
+            ("d".to_string(), 9, false),
+        ]);
+        assert_eq!(tokens(r#""say \"hi\" \\ \n""#),
+            vec![(r#"say "hi" \ \n"#.to_string(), 0, true)]);
+        assert_eq!(tokens("\"\""), vec![(String::new(), 0, true)]);
+        assert!(tokenize("\"abc").is_err());
+    }
+
+    #[test]
+    fn optional_arguments_are_skipped_when_they_do_not_fit() {
+        let support = ServerSupport::default();
+        let spec = ArgSpec::new().optional("channel", ArgKind::Channel)
+            .required("message", ArgKind::Rest);
+        let parsed = spec.parse(&words("#chan hello there"), &support).unwrap();
+        assert_eq!(parsed.text("channel"), Some("#chan"));
+        assert_eq!(parsed.text("message"), Some("hello there"));
+
+        let parsed = spec.parse(&words("hello"), &support).unwrap();
+        assert_eq!(parsed.text("channel"), None);
+        assert_eq!(parsed.text("message"), Some("hello"));
+
+        // The required argument takes priority over the optional one before it.
+        let parsed = spec.parse(&words("#only"), &support).unwrap();
+        assert_eq!(parsed.text("channel"), None);
+        assert_eq!(parsed.text("message"), Some("#only"));
+
+        assert_eq!(spec.parse(&words(""), &support).unwrap_err(),
+            ArgError::Missing("message".to_string()));
+    }
+
+    #[test]
+    fn flags_and_quoted_arguments() {
+        let support = ServerSupport::default();
+        let spec = ArgSpec::new().flag("all").flag_with_value("for", ArgKind::Duration)
+            .required("target", ArgKind::Word).optional("reason", ArgKind::Rest);
+        assert_eq!(spec.usage(), "[-all] [-for <duration>] <target> [reason...]");
+
+        let parsed = spec.parse(&words("-all -for 1h \"some nick\" being -annoying"), &support)
+            .unwrap();
+        assert!(parsed.flag("all"));
+        assert_eq!(parsed.duration("for"), Some(time::Duration::from_secs(60 * 60)));
+        assert_eq!(parsed.text("target"), Some("some nick"));
+        assert_eq!(parsed.text("reason"), Some("being -annoying"));
+
+        assert!(spec.parse(&words("-for x y"), &support).is_err());
+        assert_eq!(spec.parse(&words("\"abc"), &support).unwrap_err(), ArgError::UnclosedQuote);
+    }
+
+    #[test]
+    fn kinds_are_checked() {
+        let support = ServerSupport::default();
+        let spec = ArgSpec::new().required("max", ArgKind::Integer);
+        assert_eq!(spec.parse(&words("5"), &support).unwrap().integer("max"), Some(5));
+        assert_eq!(spec.parse(&words("5 6"), &support).unwrap_err(), ArgError::TooMany);
+        assert!(spec.parse(&words("x"), &support).is_err());
+
+        let spec = ArgSpec::new().required("nick", ArgKind::Nick);
+        assert!(spec.parse(&words("Dabo[away]"), &support).is_ok());
+        assert!(spec.parse(&words("1abc"), &support).is_err());
+    }
+}
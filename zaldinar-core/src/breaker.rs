@@ -0,0 +1,87 @@
+use std::any::Any;
+use std::collections;
+use std::time;
+
+use config::CircuitBreakerConf;
+
+/// Disables plugins whose commands and listeners keep panicking, as configured in
+/// `circuit_breaker`.
+///
+/// Failures are recorded by the threads running plugin code, through
+/// `IrcInterface::run_plugin_code`. The dispatch thread checks before running a command, and the
+/// workers before running a listener.
+pub struct CircuitBreaker {
+    max_failures: usize,
+    window: time::Duration,
+    disable_for: time::Duration,
+    /// Times of each plugin's recent failures, oldest first.
+    failures: collections::HashMap<String, collections::VecDeque<time::Instant>>,
+    /// When each disabled plugin is enabled again.
+    disabled: collections::HashMap<String, time::Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConf) -> CircuitBreaker {
+        return CircuitBreaker {
+            max_failures: config.max_failures as usize,
+            window: time::Duration::from_secs(config.window_seconds),
+            disable_for: time::Duration::from_secs(config.disable_seconds),
+            failures: collections::HashMap::new(),
+            disabled: collections::HashMap::new(),
+        };
+    }
+
+    /// Records a failure of `plugin`. Returns true if this disabled the plugin.
+    pub fn record_failure(&mut self, plugin: &str) -> bool {
+        if self.max_failures == 0 {
+            return false;
+        }
+        let now = time::Instant::now();
+        let window = self.window;
+        let tripped = {
+            let failures = self.failures.entry(plugin.to_string())
+                .or_insert_with(collections::VecDeque::new);
+            while failures.front().map_or(false, |&time| now.duration_since(time) > window) {
+                failures.pop_front();
+            }
+            failures.push_back(now);
+            failures.len() >= self.max_failures
+        };
+        if tripped {
+            self.failures.remove(plugin);
+            self.disabled.insert(plugin.to_string(), now + self.disable_for);
+        }
+        return tripped;
+    }
+
+    /// Checks whether `plugin` is disabled, returning how much longer it is disabled for if so.
+    pub fn disabled_for(&mut self, plugin: &str) -> Option<time::Duration> {
+        let now = time::Instant::now();
+        let until = match self.disabled.get(plugin) {
+            Some(&until) => until,
+            None => return None,
+        };
+        if until <= now {
+            self.disabled.remove(plugin);
+            return None;
+        }
+        return Some(until - now);
+    }
+
+    /// Enables a disabled plugin again straight away. Returns whether it was disabled.
+    pub fn reset(&mut self, plugin: &str) -> bool {
+        self.failures.remove(plugin);
+        return self.disabled.remove(plugin).is_some();
+    }
+}
+
+/// Gets the message a panic was started with.
+pub fn panic_message(payload: &Box<Any + Send>) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message;
+    }
+    return match payload.downcast_ref::<String>() {
+        Some(message) => message,
+        None => "<unknown>",
+    };
+}
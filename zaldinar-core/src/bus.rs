@@ -0,0 +1,139 @@
+use std::collections;
+use std::sync;
+
+use serde;
+use serde_json;
+
+use errors::ThrowRuntimeError;
+
+/// The most events which can wait to be handed to subscribers. Events published while the queue
+/// is full are dropped.
+const MAX_QUEUED_EVENTS: usize = 1000;
+
+/// An event published on the bus.
+#[derive(Clone, Debug)]
+pub struct BusMessage {
+    pub topic: String,
+    pub payload: serde_json::Value,
+}
+
+impl BusMessage {
+    /// Reads the event's payload as a `T`.
+    pub fn payload<T>(&self) -> Result<T, ThrowRuntimeError> where T: serde::de::DeserializeOwned {
+        return Ok(throw!(serde_json::from_value(self.payload.clone())));
+    }
+}
+
+struct BusState {
+    queue: collections::VecDeque<BusMessage>,
+    stopped: bool,
+}
+
+/// Custom events which plugins publish for each other, by topic.
+///
+/// Plugins are compiled as separate crates, so they can't share types. Instead, payloads are
+/// serialized, and the publisher and each subscriber use their own types with the same form.
+/// Published events are queued here, and handed to the subscribers registered with
+/// `PluginRegister::register_subscriber` on the plugin worker threads.
+pub struct EventBus {
+    state: sync::Mutex<BusState>,
+    /// The most events which can be queued at once.
+    capacity: usize,
+    /// Notified when events are published or the bus is stopped, so that `wait_for_events`
+    /// notices.
+    changed: sync::Condvar,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        return EventBus::with_capacity(MAX_QUEUED_EVENTS);
+    }
+
+    fn with_capacity(capacity: usize) -> EventBus {
+        return EventBus {
+            state: sync::Mutex::new(BusState {
+                queue: collections::VecDeque::new(),
+                stopped: false,
+            }),
+            capacity: capacity,
+            changed: sync::Condvar::new(),
+        };
+    }
+
+    /// Publishes an event on `topic`. Fails, dropping the event, if too many events are already
+    /// waiting or the bus has been stopped.
+    pub fn publish<T>(&self, topic: &str, payload: &T) -> Result<(), ThrowRuntimeError>
+            where T: serde::Serialize {
+        let payload = throw!(serde_json::to_value(payload));
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            warn!("The bus is stopped. Dropping event on `{}`.", topic);
+            throw_new!(format!("The bus is stopped, so the event on `{}` was dropped.", topic));
+        }
+        if state.queue.len() >= self.capacity {
+            warn!("The bus already has {} events waiting. Dropping event on `{}`.",
+                state.queue.len(), topic);
+            throw_new!(format!("The bus is full, so the event on `{}` was dropped.", topic));
+        }
+        state.queue.push_back(BusMessage {
+            topic: topic.to_string(),
+            payload: payload,
+        });
+        self.changed.notify_all();
+        return Ok(());
+    }
+
+    /// Blocks until at least one event has been published, then returns every event published
+    /// since the last call, oldest first.
+    ///
+    /// Returns None once the bus has been stopped.
+    pub fn wait_for_events(&self) -> Option<Vec<BusMessage>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            if !state.queue.is_empty() {
+                return Some(state.queue.drain(..).collect());
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Stops the bus, so that `wait_for_events` returns None. Events still queued are dropped,
+    /// and so are any published afterwards.
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        for message in state.queue.drain(..) {
+            warn!("The bus was stopped. Dropping event on `{}`.", message.topic);
+        }
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventBus;
+
+    #[test]
+    fn events_are_dropped_when_the_queue_is_full() {
+        let bus = EventBus::with_capacity(2);
+        assert!(bus.publish("a", &1).is_ok());
+        assert!(bus.publish("b", &2).is_ok());
+        assert!(bus.publish("c", &3).is_err());
+        let topics: Vec<String> = bus.wait_for_events().unwrap().into_iter()
+            .map(|m| m.topic).collect();
+        assert_eq!(topics, vec!["a".to_string(), "b".to_string()]);
+        assert!(bus.publish("c", &3).is_ok());
+    }
+
+    #[test]
+    fn stopping_drops_queued_and_later_events() {
+        let bus = EventBus::with_capacity(2);
+        assert!(bus.publish("a", &1).is_ok());
+        bus.stop();
+        assert!(bus.wait_for_events().is_none());
+        assert!(bus.publish("b", &2).is_err());
+    }
+}
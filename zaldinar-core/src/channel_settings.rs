@@ -0,0 +1,217 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::path;
+
+use commands::{self, CommandDescriptor};
+use config;
+use data;
+use errors::ThrowInitError;
+use masks::CaseMapping;
+
+/// Whether a toggle applies to a whole plugin or a single command.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToggleKind {
+    Plugin,
+    Command,
+}
+
+impl fmt::Display for ToggleKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(match self {
+            &ToggleKind::Plugin => "plugin",
+            &ToggleKind::Command => "command",
+        })
+    }
+}
+
+/// A plugin or command enabled or disabled in a channel at runtime.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Toggle {
+    pub channel: String,
+    pub kind: ToggleKind,
+    /// Plugin name, or command path.
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// `channel_settings` from the configuration, with names normalized.
+struct ChannelRules {
+    allow_plugins: Vec<String>,
+    deny_plugins: Vec<String>,
+    allow_commands: Vec<String>,
+    deny_commands: Vec<String>,
+}
+
+impl ChannelRules {
+    fn from_config(config: &config::ChannelConf) -> ChannelRules {
+        let lower = |names: &Vec<String>| {
+            names.iter().map(|n| n.to_ascii_lowercase()).collect::<Vec<String>>()
+        };
+        let paths = |names: &Vec<String>| {
+            names.iter().map(|n| commands::command_path(n)).collect::<Vec<String>>()
+        };
+        return ChannelRules {
+            allow_plugins: lower(&config.plugins.allow),
+            deny_plugins: lower(&config.plugins.deny),
+            allow_commands: paths(&config.commands.allow),
+            deny_commands: paths(&config.commands.deny),
+        };
+    }
+}
+
+/// Which plugins and commands are enabled in each channel, from `channel_settings` in the
+/// configuration and toggles made at runtime, stored in `channel_settings.json` in the data
+/// directory.
+///
+/// Runtime toggles take priority over the configuration, and rules for a command take priority
+/// over rules for its group, which take priority over rules for its plugin. If a channel has any
+/// `allow` list, only what is allowed is enabled.
+///
+/// Channel names are compared using the server's casemapping, which isn't known until after the
+/// settings are loaded, so it is given to each method.
+pub struct ChannelSettings {
+    path: path::PathBuf,
+    /// Rules by channel name, as written in the configuration.
+    rules: Vec<(String, ChannelRules)>,
+    toggles: Vec<Toggle>,
+}
+
+impl ChannelSettings {
+    pub fn load(config: &config::ClientConfiguration, path: &path::Path)
+            -> Result<ChannelSettings, ThrowInitError> {
+        let toggles: Vec<Toggle> = up!(data::load_json(path)).unwrap_or_else(Vec::new);
+        let rules = config.channel_settings.iter()
+            .map(|(channel, conf)| (channel.clone(), ChannelRules::from_config(conf)))
+            .collect();
+        return Ok(ChannelSettings {
+            path: path.to_path_buf(),
+            rules: rules,
+            toggles: toggles,
+        });
+    }
+
+    pub fn save(&self) -> Result<(), ThrowInitError> {
+        return data::save_json(&self.path, &self.toggles);
+    }
+
+    fn rules(&self, channel: &str, casemapping: CaseMapping) -> Option<&ChannelRules> {
+        self.rules.iter().find(|&&(ref name, _)| casemapping.equals(name, channel))
+            .map(|&(_, ref rules)| rules)
+    }
+
+    fn toggle(&self, channel: &str, kind: ToggleKind, name: &str, casemapping: CaseMapping)
+            -> Option<bool> {
+        self.toggles.iter()
+            .find(|t| t.kind == kind && casemapping.equals(&t.channel, channel)
+                && t.name.eq_ignore_ascii_case(name))
+            .map(|t| t.enabled)
+    }
+
+    /// Checks whether a command is enabled in `channel`.
+    ///
+    /// `chain` is the command's descriptor followed by those of the groups it is in, innermost
+    /// first, as given by `PluginRegister::command_chain`.
+    pub fn is_enabled(&self, channel: &str, chain: &[&CommandDescriptor],
+            casemapping: CaseMapping) -> bool {
+        let rules = self.rules(channel, casemapping);
+        for descriptor in chain {
+            let path = commands::command_path(&descriptor.name);
+            if let Some(enabled) = self.toggle(channel, ToggleKind::Command, &path, casemapping) {
+                return enabled;
+            }
+            if let Some(rules) = rules {
+                if rules.deny_commands.contains(&path) {
+                    return false;
+                } else if rules.allow_commands.contains(&path) {
+                    return true;
+                }
+            }
+        }
+
+        if let Some(plugin) = chain.first().and_then(|d| d.plugin.as_ref()) {
+            if let Some(enabled) = self.plugin_rule(channel, plugin, rules, casemapping) {
+                return enabled;
+            }
+        }
+
+        return match rules {
+            Some(rules) => rules.allow_plugins.is_empty() && rules.allow_commands.is_empty(),
+            None => true,
+        };
+    }
+
+    /// Checks whether the listeners of `plugin` run for messages in `channel`.
+    ///
+    /// Only rules for the plugin itself apply, so an `allow` list of commands doesn't stop
+    /// listeners from running.
+    pub fn plugin_enabled(&self, channel: &str, plugin: &str, casemapping: CaseMapping) -> bool {
+        let rules = self.rules(channel, casemapping);
+        if let Some(enabled) = self.plugin_rule(channel, plugin, rules, casemapping) {
+            return enabled;
+        }
+        return rules.map_or(true, |rules| rules.allow_plugins.is_empty());
+    }
+
+    /// Finds whether the toggles or rules for `channel` enable or disable `plugin`, if they say.
+    fn plugin_rule(&self, channel: &str, plugin: &str, rules: Option<&ChannelRules>,
+            casemapping: CaseMapping) -> Option<bool> {
+        let plugin = plugin.to_ascii_lowercase();
+        if let Some(enabled) = self.toggle(channel, ToggleKind::Plugin, &plugin, casemapping) {
+            return Some(enabled);
+        }
+        if let Some(rules) = rules {
+            if rules.deny_plugins.contains(&plugin) {
+                return Some(false);
+            } else if rules.allow_plugins.contains(&plugin) {
+                return Some(true);
+            }
+        }
+        return None;
+    }
+
+    /// Enables or disables a plugin or command in `channel`, replacing any earlier toggle of it.
+    pub fn set(&mut self, channel: &str, kind: ToggleKind, name: &str, enabled: bool,
+            casemapping: CaseMapping) {
+        let name = match kind {
+            ToggleKind::Plugin => name.to_ascii_lowercase(),
+            ToggleKind::Command => commands::command_path(name),
+        };
+        self.toggles.retain(|t| !(t.kind == kind && casemapping.equals(&t.channel, channel)
+            && t.name == name));
+        self.toggles.push(Toggle {
+            channel: channel.to_string(),
+            kind: kind,
+            name: name,
+            enabled: enabled,
+        });
+    }
+
+    /// Describes the configuration and runtime toggles for `channel`, one line each.
+    pub fn describe(&self, channel: &str, casemapping: CaseMapping) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(rules) = self.rules(channel, casemapping) {
+            let lists = [
+                ("Allowed plugins", &rules.allow_plugins),
+                ("Denied plugins", &rules.deny_plugins),
+                ("Allowed commands", &rules.allow_commands),
+                ("Denied commands", &rules.deny_commands),
+            ];
+            for &(label, list) in &lists {
+                if !list.is_empty() {
+                    lines.push(format!("{}: {}", label, list.join(", ")));
+                }
+            }
+        }
+        for &(label, enabled) in &[("Enabled", true), ("Disabled", false)] {
+            let toggled = self.toggles.iter()
+                .filter(|t| t.enabled == enabled && casemapping.equals(&t.channel, channel))
+                .map(|t| format!("{} {}", t.kind, t.name))
+                .collect::<Vec<String>>();
+            if !toggled.is_empty() {
+                lines.push(format!("{} at runtime: {}", label, toggled.join(", ")));
+            }
+        }
+        return lines;
+    }
+}
@@ -3,37 +3,135 @@ use std::sync;
 use std::ops;
 use std::collections;
 use std::collections::hash_map;
+use std::time;
+
+use serde;
+use serde_json;
 
 use config;
+use errors::ThrowInitError;
 use irc;
 use events;
+use isupport;
+use modes;
+use masks;
+use commands;
+use permissions;
+use scheduler;
+use cron;
+use storage;
 
-pub type CommandListener = Box<Fn(&events::CommandEvent) + Sync + Send>;
+pub type CommandListener =
+    Box<Fn(&events::CommandEvent) -> commands::CommandResult + Sync + Send>;
 pub type CtcpListener = Box<Fn(&events::CtcpEvent) + Sync + Send>;
 pub type MessageListener = Box<Fn(&events::MessageEvent) + Sync + Send>;
+pub type JobListener = Box<Fn(&events::JobEvent) + Sync + Send>;
+pub type BusListener = Box<Fn(&events::BusEvent) + Sync + Send>;
+pub type LifecycleListener = Box<Fn(&events::LifecycleEvent) + Sync + Send>;
+/// Reads a plugin's configuration section, returning an error message if it is invalid.
+type ConfigLoader = Box<Fn(&serde_json::Value) -> Result<(), String> + Sync + Send>;
+
+/// A listener along with the plugin which registered it.
+pub struct PluginListener<T> {
+    /// The plugin which registered the listener, or None if it was registered outside of any
+    /// plugin.
+    pub plugin: Option<String>,
+    pub listener: T,
+}
+
+impl <T> PluginListener<T> {
+    /// Gets the name of the plugin which registered the listener, or `<unknown>`.
+    pub fn plugin_name(&self) -> &str {
+        self.plugin.as_ref().map_or("<unknown>", |s| &**s)
+    }
+}
+
+/// A command listener along with its metadata.
+pub struct RegisteredCommand {
+    pub descriptor: commands::CommandDescriptor,
+    /// None for a group which only holds subcommands.
+    pub listener: Option<CommandListener>,
+    /// Whether this is a group which was created only because a subcommand was registered, and
+    /// which may be replaced by an explicitly registered command.
+    pub implicit: bool,
+}
 
 pub struct PluginRegister {
-    pub commands: collections::HashMap<String, sync::Arc<CommandListener>>,
-    pub admin_commands: collections::HashMap<String, sync::Arc<CommandListener>>,
-    pub ctcp_listeners: collections::HashMap<String, Vec<sync::Arc<CtcpListener>>>,
-    pub raw_listeners: collections::HashMap<String, Vec<sync::Arc<MessageListener>>>,
-    pub catch_all: Vec<sync::Arc<MessageListener>>,
+    /// Commands, keyed by both their lowercase path (`name` or `group name`) and the path of
+    /// each alias.
+    pub commands: collections::HashMap<String, sync::Arc<RegisteredCommand>>,
+    pub ctcp_listeners:
+        collections::HashMap<String, Vec<sync::Arc<PluginListener<CtcpListener>>>>,
+    pub raw_listeners:
+        collections::HashMap<String, Vec<sync::Arc<PluginListener<MessageListener>>>>,
+    /// Listeners which keep the client state up to date, run before any others.
+    pub tracking_listeners:
+        collections::HashMap<String, Vec<sync::Arc<PluginListener<MessageListener>>>>,
+    pub catch_all: Vec<sync::Arc<PluginListener<MessageListener>>>,
+    /// Handlers for scheduled jobs, by name.
+    pub job_handlers: collections::HashMap<String, sync::Arc<PluginListener<JobListener>>>,
+    /// Subscribers to events on the bus, by topic.
+    pub subscribers:
+        collections::HashMap<String, Vec<sync::Arc<PluginListener<BusListener>>>>,
+    /// Jobs to schedule when the bot starts, by handler name.
+    pub startup_jobs: Vec<(String, scheduler::Schedule)>,
+    /// Hooks to run at each point in the bot's life.
+    pub lifecycle_hooks:
+        collections::HashMap<events::Lifecycle, Vec<sync::Arc<PluginListener<LifecycleListener>>>>,
+    /// Names of every plugin which has begun registering.
+    pub plugin_names: collections::BTreeSet<String>,
+    /// Readers for plugins' configuration sections, by plugin name.
+    config_loaders: Vec<(String, ConfigLoader)>,
+    /// Mistakes made by plugins while registering, which stop the bot from starting.
+    registration_errors: Vec<String>,
+    /// The storage behind every plugin's `PluginStorage`, filled in when the bot starts.
+    storage: sync::Arc<sync::RwLock<Option<sync::Arc<storage::Storage>>>>,
+    /// Plugin whose `register` function is currently running.
+    current_plugin: Option<String>,
 }
 
 impl PluginRegister {
     pub fn new() -> PluginRegister {
         return PluginRegister {
             commands: collections::HashMap::new(),
-            admin_commands: collections::HashMap::new(),
             raw_listeners: collections::HashMap::new(),
+            tracking_listeners: collections::HashMap::new(),
             ctcp_listeners: collections::HashMap::new(),
             catch_all: Vec::new(),
+            job_handlers: collections::HashMap::new(),
+            subscribers: collections::HashMap::new(),
+            startup_jobs: Vec::new(),
+            lifecycle_hooks: collections::HashMap::new(),
+            plugin_names: collections::BTreeSet::new(),
+            config_loaders: Vec::new(),
+            registration_errors: Vec::new(),
+            storage: sync::Arc::new(sync::RwLock::new(None)),
+            current_plugin: None,
         }
     }
 
+    /// Marks everything registered from now on as belonging to the plugin `name`.
+    pub fn begin_plugin(&mut self, name: &str) {
+        self.current_plugin = Some(name.to_string());
+        self.plugin_names.insert(name.to_string());
+    }
+
+    /// Checks whether a plugin named `name`, compared case insensitively, has registered.
+    pub fn has_plugin(&self, name: &str) -> bool {
+        self.plugin_names.iter().any(|p| p.eq_ignore_ascii_case(name))
+    }
+
+    /// Wraps a listener along with the plugin currently registering.
+    fn owned<T>(&self, listener: T) -> sync::Arc<PluginListener<T>> {
+        return sync::Arc::new(PluginListener {
+            plugin: self.current_plugin.clone(),
+            listener: listener,
+        });
+    }
+
     pub fn register_irc<T>(&mut self, irc_command: &str, f: T)
             where T: Fn(&events::MessageEvent) + Send + Sync + 'static {
-        let boxed = sync::Arc::new(Box::new(f) as MessageListener);
+        let boxed = self.owned(Box::new(f) as MessageListener);
         let command_string = irc_command.to_string().to_ascii_lowercase();
 
         match self.raw_listeners.entry(command_string) {
@@ -42,9 +140,26 @@ impl PluginRegister {
         }
     }
 
+    /// Registers a listener which keeps the client state up to date. Unlike those registered with
+    /// `register_irc`, these run on the dispatch thread, before the message is given to any other
+    /// listener, so that other listeners always see state which includes the message.
+    ///
+    /// They hold up every other message while they run, so they must be quick, and must never
+    /// wait on the server, as with `IrcInterface::whois`.
+    pub fn register_tracking<T>(&mut self, irc_command: &str, f: T)
+            where T: Fn(&events::MessageEvent) + Send + Sync + 'static {
+        let boxed = self.owned(Box::new(f) as MessageListener);
+        let command_string = irc_command.to_string().to_ascii_lowercase();
+
+        match self.tracking_listeners.entry(command_string) {
+            hash_map::Entry::Occupied(mut e) => e.get_mut().push(boxed),
+            hash_map::Entry::Vacant(e) => drop(e.insert(vec!(boxed))),
+        }
+    }
+
     pub fn register_ctcp<T>(&mut self, ctcp_command: &str, f: T)
             where T: Fn(&events::CtcpEvent) + Send + Sync + 'static {
-        let boxed = sync::Arc::new(Box::new(f) as CtcpListener);
+        let boxed = self.owned(Box::new(f) as CtcpListener);
         let command_string = ctcp_command.to_string().to_ascii_lowercase();
 
         match self.ctcp_listeners.entry(command_string) {
@@ -56,38 +171,336 @@ impl PluginRegister {
 
     pub fn register_catch_all<T>(&mut self, f: T)
             where T: Fn(&events::MessageEvent) + Send + Sync + 'static {
-        self.catch_all.push(sync::Arc::new(Box::new(f) as MessageListener));
+        let boxed = self.owned(Box::new(f) as MessageListener);
+        self.catch_all.push(boxed);
     }
 
-    pub fn register_command<T>(&mut self, command: &str, f: T)
-            where T: Fn(&events::CommandEvent) + Send + Sync + 'static {
-        let boxed = sync::Arc::new(Box::new(f) as CommandListener);
-        let command_lower = command.to_string().to_ascii_lowercase();
+    /// Reads the current plugin's section of `plugins` in the configuration into a `T` when the
+    /// bot starts. A missing section is read as `{}`, so that fields with defaults can be left
+    /// out.
+    pub fn config<T>(&mut self) -> config::PluginConfig<T>
+            where T: serde::de::DeserializeOwned + Send + Sync + 'static {
+        return self.validated_config(|_: &T| Ok(()));
+    }
 
-        match self.commands.entry(command_lower) {
-            hash_map::Entry::Occupied(mut e) => {
-                warn!("Replacing already registered command {} with newly registered command.",
-                    command.to_ascii_lowercase());
-                e.insert(boxed);
-            },
-            hash_map::Entry::Vacant(e) => drop(e.insert(boxed)),
+    /// Gets a handle to the current plugin's persistent key-value store. The store is named
+    /// after the plugin, so that plugins don't overwrite each other's data. Data meant to be
+    /// shared between plugins goes in `IrcInterface::shared_storage` instead.
+    pub fn storage(&mut self) -> storage::PluginStorage {
+        let plugin = self.current_plugin.clone().unwrap_or_else(|| "<unknown>".to_string());
+        return storage::PluginStorage::new(&plugin, self.storage.clone());
+    }
+
+    /// Gives every plugin's `PluginStorage` the stores it reads from. Called when the bot
+    /// starts.
+    pub fn set_storage(&self, storage: sync::Arc<storage::Storage>) {
+        *self.storage.write().unwrap() = Some(storage);
+    }
+
+    /// Like `config`, but also checks the settings with `validate`, which returns a message
+    /// saying what is wrong if they are invalid. The bot refuses to start with invalid settings.
+    pub fn validated_config<T, F>(&mut self, validate: F) -> config::PluginConfig<T>
+            where T: serde::de::DeserializeOwned + Send + Sync + 'static,
+            F: Fn(&T) -> Result<(), String> + Send + Sync + 'static {
+        let handle = config::PluginConfig::new();
+        let plugin = self.current_plugin.clone().unwrap_or_else(|| "<unknown>".to_string());
+        let target = handle.clone();
+        self.config_loaders.push((plugin, Box::new(move |section: &serde_json::Value| {
+            let value = try!(serde_json::from_value(section.clone()).map_err(|e| e.to_string()));
+            try!(validate(&value));
+            target.set(value);
+            Ok(())
+        })));
+        return handle;
+    }
+
+    /// Fails naming the plugin if any plugin registered something invalid, such as a bad cron
+    /// expression.
+    pub fn check_registrations(&self) -> Result<(), ThrowInitError> {
+        if !self.registration_errors.is_empty() {
+            throw_new!(self.registration_errors.join(" "));
         }
+        return Ok(());
     }
 
-    pub fn register_admin_command<T>(&mut self, command: &str, f: T)
-            where T: Fn(&events::CommandEvent) + Send + Sync + 'static {
-        let boxed = sync::Arc::new(Box::new(f) as CommandListener);
-        let command_lower = command.to_string().to_ascii_lowercase();
+    /// Reads every plugin's configuration section. Fails naming the plugin if one is invalid.
+    pub fn load_plugin_configs(&self, config: &config::ClientConfiguration)
+            -> Result<(), ThrowInitError> {
+        let empty = serde_json::Value::Object(serde_json::Map::new());
+        for &(ref plugin, ref loader) in &self.config_loaders {
+            let section = config.plugins.get(plugin).unwrap_or(&empty);
+            if let Err(e) = loader(section) {
+                throw_new!(format!("Invalid configuration for plugin `{}`: {}", plugin, e));
+            }
+        }
+        for name in config.plugins.keys() {
+            if !self.config_loaders.iter().any(|&(ref plugin, _)| plugin == name) {
+                warn!("The configuration section for plugin `{}` isn't used by any plugin.",
+                    name);
+            }
+        }
+        return Ok(());
+    }
+
+    /// Registers a hook which runs when the bot starts, after the configuration is loaded and
+    /// before connecting. It runs again each time the bot restarts.
+    ///
+    /// Load hooks finish before any of the plugin's listeners, jobs or subscribers can run.
+    pub fn register_on_load<T>(&mut self, f: T)
+            where T: Fn(&events::LifecycleEvent) + Send + Sync + 'static {
+        self.register_lifecycle(events::Lifecycle::Load, f);
+    }
 
-        match self.admin_commands.entry(command_lower) {
-            hash_map::Entry::Occupied(mut e) => {
-                warn!("Replacing already registered command {} with newly registered command.",
-                    command.to_ascii_lowercase());
-                e.insert(boxed);
+    /// Registers a hook which runs once the bot is registered with the server and has sent JOINs
+    /// for the configured channels.
+    pub fn register_on_connect<T>(&mut self, f: T)
+            where T: Fn(&events::LifecycleEvent) + Send + Sync + 'static {
+        self.register_lifecycle(events::Lifecycle::Connect, f);
+    }
+
+    /// Registers a hook which runs when the connection to the server closes.
+    pub fn register_on_disconnect<T>(&mut self, f: T)
+            where T: Fn(&events::LifecycleEvent) + Send + Sync + 'static {
+        self.register_lifecycle(events::Lifecycle::Disconnect, f);
+    }
+
+    /// Registers a hook which runs just before the bot sends QUIT, so that plugins can save
+    /// anything they have held in memory.
+    pub fn register_on_shutdown<T>(&mut self, f: T)
+            where T: Fn(&events::LifecycleEvent) + Send + Sync + 'static {
+        self.register_lifecycle(events::Lifecycle::Shutdown, f);
+    }
+
+    fn register_lifecycle<T>(&mut self, stage: events::Lifecycle, f: T)
+            where T: Fn(&events::LifecycleEvent) + Send + Sync + 'static {
+        let boxed = self.owned(Box::new(f) as LifecycleListener);
+        match self.lifecycle_hooks.entry(stage) {
+            hash_map::Entry::Occupied(mut e) => e.get_mut().push(boxed),
+            hash_map::Entry::Vacant(e) => drop(e.insert(vec!(boxed))),
+        }
+    }
+
+    /// Registers a handler for jobs scheduled with `IrcInterface::scheduler`. Handler names are
+    /// saved with persistent jobs, so they should stay the same between versions.
+    pub fn register_job<T>(&mut self, name: &str, f: T)
+            where T: Fn(&events::JobEvent) + Send + Sync + 'static {
+        let boxed = self.owned(Box::new(f) as JobListener);
+        match self.job_handlers.entry(name.to_string()) {
+            hash_map::Entry::Occupied(e) => {
+                warn!("Job handler `{}` from plugin {} conflicts with an existing handler. \
+                    Keeping the existing handler.", e.key(),
+                    self.current_plugin.as_ref().map_or("*unknown*", |s| &**s));
             },
             hash_map::Entry::Vacant(e) => drop(e.insert(boxed)),
         }
     }
+
+    /// Registers a job handler which runs every `interval`, starting one interval after the bot
+    /// starts.
+    pub fn register_interval<T>(&mut self, name: &str, interval: time::Duration, f: T)
+            where T: Fn(&events::JobEvent) + Send + Sync + 'static {
+        self.register_job(name, f);
+        self.startup_jobs.push((name.to_string(), scheduler::Schedule::Every(interval.as_secs())));
+    }
+
+    /// Registers a job handler which runs whenever the cron expression `expression` matches.
+    /// See `CronSchedule` for the syntax. An invalid expression stops the bot from starting.
+    pub fn register_cron<T>(&mut self, name: &str, expression: &str, f: T)
+            where T: Fn(&events::JobEvent) + Send + Sync + 'static {
+        if let Err(e) = cron::CronSchedule::parse(expression) {
+            let error = format!("Invalid cron expression for job handler `{}` from plugin {}: {}",
+                name, self.current_plugin.as_ref().map_or("*unknown*", |s| &**s), e);
+            self.registration_errors.push(error);
+            return;
+        }
+        self.register_job(name, f);
+        self.startup_jobs.push((name.to_string(),
+            scheduler::Schedule::Cron(expression.to_string())));
+    }
+
+    /// Subscribes to events published on `topic` with `IrcInterface::publish`. Each event's
+    /// payload is read as a `T`, and events which can't be are logged and skipped. Panics in the
+    /// subscriber count against its plugin, as they do for other listeners.
+    pub fn register_subscriber<T, F>(&mut self, topic: &str, f: F)
+            where T: serde::de::DeserializeOwned,
+            F: Fn(&events::BusEvent, T) + Send + Sync + 'static {
+        let plugin = self.current_plugin.clone().unwrap_or_else(|| "<unknown>".to_string());
+        let listener = move |event: &events::BusEvent| {
+            match event.payload() {
+                Ok(payload) => f(event, payload),
+                Err(e) => warn!("Plugin {} can't read an event on `{}`: {}", plugin, event.topic,
+                    e),
+            }
+        };
+        let boxed = self.owned(Box::new(listener) as BusListener);
+
+        match self.subscribers.entry(topic.to_string()) {
+            hash_map::Entry::Occupied(mut e) => e.get_mut().push(boxed),
+            hash_map::Entry::Vacant(e) => drop(e.insert(vec!(boxed))),
+        }
+    }
+
+    pub fn register_command<T, R>(&mut self, command: &str, f: T)
+            where T: Fn(&events::CommandEvent) -> R + Send + Sync + 'static,
+            R: commands::IntoCommandResult {
+        self.register_described_command(commands::CommandDescriptor::new(command), f);
+    }
+
+    /// Registers a command which requires the `admin` permission by default.
+    pub fn register_admin_command<T, R>(&mut self, command: &str, f: T)
+            where T: Fn(&events::CommandEvent) -> R + Send + Sync + 'static,
+            R: commands::IntoCommandResult {
+        let descriptor = commands::CommandDescriptor::new(command)
+            .permission(permissions::ADMIN_PERMISSION);
+        self.register_described_command(descriptor, f);
+    }
+
+    /// Registers a command along with metadata for `help`. If the descriptor doesn't name an
+    /// owning plugin, the plugin currently registering is used.
+    ///
+    /// A name containing spaces, such as `quote add`, registers a subcommand. Groups for each
+    /// parent are created as needed.
+    ///
+    /// The listener may return nothing, or a `Result` whose error is reported to the user.
+    ///
+    /// The first command registered with a name or alias keeps it: later commands conflicting
+    /// with it aren't reachable by that name, and a warning naming both plugins is logged. The
+    /// only exception is a group created implicitly for subcommands, which an explicitly
+    /// registered command replaces, also with a warning.
+    pub fn register_described_command<T, R>(&mut self, descriptor: commands::CommandDescriptor,
+            f: T) where T: Fn(&events::CommandEvent) -> R + Send + Sync + 'static,
+            R: commands::IntoCommandResult {
+        self.insert_command(descriptor, Some(Box::new(move |event: &events::CommandEvent| {
+            f(event).into_command_result()
+        })));
+    }
+
+    /// Registers a group of subcommands, so that it can have its own summary, category and
+    /// permission. The group's permission also applies to subcommands which don't give one.
+    pub fn register_group(&mut self, descriptor: commands::CommandDescriptor) {
+        self.insert_command(descriptor, None);
+    }
+
+    fn insert_command(&mut self, mut descriptor: commands::CommandDescriptor,
+            listener: Option<CommandListener>) {
+        if descriptor.plugin.is_none() {
+            descriptor.plugin = self.current_plugin.clone();
+        }
+        let path = commands::command_path(&descriptor.name);
+
+        // Create any missing parent groups.
+        let words = path.split(' ').collect::<Vec<&str>>();
+        for depth in 1..words.len() {
+            let parent = words[..depth].join(" ");
+            if !self.commands.contains_key(&parent) {
+                let mut group = commands::CommandDescriptor::new(&parent);
+                group.plugin = descriptor.plugin.clone();
+                self.commands.insert(parent, sync::Arc::new(RegisteredCommand {
+                    descriptor: group,
+                    listener: None,
+                    implicit: true,
+                }));
+            }
+        }
+
+        let parent_prefix = match path.rfind(' ') {
+            Some(index) => &path[..index + 1],
+            None => "",
+        };
+        let mut paths = vec![path.clone()];
+        paths.extend(descriptor.aliases.iter()
+            .map(|alias| format!("{}{}", parent_prefix, commands::command_path(alias))));
+        let registered = sync::Arc::new(RegisteredCommand {
+            descriptor: descriptor,
+            listener: listener,
+            implicit: false,
+        });
+
+        for path in paths {
+            match self.commands.entry(path) {
+                hash_map::Entry::Occupied(mut e) => {
+                    if e.get().implicit {
+                        warn!("Command `{}` from plugin {} replaces the group created for \
+                            subcommands from plugin {}.", e.key(),
+                            registered.descriptor.plugin_name(), e.get().descriptor.plugin_name());
+                        e.insert(registered.clone());
+                    } else {
+                        warn!("Command `{}` from plugin {} conflicts with the command registered \
+                            by plugin {}. Keeping the command from {}.", e.key(),
+                            registered.descriptor.plugin_name(),
+                            e.get().descriptor.plugin_name(), e.get().descriptor.plugin_name());
+                    }
+                },
+                hash_map::Entry::Vacant(e) => drop(e.insert(registered.clone())),
+            }
+        }
+    }
+
+    /// Finds the most specific command matching `command` followed by `args`, such as
+    /// `quote add` for `.quote add some text`.
+    ///
+    /// Returns the command and how many of `args` are part of its path.
+    pub fn find_command<S: AsRef<str>>(&self, command: &str, args: &[S])
+            -> Option<(&sync::Arc<RegisteredCommand>, usize)> {
+        let mut path = command.to_ascii_lowercase();
+        let mut found = match self.commands.get(&path) {
+            Some(v) => (v, 0),
+            None => return None,
+        };
+        for (index, arg) in args.iter().enumerate() {
+            path.push(' ');
+            path.push_str(&arg.as_ref().to_ascii_lowercase());
+            match self.commands.get(&path) {
+                Some(v) => found = (v, index + 1),
+                None => break,
+            }
+        }
+        return Some(found);
+    }
+
+    /// Gets every registered command and group once, skipping aliases, sorted by name.
+    pub fn unique_commands(&self) -> Vec<&RegisteredCommand> {
+        let mut commands = self.commands.iter()
+            .filter(|&(key, command)| commands::command_path(&command.descriptor.name) == **key)
+            .map(|(_, command)| &**command)
+            .collect::<Vec<&RegisteredCommand>>();
+        commands.sort_by(|a, b| a.descriptor.name.cmp(&b.descriptor.name));
+        return commands;
+    }
+
+    /// Gets every command which requires the admin permission itself, as those registered with
+    /// `register_admin_command` do, skipping aliases.
+    #[deprecated(note = "permissions can now come from groups and the configuration; use \
+        `unique_commands` and `IrcInterface::command_permission` instead")]
+    pub fn admin_commands(&self) -> Vec<&RegisteredCommand> {
+        return self.unique_commands().into_iter()
+            .filter(|c| c.descriptor.permission.as_ref()
+                .map_or(false, |p| p == permissions::ADMIN_PERMISSION))
+            .collect();
+    }
+
+    /// Gets the direct subcommands of the command or group at `path`, skipping aliases.
+    pub fn subcommands(&self, path: &str) -> Vec<&RegisteredCommand> {
+        let path = commands::command_path(path);
+        return self.unique_commands().into_iter()
+            .filter(|c| commands::parent_path(&commands::command_path(&c.descriptor.name))
+                == Some(&*path))
+            .collect();
+    }
+
+    /// Gets a command's descriptor followed by those of each group it is in, innermost first.
+    pub fn command_chain<'a>(&'a self, descriptor: &'a commands::CommandDescriptor)
+            -> Vec<&'a commands::CommandDescriptor> {
+        let mut chain = vec![descriptor];
+        let mut path = commands::command_path(&descriptor.name);
+        while let Some(parent) = commands::parent_path(&path).map(|p| p.to_string()) {
+            if let Some(group) = self.commands.get(&parent) {
+                chain.push(&group.descriptor);
+            }
+            path = parent;
+        }
+        return chain;
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -108,9 +521,113 @@ pub enum ExecutingState {
     RestartExec,
 }
 
+/// Tracked state of a single channel which the bot is in.
+pub struct ChannelState {
+    pub name: String,
+    /// Users in the channel, keyed by nick lowercased with `casemapping`, with the prefix modes
+    /// (`o`, `v`, ...) each user currently holds.
+    pub users: collections::HashMap<String, Vec<char>>,
+    /// Non-list channel modes which are set, along with their parameter if they have one.
+    pub modes: collections::HashMap<char, Option<String>>,
+    /// Entries for list modes (bans, exceptions, ...) which have been seen since joining.
+    pub lists: collections::HashMap<char, Vec<String>>,
+    /// The server's casemapping when the channel was joined.
+    casemapping: masks::CaseMapping,
+}
+
+impl ChannelState {
+    pub fn new(name: &str, casemapping: masks::CaseMapping) -> ChannelState {
+        return ChannelState {
+            name: name.to_string(),
+            casemapping: casemapping,
+            users: collections::HashMap::new(),
+            modes: collections::HashMap::new(),
+            lists: collections::HashMap::new(),
+        };
+    }
+
+    pub fn add_user(&mut self, nick: &str, prefix_modes: Vec<char>) {
+        self.users.insert(self.casemapping.to_lower(nick), prefix_modes);
+    }
+
+    pub fn remove_user(&mut self, nick: &str) {
+        self.users.remove(&self.casemapping.to_lower(nick));
+    }
+
+    pub fn rename_user(&mut self, old_nick: &str, new_nick: &str) {
+        if let Some(prefix_modes) = self.users.remove(&self.casemapping.to_lower(old_nick)) {
+            self.users.insert(self.casemapping.to_lower(new_nick), prefix_modes);
+        }
+    }
+
+    /// Gets the prefix modes `nick` holds, or None if they aren't in the channel.
+    pub fn user_modes(&self, nick: &str) -> Option<&[char]> {
+        self.users.get(&self.casemapping.to_lower(nick)).map(|modes| &**modes)
+    }
+
+    /// Checks whether `nick` holds the given prefix mode, such as `o` or `v`.
+    pub fn has_prefix_mode(&self, nick: &str, mode: char) -> bool {
+        match self.user_modes(nick) {
+            Some(prefix_modes) => prefix_modes.contains(&mode),
+            None => false,
+        }
+    }
+
+    pub fn apply_mode_change(&mut self, change: &modes::ModeChange) {
+        match change.kind {
+            modes::ModeKind::Prefix => {
+                if let Some(ref nick) = change.argument {
+                    let nick = self.casemapping.to_lower(nick);
+                    if let Some(prefix_modes) = self.users.get_mut(&nick) {
+                        prefix_modes.retain(|m| *m != change.mode);
+                        if change.adding {
+                            prefix_modes.push(change.mode);
+                        }
+                    }
+                }
+            },
+            modes::ModeKind::List => {
+                if let Some(ref entry) = change.argument {
+                    let list = self.lists.entry(change.mode).or_insert_with(Vec::new);
+                    list.retain(|e| e != entry);
+                    if change.adding {
+                        list.push(entry.clone());
+                    }
+                }
+            },
+            _ => {
+                if change.adding {
+                    self.modes.insert(change.mode, change.argument.clone());
+                } else {
+                    self.modes.remove(&change.mode);
+                }
+            },
+        }
+    }
+}
+
+/// What is known about a user's services account.
+pub struct AccountInfo {
+    /// The account name, or None if the user is known not to be logged in.
+    pub account: Option<String>,
+    pub updated: time::Instant,
+}
+
 pub struct ClientState {
     pub nick: String,
     pub channels: Vec<String>,
+    /// Features the server has advertised in RPL_ISUPPORT.
+    pub support: isupport::ServerSupport,
+    /// Detailed state for each channel the bot is in, keyed by lowercase channel name.
+    pub channel_states: collections::HashMap<String, ChannelState>,
+    /// User modes currently set on the bot.
+    pub modes: Vec<char>,
+    /// Capabilities the server has advertised in `CAP LS`.
+    pub available_capabilities: Vec<String>,
+    /// Capabilities which the server has acknowledged enabling.
+    pub capabilities: Vec<String>,
+    /// Known services accounts, keyed by lowercase nick.
+    pub accounts: collections::HashMap<String, AccountInfo>,
     /// This is a marker for what the bot should do after the main program exits.
     /// - The main function will just be re-run if this is still "Running".
     /// - The bot will exit if this is "Done".
@@ -124,9 +641,88 @@ impl ClientState {
         return ClientState {
             nick: nick,
             channels: Vec::new(),
+            support: isupport::ServerSupport::default(),
+            channel_states: collections::HashMap::new(),
+            modes: Vec::new(),
+            available_capabilities: Vec::new(),
+            capabilities: Vec::new(),
+            accounts: collections::HashMap::new(),
             done_executing: ExecutingState::Running,
         };
     }
+
+    pub fn channel(&self, name: &str) -> Option<&ChannelState> {
+        self.channel_states.get(&self.support.casemapping.to_lower(name))
+    }
+
+    pub fn channel_mut(&mut self, name: &str) -> Option<&mut ChannelState> {
+        let name = self.support.casemapping.to_lower(name);
+        self.channel_states.get_mut(&name)
+    }
+
+    /// Starts tracking a channel the bot has joined.
+    pub fn add_channel(&mut self, name: &str) {
+        let state = ChannelState::new(name, self.support.casemapping);
+        self.channel_states.insert(self.support.casemapping.to_lower(name), state);
+    }
+
+    /// Stops tracking a channel the bot has left.
+    pub fn remove_channel(&mut self, name: &str) {
+        let name = self.support.casemapping.to_lower(name);
+        self.channel_states.remove(&name);
+    }
+
+    /// Checks whether `nick` is the bot's own nick.
+    pub fn is_own_nick(&self, nick: &str) -> bool {
+        self.support.casemapping.equals(nick, &self.nick)
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Records the account `nick` is logged in to. `*` and None both mean not logged in.
+    pub fn set_account(&mut self, nick: &str, account: Option<&str>) {
+        let account = match account {
+            Some("*") | None => None,
+            Some(v) => Some(v.to_string()),
+        };
+        self.accounts.insert(self.support.casemapping.to_lower(nick), AccountInfo {
+            account: account,
+            updated: time::Instant::now(),
+        });
+    }
+
+    pub fn rename_account(&mut self, old_nick: &str, new_nick: &str) {
+        let casemapping = self.support.casemapping;
+        if let Some(info) = self.accounts.remove(&casemapping.to_lower(old_nick)) {
+            self.accounts.insert(casemapping.to_lower(new_nick), info);
+        }
+    }
+
+    pub fn forget_account(&mut self, nick: &str) {
+        let nick = self.support.casemapping.to_lower(nick);
+        self.accounts.remove(&nick);
+    }
+
+    /// Gets the account `nick` is logged in to, if it is known and still trustworthy.
+    ///
+    /// Returns `Some(None)` if the user is known not to be logged in, and None if it isn't known.
+    /// Information older than `max_age` is only trusted if `account-notify` is enabled and the
+    /// user shares a channel with the bot, since the server then tells us about every change.
+    pub fn known_account(&self, nick: &str, max_age: time::Duration) -> Option<Option<&str>> {
+        let info = match self.accounts.get(&self.support.casemapping.to_lower(nick)) {
+            Some(v) => v,
+            None => return None,
+        };
+        let kept_updated = self.has_capability("account-notify")
+            && self.channel_states.values().any(|c| c.user_modes(nick).is_some());
+        if kept_updated || info.updated.elapsed() < max_age {
+            return Some(info.account.as_ref().map(|s| &**s));
+        } else {
+            return None;
+        }
+    }
 }
 
 struct ClientInner {
@@ -157,9 +753,16 @@ impl Client {
         return &self.0.config;
     }
 
+    /// Gets the state tracked from the server. The lock is shared by every thread, so it should
+    /// be held briefly, and never across a panic, which would poison it for everyone.
     pub fn state(&self) -> &sync::RwLock<ClientState> {
         return &self.0.state;
     }
+
+    /// Gets the casemapping the server uses for channel names and nicks.
+    pub fn casemapping(&self) -> masks::CaseMapping {
+        return self.0.state.read().unwrap().support.casemapping;
+    }
 }
 
 impl irc::HasNick for Client {
@@ -177,3 +780,22 @@ impl ops::Deref for Client {
         return self.config();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PluginRegister;
+
+    #[test]
+    fn invalid_cron_expressions_stop_startup() {
+        let mut register = PluginRegister::new();
+        register.begin_plugin("clock");
+        register.register_cron("chime", "0 * * * *", |_| ());
+        assert!(register.check_registrations().is_ok());
+        register.register_cron("broken", "not cron", |_| ());
+        assert!(!register.job_handlers.contains_key("broken"));
+        match register.check_registrations() {
+            Ok(()) => panic!("an invalid cron expression was accepted"),
+            Err(e) => assert!(e.to_string().contains("`broken` from plugin clock")),
+        }
+    }
+}
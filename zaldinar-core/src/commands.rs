@@ -0,0 +1,294 @@
+use std::ascii::AsciiExt;
+
+use std::fmt;
+use std::time;
+
+use args::ArgSpec;
+use events::MessageContext;
+
+/// Category used for commands which give neither a category nor an owning plugin.
+pub const DEFAULT_CATEGORY: &'static str = "general";
+
+/// An error from a command, which is reported to the user who used it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CommandError {
+    message: String,
+}
+
+impl CommandError {
+    pub fn new<T: Into<String>>(message: T) -> CommandError {
+        return CommandError {
+            message: message.into(),
+        };
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(&self.message)
+    }
+}
+
+pub type CommandResult = Result<(), CommandError>;
+
+/// Anything a command listener may return: nothing, or a `Result` whose error is reported to the
+/// user who used the command.
+pub trait IntoCommandResult {
+    fn into_command_result(self) -> CommandResult;
+}
+
+impl IntoCommandResult for () {
+    fn into_command_result(self) -> CommandResult {
+        Ok(())
+    }
+}
+
+impl <E: fmt::Display> IntoCommandResult for Result<(), E> {
+    fn into_command_result(self) -> CommandResult {
+        self.map_err(|e| CommandError::new(e.to_string()))
+    }
+}
+
+/// Where a command may be used.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Availability {
+    Anywhere,
+    /// Only in channels, including STATUSMSG messages to a channel.
+    ChannelOnly,
+    /// Only in private messages to the bot.
+    PrivateOnly,
+}
+
+impl Availability {
+    /// Checks whether a command may be used in a message sent in `context`.
+    pub fn allows(&self, context: MessageContext) -> bool {
+        match self {
+            &Availability::Anywhere => true,
+            &Availability::ChannelOnly => context.is_channel(),
+            &Availability::PrivateOnly => !context.is_channel(),
+        }
+    }
+}
+
+impl fmt::Display for Availability {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(match self {
+            &Availability::Anywhere => "anywhere",
+            &Availability::ChannelOnly => "in channels",
+            &Availability::PrivateOnly => "in private messages",
+        })
+    }
+}
+
+/// Metadata about a command, shown by `help` and used when dispatching it.
+///
+/// Built with chained calls, as in
+/// `CommandDescriptor::new("rand").summary("Picks a random number.").usage("<max>")`.
+#[derive(Clone, Debug)]
+pub struct CommandDescriptor {
+    /// The command's name. Subcommands are named by their full path, as in `quote add`.
+    pub name: String,
+    /// One line describing what the command does.
+    pub summary: Option<String>,
+    /// The arguments the command takes, without the command name, as in `<channel> [message]`.
+    pub usage: Option<String>,
+    /// Example arguments, without the command name.
+    pub examples: Vec<String>,
+    /// Other names the command can be used by. Aliases of subcommands are names within the same
+    /// group, so `msg` is an alias of `admin message` usable as `admin msg`.
+    pub aliases: Vec<String>,
+    /// Category to list the command under in `help`. Defaults to the owning plugin's name.
+    pub category: Option<String>,
+    /// Name of the plugin which registered the command.
+    pub plugin: Option<String>,
+    /// The permission needed to use the command, or None if anyone may use it.
+    pub permission: Option<String>,
+    /// The arguments the command takes. If given, arguments are parsed before the command runs,
+    /// and the user is told the usage instead if they don't match.
+    pub args: Option<ArgSpec>,
+    /// Where the command may be used. Subcommands may be further restricted than their group,
+    /// but not less.
+    pub availability: Availability,
+    /// Whether the command runs on the heavy worker threads, so that it can't hold up other
+    /// plugins.
+    pub heavy: bool,
+    /// How long the command is expected to take at most. A warning is logged when it takes
+    /// longer. Defaults to `time_budget_seconds` from `workers` in the configuration.
+    pub time_budget: Option<time::Duration>,
+}
+
+impl CommandDescriptor {
+    pub fn new(name: &str) -> CommandDescriptor {
+        return CommandDescriptor {
+            name: name.to_string(),
+            summary: None,
+            usage: None,
+            examples: Vec::new(),
+            aliases: Vec::new(),
+            category: None,
+            plugin: None,
+            permission: None,
+            args: None,
+            availability: Availability::Anywhere,
+            heavy: false,
+            time_budget: None,
+        };
+    }
+
+    pub fn summary(mut self, summary: &str) -> CommandDescriptor {
+        self.summary = Some(summary.to_string());
+        return self;
+    }
+
+    pub fn usage(mut self, usage: &str) -> CommandDescriptor {
+        self.usage = Some(usage.to_string());
+        return self;
+    }
+
+    pub fn example(mut self, example: &str) -> CommandDescriptor {
+        self.examples.push(example.to_string());
+        return self;
+    }
+
+    pub fn alias(mut self, alias: &str) -> CommandDescriptor {
+        self.aliases.push(alias.to_string());
+        return self;
+    }
+
+    pub fn category(mut self, category: &str) -> CommandDescriptor {
+        self.category = Some(category.to_string());
+        return self;
+    }
+
+    pub fn plugin(mut self, plugin: &str) -> CommandDescriptor {
+        self.plugin = Some(plugin.to_string());
+        return self;
+    }
+
+    pub fn permission(mut self, permission: &str) -> CommandDescriptor {
+        self.permission = Some(permission.to_string());
+        return self;
+    }
+
+    /// Sets the arguments the command takes. This also sets the usage, unless it was already
+    /// given.
+    pub fn args(mut self, args: ArgSpec) -> CommandDescriptor {
+        if self.usage.is_none() {
+            self.usage = Some(args.usage());
+        }
+        self.args = Some(args);
+        return self;
+    }
+
+    /// Only allows the command to be used in channels.
+    pub fn channel_only(mut self) -> CommandDescriptor {
+        self.availability = Availability::ChannelOnly;
+        return self;
+    }
+
+    /// Only allows the command to be used in private messages, such as for commands which take
+    /// passwords.
+    pub fn private_only(mut self) -> CommandDescriptor {
+        self.availability = Availability::PrivateOnly;
+        return self;
+    }
+
+    /// Runs the command on the heavy worker threads. This is for commands which may take a long
+    /// time, such as running user supplied programs.
+    pub fn heavy(mut self) -> CommandDescriptor {
+        self.heavy = true;
+        return self;
+    }
+
+    pub fn time_budget(mut self, budget: time::Duration) -> CommandDescriptor {
+        self.time_budget = Some(budget);
+        return self;
+    }
+
+    /// Gets the name of the plugin which registered the command, or `<unknown>`.
+    pub fn plugin_name(&self) -> &str {
+        self.plugin.as_ref().map_or("<unknown>", |s| &**s)
+    }
+
+    /// Gets the category the command is listed under: its category if given, otherwise the name
+    /// of its plugin.
+    pub fn category_name(&self) -> &str {
+        match (&self.category, &self.plugin) {
+            (&Some(ref category), _) => category,
+            (&None, &Some(ref plugin)) => plugin,
+            (&None, &None) => DEFAULT_CATEGORY,
+        }
+    }
+}
+
+/// Normalizes a command name or path to lowercase words separated by single spaces.
+pub fn command_path(name: &str) -> String {
+    name.split_whitespace().map(|word| word.to_ascii_lowercase()).collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Gets the path of the group containing the command at `path`, or None if it is top level.
+pub fn parent_path(path: &str) -> Option<&str> {
+    path.rfind(' ').map(|index| &path[..index])
+}
+
+/// Counts the single character insertions, deletions, substitutions and swaps of adjacent
+/// characters needed to turn `a` into `b`, ignoring ASCII case.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().map(|c| c.to_ascii_lowercase()).collect::<Vec<char>>();
+    let b = b.chars().map(|c| c.to_ascii_lowercase()).collect::<Vec<char>>();
+    // distances[i][j] is the distance between the first i characters of a and first j of b.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..a.len() + 1 {
+        distances[i][0] = i;
+    }
+    for j in 0..b.len() + 1 {
+        distances[0][j] = j;
+    }
+    for i in 1..a.len() + 1 {
+        for j in 1..b.len() + 1 {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    return distances[a.len()][b.len()];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::edit_distance;
+
+    #[test]
+    fn edit_distance_operations() {
+        assert_eq!(edit_distance("help", "help"), 0);
+        assert_eq!(edit_distance("hlp", "help"), 1);
+        assert_eq!(edit_distance("helpp", "help"), 1);
+        assert_eq!(edit_distance("hwlp", "help"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+    }
+
+    #[test]
+    fn edit_distance_swaps() {
+        assert_eq!(edit_distance("hepl", "help"), 1);
+        assert_eq!(edit_distance("ehlp", "help"), 1);
+        assert_eq!(edit_distance("ca", "abc"), 3);
+    }
+
+    #[test]
+    fn edit_distance_ignores_case() {
+        assert_eq!(edit_distance("HELP", "help"), 0);
+        assert_eq!(edit_distance("Hepl", "hELP"), 1);
+    }
+}
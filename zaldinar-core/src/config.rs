@@ -1,9 +1,12 @@
 use std::io::prelude::*;
 use std::fs;
+use std::collections;
+use std::sync;
 use serde_json;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use errors::ThrowInitError;
+use masks::CaseMapping;
 
 #[derive(Deserialize)]
 pub struct NickServConf {
@@ -14,6 +17,321 @@ pub struct NickServConf {
     pub enabled: bool,
 }
 
+/// One way of being granted a role. Every condition given must hold for the grant to apply.
+#[derive(Deserialize)]
+pub struct RoleMemberConf {
+    /// Mask which the user's `nick!user@host` must match. Masks are globs unless prefixed with
+    /// `re:`.
+    #[serde(default)]
+    pub mask: Option<String>,
+    /// Services account which the user must be logged in to.
+    #[serde(default)]
+    pub account: Option<String>,
+    /// Channel status mode (such as `o` or `v`) which the user must hold in the channel the
+    /// command is used in. Any higher status also matches.
+    #[serde(default)]
+    pub channel_status: Option<char>,
+    /// Channels which this grant is limited to. The grant applies in all channels if empty.
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleConf {
+    pub name: String,
+    /// Permissions granted by this role. `*` grants everything, and `name.*` grants every
+    /// permission starting with `name.`.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Other roles whose permissions this role also has.
+    #[serde(default)]
+    pub inherits: Vec<String>,
+    #[serde(default)]
+    pub members: Vec<RoleMemberConf>,
+}
+
+/// Settings for identifying users by their services account.
+#[derive(Deserialize)]
+pub struct AccountConf {
+    /// Whether to look up a user's account with WHOIS when they use a command requiring a
+    /// permission and their account isn't known.
+    #[serde(default = "default_true")]
+    pub whois_lookup: bool,
+    /// How long accounts learned from WHOIS are trusted for, in seconds. Accounts of users in a
+    /// channel with the bot are kept up to date by `account-notify` when the server supports it.
+    #[serde(default = "default_account_cache_seconds")]
+    pub cache_seconds: u64,
+    /// If true, role members identified only by `mask` are ignored, so that permissions can
+    /// only be gained through a services account or channel status.
+    #[serde(default)]
+    pub require_account: bool,
+}
+
+impl Default for AccountConf {
+    fn default() -> AccountConf {
+        return AccountConf {
+            whois_lookup: default_true(),
+            cache_seconds: default_account_cache_seconds(),
+            require_account: false,
+        };
+    }
+}
+
+/// Names which are allowed or denied. If `allow` isn't empty, anything not in it is denied.
+#[derive(Deserialize, Default)]
+pub struct AllowDenyConf {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// Where a reply to a command is sent.
+#[derive(Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyMode {
+    /// To the channel the command was used in.
+    Channel,
+    /// In a notice to the user who used the command.
+    Notice,
+    /// In a private message to the user who used the command.
+    Private,
+}
+
+fn default_reply_mode() -> ReplyMode {
+    ReplyMode::Channel
+}
+
+fn default_error_mode() -> ReplyMode {
+    ReplyMode::Notice
+}
+
+/// How replies to commands are sent. Replies to commands used in a private message are always
+/// sent in a private message.
+#[derive(Deserialize, Clone)]
+pub struct ReplyConf {
+    /// Where normal replies go.
+    #[serde(default = "default_reply_mode")]
+    pub mode: ReplyMode,
+    /// Where errors go.
+    #[serde(default = "default_error_mode")]
+    pub errors: ReplyMode,
+    /// If true, replies in a channel start with the nick of the user who used the command.
+    #[serde(default)]
+    pub prefix_nick: bool,
+    /// If true, replies are marked as replies to the command's message with `+draft/reply` when
+    /// the server supports message tags.
+    #[serde(default = "default_true")]
+    pub threaded: bool,
+}
+
+impl Default for ReplyConf {
+    fn default() -> ReplyConf {
+        return ReplyConf {
+            mode: default_reply_mode(),
+            errors: default_error_mode(),
+            prefix_nick: false,
+            threaded: default_true(),
+        };
+    }
+}
+
+/// Settings for a single channel.
+#[derive(Deserialize, Default)]
+pub struct ChannelConf {
+    /// Plugins which may be used in the channel, by name.
+    #[serde(default)]
+    pub plugins: AllowDenyConf,
+    /// Commands which may be used in the channel, by path. These take priority over `plugins`.
+    #[serde(default)]
+    pub commands: AllowDenyConf,
+    /// Prefixes commands can be used with in the channel, replacing the global ones. An empty
+    /// list means commands can only be used by addressing the bot.
+    pub command_prefixes: Option<Vec<String>>,
+    /// Whether commands can be used by addressing the bot, as in `zaldinar, help`. Defaults to
+    /// true.
+    pub nick_addressing: Option<bool>,
+    /// How replies to commands are sent in the channel, replacing the global `replies`.
+    pub replies: Option<ReplyConf>,
+    /// Whether to suggest similar commands when an unknown one is used, replacing the global
+    /// `suggest_commands`.
+    pub suggest_commands: Option<bool>,
+}
+
+/// Minimum times between uses of a command, in seconds. Zero means no limit.
+#[derive(Deserialize, Clone, Default)]
+pub struct CooldownConf {
+    /// Time between uses by the same user, who is identified by account if it is known, and
+    /// otherwise by `user@host`.
+    #[serde(default)]
+    pub user_seconds: u64,
+    /// Time between uses in the same channel.
+    #[serde(default)]
+    pub channel_seconds: u64,
+    /// Time between uses by anyone, anywhere.
+    #[serde(default)]
+    pub global_seconds: u64,
+}
+
+/// Settings for limiting how often commands can be used.
+#[derive(Deserialize)]
+pub struct RateLimitConf {
+    /// Cooldowns shared by all commands, so that using any command starts them.
+    #[serde(default)]
+    pub all_commands: CooldownConf,
+    /// Cooldowns for individual commands, by command path. A group's cooldowns are shared by all
+    /// of its subcommands which don't have their own.
+    #[serde(default)]
+    pub commands: collections::HashMap<String, CooldownConf>,
+    /// Users with this permission are never rate limited.
+    #[serde(default = "default_exempt_permission")]
+    pub exempt_permission: String,
+}
+
+impl Default for RateLimitConf {
+    fn default() -> RateLimitConf {
+        return RateLimitConf {
+            all_commands: CooldownConf::default(),
+            commands: collections::HashMap::new(),
+            exempt_permission: default_exempt_permission(),
+        };
+    }
+}
+
+/// Settings for the threads which run plugins.
+#[derive(Deserialize)]
+pub struct WorkerConf {
+    /// How many threads run listeners and ordinary commands.
+    #[serde(default = "default_worker_threads")]
+    pub threads: usize,
+    /// How many threads run heavy commands, jobs and bus events, so that those can't hold up
+    /// other plugins. If 0, they run on the ordinary threads.
+    #[serde(default = "default_heavy_threads")]
+    pub heavy_threads: usize,
+    /// Commands to run on the heavy threads, by path, as well as those their plugins mark heavy.
+    #[serde(default)]
+    pub heavy_commands: Vec<String>,
+    /// Job handlers whose jobs run on the heavy threads, by name.
+    #[serde(default)]
+    pub heavy_jobs: Vec<String>,
+    /// Bus topics whose events are delivered on the heavy threads.
+    #[serde(default)]
+    pub heavy_topics: Vec<String>,
+    /// How long a listener or command may take, in seconds, before a warning is logged, unless
+    /// the command sets its own time budget.
+    #[serde(default = "default_time_budget_seconds")]
+    pub time_budget_seconds: u64,
+    /// How long a listener or command may run, in seconds, before the watchdog reports it as
+    /// stuck.
+    #[serde(default = "default_watchdog_seconds")]
+    pub watchdog_seconds: u64,
+    /// Which events are guaranteed to be handled in the order they arrived.
+    #[serde(default = "default_delivery_order")]
+    pub ordering: DeliveryOrder,
+}
+
+impl Default for WorkerConf {
+    fn default() -> WorkerConf {
+        return WorkerConf {
+            threads: default_worker_threads(),
+            heavy_threads: default_heavy_threads(),
+            heavy_commands: Vec::new(),
+            heavy_jobs: Vec::new(),
+            heavy_topics: Vec::new(),
+            time_budget_seconds: default_time_budget_seconds(),
+            watchdog_seconds: default_watchdog_seconds(),
+            ordering: default_delivery_order(),
+        };
+    }
+}
+
+/// Which events the worker threads handle in the order they arrived. Events which must be handled
+/// in order wait for each other, so the more that are, the less parallelism there is. Heavy
+/// commands are only ordered among themselves.
+#[derive(Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryOrder {
+    /// Events are handled by whichever worker is free first, in no particular order.
+    None,
+    /// Events from the same channel, or from the same user in private, are handled in order.
+    /// Events from no channel, such as QUIT, are handled in order with each other.
+    Channel,
+    /// Each listener and command sees events in order, but different ones may run at once.
+    Listener,
+}
+
+fn default_delivery_order() -> DeliveryOrder {
+    DeliveryOrder::Channel
+}
+
+fn default_worker_threads() -> usize {
+    4
+}
+
+fn default_heavy_threads() -> usize {
+    2
+}
+
+fn default_time_budget_seconds() -> u64 {
+    5
+}
+
+fn default_watchdog_seconds() -> u64 {
+    60
+}
+
+/// Settings for disabling plugins whose commands keep panicking.
+#[derive(Deserialize)]
+pub struct CircuitBreakerConf {
+    /// How many times a plugin's commands may panic within `window_seconds` before the plugin is
+    /// disabled. 0 never disables plugins.
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    #[serde(default = "default_failure_window_seconds")]
+    pub window_seconds: u64,
+    /// How long a plugin stays disabled for, in seconds.
+    #[serde(default = "default_disable_seconds")]
+    pub disable_seconds: u64,
+}
+
+impl Default for CircuitBreakerConf {
+    fn default() -> CircuitBreakerConf {
+        return CircuitBreakerConf {
+            max_failures: default_max_failures(),
+            window_seconds: default_failure_window_seconds(),
+            disable_seconds: default_disable_seconds(),
+        };
+    }
+}
+
+fn default_max_failures() -> u32 {
+    5
+}
+
+fn default_failure_window_seconds() -> u64 {
+    600
+}
+
+fn default_disable_seconds() -> u64 {
+    1800
+}
+
+fn default_exempt_permission() -> String {
+    "ratelimit.exempt".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_account_cache_seconds() -> u64 {
+    300
+}
+
+fn default_data_directory() -> String {
+    "data".to_string()
+}
+
 #[derive(Deserialize)]
 pub struct ClientConfiguration {
     pub nick: String,
@@ -23,12 +341,88 @@ pub struct ClientConfiguration {
     pub nickserv: NickServConf,
     pub channels: Vec<String>,
     pub command_prefix: String,
+    /// Prefixes commands can also be used with, besides `command_prefix`.
+    #[serde(default)]
+    pub command_prefixes: Vec<String>,
+    /// Masks of users with every permission. Masks are globs unless prefixed with `re:`. Prefer
+    /// `roles` for new configurations.
+    #[serde(default)]
     pub admins: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<RoleConf>,
+    /// The permission required to use each listed command, overriding the command's default.
+    /// Subcommands are listed by their full path, as in `admin say`, and a group's permission
+    /// also applies to its subcommands. An empty string allows anyone to use the command.
+    #[serde(default)]
+    pub command_permissions: collections::HashMap<String, String>,
+    #[serde(default)]
+    pub accounts: AccountConf,
+    #[serde(default)]
+    pub rate_limits: RateLimitConf,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConf,
+    #[serde(default)]
+    pub workers: WorkerConf,
+    /// How replies to commands are sent, unless a channel's settings say otherwise.
+    #[serde(default)]
+    pub replies: ReplyConf,
+    /// Whether to suggest similar commands when an unknown one is used.
+    #[serde(default)]
+    pub suggest_commands: bool,
+    /// Command aliases, by name. See `aliases::AliasTable` for how they expand.
+    #[serde(default)]
+    pub aliases: collections::HashMap<String, String>,
+    /// Settings for individual channels, by channel name.
+    #[serde(default)]
+    pub channel_settings: collections::HashMap<String, ChannelConf>,
+    /// Settings for each plugin, by plugin name. Plugins read their own section with
+    /// `PluginRegister::config`.
+    #[serde(default)]
+    pub plugins: collections::HashMap<String, serde_json::Value>,
     pub on_connect: Vec<String>,
     pub password: Option<String>,
     pub log_file: String,
     pub log_level: String,
     pub watch_binary: bool,
+    /// Directory which state changed at runtime, such as the ignore list and plugins' stored
+    /// data, is saved in.
+    #[serde(default = "default_data_directory")]
+    pub data_directory: String,
+}
+
+/// A plugin's settings, from its section of `plugins` in the configuration. These are read when
+/// the bot starts, before any plugin runs.
+pub struct PluginConfig<T> {
+    value: sync::Arc<sync::RwLock<Option<sync::Arc<T>>>>,
+}
+
+impl <T> PluginConfig<T> {
+    pub fn new() -> PluginConfig<T> {
+        return PluginConfig {
+            value: sync::Arc::new(sync::RwLock::new(None)),
+        };
+    }
+
+    /// Gets the plugin's settings.
+    ///
+    /// Panics if the configuration hasn't been loaded yet, which can only happen when called
+    /// from a plugin's `register` function.
+    pub fn get(&self) -> sync::Arc<T> {
+        let value = self.value.read().unwrap();
+        return value.clone().expect("Plugin configuration used before it was loaded.");
+    }
+
+    pub fn set(&self, value: T) {
+        *self.value.write().unwrap() = Some(sync::Arc::new(value));
+    }
+}
+
+impl <T> Clone for PluginConfig<T> {
+    fn clone(&self) -> PluginConfig<T> {
+        return PluginConfig {
+            value: self.value.clone(),
+        };
+    }
 }
 
 impl ClientConfiguration {
@@ -41,4 +435,52 @@ impl ClientConfiguration {
 
         Ok(throw!(serde_json::from_str(&config_contents)))
     }
+
+    /// Gets the path of a file in the data directory.
+    pub fn data_path(&self, file_name: &str) -> PathBuf {
+        return Path::new(&self.data_directory).join(file_name);
+    }
+
+    /// Gets the settings for `channel`, if there are any. Channel names are compared using the
+    /// server's casemapping.
+    pub fn channel_conf(&self, channel: &str, casemapping: CaseMapping) -> Option<&ChannelConf> {
+        self.channel_settings.iter()
+            .find(|&(name, _)| casemapping.equals(name, channel))
+            .map(|(_, conf)| conf)
+    }
+
+    /// Gets the prefixes commands can be used with in `channel`, in order of preference.
+    pub fn command_prefixes(&self, channel: &str, casemapping: CaseMapping) -> Vec<&str> {
+        let conf = self.channel_conf(channel, casemapping);
+        let prefixes = match conf.and_then(|c| c.command_prefixes.as_ref()) {
+            Some(prefixes) => prefixes.iter().map(|s| &**s).collect::<Vec<&str>>(),
+            None => Some(&*self.command_prefix).into_iter()
+                .chain(self.command_prefixes.iter().map(|s| &**s)).collect(),
+        };
+        return prefixes.into_iter().filter(|s| !s.is_empty()).collect();
+    }
+
+    /// Gets the prefix to show in command usage and help for `channel`.
+    pub fn display_prefix(&self, channel: &str, casemapping: CaseMapping) -> &str {
+        return self.command_prefixes(channel, casemapping).first()
+            .map_or(&*self.command_prefix, |s| *s);
+    }
+
+    /// Gets how replies to commands are sent in `channel`.
+    pub fn reply_conf(&self, channel: &str, casemapping: CaseMapping) -> &ReplyConf {
+        return self.channel_conf(channel, casemapping).and_then(|c| c.replies.as_ref())
+            .unwrap_or(&self.replies);
+    }
+
+    /// Checks whether similar commands are suggested when an unknown one is used in `channel`.
+    pub fn suggest_commands(&self, channel: &str, casemapping: CaseMapping) -> bool {
+        return self.channel_conf(channel, casemapping).and_then(|c| c.suggest_commands)
+            .unwrap_or(self.suggest_commands);
+    }
+
+    /// Checks whether commands can be used in `channel` by addressing the bot by nick.
+    pub fn nick_addressing(&self, channel: &str, casemapping: CaseMapping) -> bool {
+        return self.channel_conf(channel, casemapping).and_then(|c| c.nick_addressing)
+            .unwrap_or(true);
+    }
 }
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::u64;
+
+/// How many days ahead `CronSchedule::next_after` looks. Eight years always include a 29th of
+/// February, even around a century year which isn't a leap year.
+const SEARCH_DAYS: u64 = 366 * 8 + 1;
+
+/// A cron-like schedule: `minute hour day-of-month month day-of-week`, in UTC.
+///
+/// Each field is `*`, a number, a range such as `1-5`, any of those followed by a step such as
+/// `*/15`, or a comma separated list of them. Days of the week go from 0 for Sunday to 6, and 7
+/// is also Sunday. As in cron, if both the day of the month and day of the week are restricted,
+/// a day matching either is used.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<CronSchedule, String> {
+        let fields = expression.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() != 5 {
+            return Err(format!("Expected 5 fields in `{}`, found {}.", expression, fields.len()));
+        }
+        let mut weekdays = try!(parse_field(fields[4], 0, 7));
+        // 7 is another name for Sunday.
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        weekdays.truncate(7);
+        return Ok(CronSchedule {
+            expression: fields.join(" "),
+            minutes: try!(parse_field(fields[0], 0, 59)),
+            hours: try!(parse_field(fields[1], 0, 23)),
+            // Index 0 is unused, so that days and months can be indexed directly.
+            days: try!(parse_field(fields[2], 1, 31)),
+            months: try!(parse_field(fields[3], 1, 12)),
+            weekdays: weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        });
+    }
+
+    /// Finds the first time matching the schedule strictly after `after`, both in unix seconds.
+    /// Returns None if there is none in the next several years, as for `0 0 31 2 *`.
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        // Times this far ahead can't be searched without overflowing.
+        if after > u64::MAX - (SEARCH_DAYS + 1) * 86400 {
+            return None;
+        }
+        let start = after - after % 60 + 60;
+        let start_day = start / 86400;
+        for day in start_day..start_day + SEARCH_DAYS {
+            if !self.day_matches(day) {
+                continue;
+            }
+            for hour in 0..24 {
+                if !self.hours[hour] {
+                    continue;
+                }
+                for minute in 0..60 {
+                    let time = day * 86400 + hour as u64 * 3600 + minute as u64 * 60;
+                    if self.minutes[minute] && time >= start {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+        return None;
+    }
+
+    fn day_matches(&self, day: u64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        if !self.months[month as usize] {
+            return false;
+        }
+        // 1970-01-01 was a Thursday.
+        let weekday = ((day + 4) % 7) as usize;
+        let day_matches = self.days[day_of_month as usize];
+        let weekday_matches = self.weekdays[weekday];
+        if self.days_restricted && self.weekdays_restricted {
+            return day_matches || weekday_matches;
+        }
+        return day_matches && weekday_matches;
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(&self.expression)
+    }
+}
+
+/// Parses one field, returning which values from 0 to `max` it includes.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    let mut included = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(index) => {
+                let step = try!(parse_number(&part[index + 1..], 1, max));
+                (&part[..index], step)
+            },
+            None => (part, 1),
+        };
+        let (low, high) = if range == "*" {
+            (min, max)
+        } else if let Some(index) = range.find('-') {
+            (try!(parse_number(&range[..index], min, max)),
+                try!(parse_number(&range[index + 1..], min, max)))
+        } else {
+            let value = try!(parse_number(range, min, max));
+            // As in cron, `5/10` means from 5 to the end in steps of 10.
+            (value, if step > 1 { max } else { value })
+        };
+        if low > high {
+            return Err(format!("Range `{}` is backwards.", range));
+        }
+        let mut value = low;
+        while value <= high {
+            included[value as usize] = true;
+            value += step;
+        }
+    }
+    return Ok(included);
+}
+
+fn parse_number(text: &str, min: u32, max: u32) -> Result<u32, String> {
+    match text.parse::<u32>() {
+        Ok(value) if value >= min && value <= max => Ok(value),
+        _ => Err(format!("`{}` isn't a number from {} to {}.", text, min, max)),
+    }
+}
+
+/// Converts days since 1970-01-01 to a `(year, month, day)` date, using the algorithm from
+/// http://howardhinnant.github.io/date_algorithms.html.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::u64;
+
+    use super::CronSchedule;
+
+    /// 2024-01-01 00:00:00 UTC, a Monday.
+    const NEW_YEAR_2024: u64 = 1704067200;
+    const DAY: u64 = 86400;
+
+    fn next(expression: &str, after: u64) -> Option<u64> {
+        CronSchedule::parse(expression).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(CronSchedule::parse("* * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("* * 0 * *").is_err());
+        assert!(CronSchedule::parse("5-1 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert_eq!(CronSchedule::parse(" 0  12 * *  1 ").unwrap().to_string(), "0 12 * * 1");
+    }
+
+    #[test]
+    fn next_after_is_strictly_later() {
+        let t = NEW_YEAR_2024;
+        assert_eq!(next("*/15 * * * *", t), Some(t + 15 * 60));
+        assert_eq!(next("*/15 * * * *", t + 1), Some(t + 15 * 60));
+        assert_eq!(next("* * * * *", t + 59), Some(t + 60));
+        assert_eq!(next("30 9 * * 1-5", t), Some(t + 9 * 3600 + 30 * 60));
+        assert_eq!(next("5/20 0 * * *", t), Some(t + 5 * 60));
+    }
+
+    #[test]
+    fn weekdays() {
+        let t = NEW_YEAR_2024;
+        // The first Sunday of 2024 was the 7th, and 7 is another name for Sunday.
+        assert_eq!(next("0 12 * * 0", t), Some(t + 6 * DAY + 12 * 3600));
+        assert_eq!(next("0 0 * * 7", t), Some(t + 6 * DAY));
+        assert_eq!(next("0 0 * * 6", t), Some(1704499200));
+    }
+
+    #[test]
+    fn restricted_day_and_weekday_either_match() {
+        let t = NEW_YEAR_2024;
+        // Mondays or the 1st of the month. The 1st of January itself is excluded as it isn't
+        // strictly later, so the next is Monday the 8th.
+        assert_eq!(next("0 0 1 * 1", t), Some(t + 7 * DAY));
+        // Fridays or the 3rd: the 3rd comes first.
+        assert_eq!(next("0 0 3 * 5", t), Some(t + 2 * DAY));
+        // With only the day restricted, the weekday doesn't matter.
+        assert_eq!(next("0 0 3 * *", t), Some(t + 2 * DAY));
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(next("0 0 29 2 *", NEW_YEAR_2024), Some(1709164800));
+        // From 2025, the next leap day is in 2028.
+        assert_eq!(next("0 0 29 2 *", 1735689600), Some(1835395200));
+        // 2100 isn't a leap year, so the next leap day after it is in 2104.
+        assert_eq!(next("0 0 29 2 *", 4102444800), Some(4233686400));
+        assert_eq!(next("0 0 31 2 *", NEW_YEAR_2024), None);
+    }
+
+    #[test]
+    fn far_future_does_not_overflow() {
+        assert_eq!(next("* * * * *", u64::MAX), None);
+        assert_eq!(next("* * * * *", u64::MAX - 60), None);
+    }
+}
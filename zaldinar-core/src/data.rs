@@ -0,0 +1,44 @@
+use std::io::prelude::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde;
+use serde_json;
+
+use errors::ThrowInitError;
+
+/// Reads a JSON file written by `save_json`. Returns None if the file doesn't exist yet.
+pub fn load_json<T>(path: &Path) -> Result<Option<T>, ThrowInitError>
+        where T: serde::de::DeserializeOwned {
+    let mut file = match fs::File::open(path) {
+        Ok(v) => v,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => throw!(Err(e)),
+    };
+    let mut buf = String::new();
+    throw!(file.read_to_string(&mut buf));
+    return Ok(Some(throw!(serde_json::from_str(&buf))));
+}
+
+/// Writes `value` to a JSON file, creating its directory if needed.
+///
+/// The data is written to a temporary file which then replaces `path`, so that the old file is
+/// left intact if writing fails part way through.
+pub fn save_json<T>(path: &Path, value: &T) -> Result<(), ThrowInitError>
+        where T: serde::Serialize {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            throw!(fs::create_dir_all(parent));
+        }
+    }
+    let contents = throw!(serde_json::to_string_pretty(value));
+    let temp_path = path.with_extension("json.tmp");
+    {
+        let mut file = throw!(fs::File::create(&temp_path));
+        throw!(file.write_all(contents.as_bytes()));
+        throw!(file.sync_all());
+    }
+    throw!(fs::rename(&temp_path, path));
+    return Ok(());
+}
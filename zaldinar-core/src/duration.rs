@@ -0,0 +1,117 @@
+use std::ascii::AsciiExt;
+use std::time;
+
+/// Units accepted by `parse`, with their length in seconds.
+const UNITS: &'static [(char, u64)] = &[
+    ('w', 7 * 24 * 60 * 60),
+    ('d', 24 * 60 * 60),
+    ('h', 60 * 60),
+    ('m', 60),
+    ('s', 1),
+];
+
+/// Parses a human written duration such as `30s`, `10m`, `2h` or `1d12h`.
+///
+/// A number without a unit is a number of seconds. Returns None if the input is empty or
+/// malformed.
+pub fn parse(input: &str) -> Option<time::Duration> {
+    if input.is_empty() {
+        return None;
+    }
+    let mut total = 0u64;
+    let mut number: Option<u64> = None;
+    for c in input.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            let previous = number.unwrap_or(0);
+            number = match previous.checked_mul(10).and_then(|n| n.checked_add(digit as u64)) {
+                Some(v) => Some(v),
+                None => return None,
+            };
+            continue;
+        }
+        let unit_seconds = match UNITS.iter().find(|&&(u, _)| u == c.to_ascii_lowercase()) {
+            Some(&(_, seconds)) => seconds,
+            None => return None,
+        };
+        let amount = match number.take() {
+            Some(v) => v,
+            None => return None,
+        };
+        total = match amount.checked_mul(unit_seconds).and_then(|s| total.checked_add(s)) {
+            Some(v) => v,
+            None => return None,
+        };
+    }
+    if let Some(seconds) = number {
+        total = match total.checked_add(seconds) {
+            Some(v) => v,
+            None => return None,
+        };
+    }
+    return Some(time::Duration::from_secs(total));
+}
+
+/// Formats a duration in the style accepted by `parse`, such as `1d 2h 5m`.
+pub fn format(duration: time::Duration) -> String {
+    let mut remaining = duration.as_secs();
+    if remaining == 0 {
+        return "0s".to_string();
+    }
+    let mut parts = Vec::new();
+    for &(unit, seconds) in UNITS {
+        if remaining >= seconds {
+            parts.push(format!("{}{}", remaining / seconds, unit));
+            remaining %= seconds;
+        }
+    }
+    return parts.join(" ");
+}
+
+/// Gets the current unix time in seconds, as used for expiry times and scheduled jobs.
+pub fn unix_now() -> u64 {
+    match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(v) => v.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format, parse};
+
+    #[test]
+    fn parse_units() {
+        assert_eq!(parse("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("10M"), Some(Duration::from_secs(600)));
+        assert_eq!(parse("1d12h"), Some(Duration::from_secs(36 * 60 * 60)));
+        assert_eq!(parse("1w"), Some(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert_eq!(parse("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse("1m30"), Some(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("h"), None);
+        assert_eq!(parse("5x"), None);
+        assert_eq!(parse("1 h"), None);
+        assert_eq!(parse("-5s"), None);
+    }
+
+    #[test]
+    fn parse_rejects_overflow() {
+        assert_eq!(parse("18446744073709551616"), None);
+        assert_eq!(parse("18446744073709551615w"), None);
+        assert_eq!(parse("18446744073709551615s1s"), None);
+    }
+
+    #[test]
+    fn format_round_trips() {
+        assert_eq!(format(Duration::from_secs(93784)), "1d 2h 3m 4s");
+        assert_eq!(format(Duration::from_secs(0)), "0s");
+        assert_eq!(parse(&format(Duration::from_secs(93784)).replace(" ", "")),
+            Some(Duration::from_secs(93784)));
+    }
+}
@@ -77,3 +77,63 @@ impl fmt::Display for InitializationError {
         }
     }
 }
+
+pub type ThrowRuntimeError = throw::Error<RuntimeError>;
+
+/// An error from something plugins do while the bot is running, such as saving to storage or
+/// scheduling a job.
+#[derive(Debug)]
+pub enum RuntimeError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Generic(String),
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(error: io::Error) -> RuntimeError {
+        RuntimeError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for RuntimeError {
+    fn from(error: serde_json::Error) -> RuntimeError {
+        RuntimeError::Json(error)
+    }
+}
+
+impl From<String> for RuntimeError {
+    fn from(error: String) -> RuntimeError {
+        RuntimeError::Generic(error)
+    }
+}
+
+/// For errors from the `data` functions, which are shared with initialization.
+impl From<InitializationError> for RuntimeError {
+    fn from(error: InitializationError) -> RuntimeError {
+        match error {
+            InitializationError::Io(e) => RuntimeError::Io(e),
+            InitializationError::Decoder(e) => RuntimeError::Json(e),
+            InitializationError::Regex(e) => RuntimeError::Generic(format!("{}", e)),
+            InitializationError::Generic(s) => RuntimeError::Generic(s),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &RuntimeError::Io(ref e) => {
+                try!(fmt.write_str("IO Error: "));
+                fmt::Display::fmt(e, fmt)
+            },
+            &RuntimeError::Json(ref e) => {
+                try!(fmt.write_str("JSON Error: "));
+                fmt::Display::fmt(e, fmt)
+            },
+            &RuntimeError::Generic(ref s) => {
+                try!(fmt.write_str("Error: "));
+                fmt::Display::fmt(s, fmt)
+            }
+        }
+    }
+}
@@ -1,7 +1,16 @@
+use std::ascii::AsciiExt;
+use std::borrow::Borrow;
+use std::cell;
 use std::ops;
 
 use irc;
+use modes;
+use args;
+use config::{ReplyConf, ReplyMode};
+use isupport::ServerSupport;
 use interface::IrcInterface;
+use scheduler::Job;
+use bus::BusMessage;
 
 #[derive(Clone)]
 pub struct FullIrcMask {
@@ -93,6 +102,42 @@ impl IrcMask {
     }
 }
 
+/// Where a message was sent.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MessageContext {
+    /// To a channel.
+    Channel,
+    /// Directly to the bot.
+    Private,
+    /// To the users in a channel with the given status or higher, as in `PRIVMSG @#channel`.
+    StatusMsg(char),
+}
+
+impl MessageContext {
+    /// Finds where a message sent to `target` went. Also returns the channel name without any
+    /// STATUSMSG prefix.
+    pub fn from_target<'a>(target: &'a str, support: &ServerSupport) -> (MessageContext, &'a str) {
+        if support.is_channel(target) {
+            return (MessageContext::Channel, target);
+        }
+        let mut chars = target.chars();
+        if let Some(c) = chars.next() {
+            if support.statusmsg.contains(c) && support.is_channel(chars.as_str()) {
+                return (MessageContext::StatusMsg(c), chars.as_str());
+            }
+        }
+        return (MessageContext::Private, target);
+    }
+
+    /// Checks whether the message was sent to a channel, including to some of its users.
+    pub fn is_channel(&self) -> bool {
+        match self {
+            &MessageContext::Channel | &MessageContext::StatusMsg(_) => true,
+            &MessageContext::Private => false,
+        }
+    }
+}
+
 pub struct MessageEvent<'a> {
     pub client: &'a IrcInterface,
     internal: &'a MessageTransport,
@@ -105,6 +150,21 @@ impl <'a> MessageEvent<'a> {
             internal: internal,
         }
     }
+
+    /// Parses a MODE message into the individual modes it changes, using the modes advertised by
+    /// the server to decide which modes take parameters. Returns an empty list for any other
+    /// command.
+    pub fn mode_changes(&self) -> Vec<modes::ModeChange> {
+        if !self.command.eq_ignore_ascii_case("MODE") || self.args.len() < 2 {
+            return Vec::new();
+        }
+        let state = self.client.state().read().unwrap();
+        if state.support.is_channel(&self.args[0]) {
+            return modes::parse_channel_modes(&state.support, &self.args[1..]);
+        } else {
+            return modes::parse_user_modes(&self.args[1..]);
+        }
+    }
 }
 
 impl <'a> ops::Deref for MessageEvent<'a> {
@@ -118,6 +178,9 @@ impl <'a> ops::Deref for MessageEvent<'a> {
 pub struct CommandEvent<'a> {
     pub client: &'a IrcInterface,
     internal: &'a CommandTransport,
+    /// Where replies go instead of being sent, when the command's output is piped into another
+    /// command.
+    output: Option<&'a cell::RefCell<Vec<String>>>,
 }
 
 impl <'a> CommandEvent<'a> {
@@ -125,7 +188,118 @@ impl <'a> CommandEvent<'a> {
         return CommandEvent {
             client: client,
             internal: internal,
+            output: None,
+        }
+    }
+
+    /// Creates an event whose replies, other than errors, are added to `output` rather than
+    /// sent.
+    pub fn capturing(client: &'a IrcInterface, internal: &'a CommandTransport,
+            output: &'a cell::RefCell<Vec<String>>) -> CommandEvent<'a> {
+        return CommandEvent {
+            client: client,
+            internal: internal,
+            output: Some(output),
+        }
+    }
+
+    /// Checks whether replies are being captured, because the command's output is piped into
+    /// another command.
+    pub fn is_captured(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Replies to the command where the channel's `replies` settings say to.
+    pub fn reply<T: Borrow<str>>(&self, message: T) {
+        if self.capture(message.borrow()) {
+            return;
+        }
+        let mode = self.reply_conf().mode;
+        self.send_reply(mode, false, message.borrow());
+    }
+
+    /// Replies to the command in a private message to the user who used it.
+    pub fn reply_private<T: Borrow<str>>(&self, message: T) {
+        if self.capture(message.borrow()) {
+            return;
         }
+        self.send_reply(ReplyMode::Private, false, message.borrow());
+    }
+
+    /// Replies to the command with a CTCP ACTION. Actions can't be sent in notices, so they are
+    /// sent in a private message instead when replies are sent in notices.
+    pub fn reply_action<T: Borrow<str>>(&self, message: T) {
+        if self.capture(message.borrow()) {
+            return;
+        }
+        let mode = match self.reply_conf().mode {
+            ReplyMode::Notice => ReplyMode::Private,
+            mode => mode,
+        };
+        self.send_reply(mode, true, message.borrow());
+    }
+
+    /// Tells the user who used the command about an error, where the channel's `replies`
+    /// settings say to. Errors are never captured.
+    pub fn reply_error<T: Borrow<str>>(&self, message: T) {
+        let mode = self.reply_conf().errors;
+        self.send_reply(mode, false, message.borrow());
+    }
+
+    /// Gets how replies are sent in the channel the command was used in.
+    fn reply_conf(&self) -> &ReplyConf {
+        return self.client.reply_conf(self.channel(), self.client.casemapping());
+    }
+
+    fn capture(&self, message: &str) -> bool {
+        match self.output {
+            Some(output) => {
+                output.borrow_mut().push(message.to_string());
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    fn send_reply(&self, mode: ReplyMode, action: bool, message: &str) {
+        let nick = match self.mask().nick() {
+            Some(v) => v,
+            None => return,
+        };
+        // Replies to STATUSMSG commands go to the same users the command was seen by.
+        let source = match self.context {
+            MessageContext::StatusMsg(c) => format!("{}{}", c, self.channel()),
+            _ => self.channel().to_string(),
+        };
+        let private = self.context == MessageContext::Private;
+        let (command, target) = match mode {
+            _ if private => ("PRIVMSG", nick),
+            ReplyMode::Channel => ("PRIVMSG", &*source),
+            ReplyMode::Notice => ("NOTICE", nick),
+            ReplyMode::Private => ("PRIVMSG", nick),
+        };
+        let to_source = private || target == source;
+        let conf = self.reply_conf();
+
+        let mut line = String::new();
+        // Only replies sent where the command was used can be threaded.
+        if conf.threaded && to_source {
+            if let Some(ref msgid) = self.msgid {
+                let state = self.client.state().read().unwrap();
+                if state.has_capability("message-tags") {
+                    line.push_str(&format!("@+draft/reply={} ", irc::escape_tag_value(msgid)));
+                }
+            }
+        }
+        line.push_str(&format!("{} {} :", command, target));
+        if action {
+            line.push_str(&format!("\x01ACTION {}\x01", message));
+        } else if conf.prefix_nick && !private && to_source {
+            line.push_str(&format!("{}: {}", nick, message));
+        } else {
+            line.push_str(message);
+        }
+        self.client.send_raw(line);
     }
 }
 
@@ -159,6 +333,80 @@ impl <'a> ops::Deref for CtcpEvent<'a> {
     }
 }
 
+/// Points in the bot's life which plugins can run hooks at.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Lifecycle {
+    /// The bot is starting up, and the configuration is loaded. This is before connecting, and
+    /// happens again each time the bot restarts.
+    Load,
+    /// The bot is registered with the server and has sent JOINs for the configured channels.
+    Connect,
+    /// The connection to the server has closed.
+    Disconnect,
+    /// The bot is about to quit, either to exit or to restart.
+    Shutdown,
+}
+
+pub struct LifecycleEvent<'a> {
+    pub client: &'a IrcInterface,
+    pub stage: Lifecycle,
+}
+
+impl <'a> LifecycleEvent<'a> {
+    pub fn new(client: &'a IrcInterface, stage: Lifecycle) -> LifecycleEvent<'a> {
+        return LifecycleEvent {
+            client: client,
+            stage: stage,
+        }
+    }
+}
+
+/// A scheduled job which is due to run.
+pub struct JobEvent<'a> {
+    pub client: &'a IrcInterface,
+    internal: &'a Job,
+}
+
+impl <'a> JobEvent<'a> {
+    pub fn new(client: &'a IrcInterface, internal: &'a Job) -> JobEvent<'a> {
+        return JobEvent {
+            client: client,
+            internal: internal,
+        }
+    }
+}
+
+impl <'a> ops::Deref for JobEvent<'a> {
+    type Target = Job;
+
+    fn deref(&self) -> &Job {
+        self.internal
+    }
+}
+
+/// An event published on the bus by a plugin.
+pub struct BusEvent<'a> {
+    pub client: &'a IrcInterface,
+    internal: &'a BusMessage,
+}
+
+impl <'a> BusEvent<'a> {
+    pub fn new(client: &'a IrcInterface, internal: &'a BusMessage) -> BusEvent<'a> {
+        return BusEvent {
+            client: client,
+            internal: internal,
+        }
+    }
+}
+
+impl <'a> ops::Deref for BusEvent<'a> {
+    type Target = BusMessage;
+
+    fn deref(&self) -> &BusMessage {
+        self.internal
+    }
+}
+
 #[derive(Clone)]
 pub struct MessageTransport {
     pub command: String,
@@ -166,20 +414,48 @@ pub struct MessageTransport {
     pub mask: IrcMask,
     /// (ctcp_command, ctcp_message)
     pub ctcp: Option<(String, String)>,
+    /// The channel the message is for. For private messages, this is the sender's nick, and for
+    /// STATUSMSG messages, it is the channel without the status prefix.
     pub channel: Option<String>,
+    /// Where the message was sent, for messages which have a channel.
+    pub context: Option<MessageContext>,
+    /// IRCv3 message tags as (key, value) pairs.
+    pub tags: Vec<(String, Option<String>)>,
 }
 
 impl MessageTransport {
-    pub fn from_internal(m: &irc::IrcMessage) -> MessageTransport {
+    pub fn from_internal(m: &irc::IrcMessage, support: &ServerSupport) -> MessageTransport {
+        let is_message = m.command.eq_ignore_ascii_case("PRIVMSG")
+            || m.command.eq_ignore_ascii_case("NOTICE");
+        let (channel, context) = match (&m.channel, m.args.first()) {
+            (&Some(ref channel), Some(target)) if is_message => {
+                match MessageContext::from_target(target, support) {
+                    (MessageContext::StatusMsg(c), name) => {
+                        (Some(name.to_string()), Some(MessageContext::StatusMsg(c)))
+                    },
+                    (context, _) => (Some(channel.clone()), Some(context)),
+                }
+            },
+            (&Some(ref channel), _) => (Some(channel.clone()), Some(MessageContext::Channel)),
+            (&None, _) => (None, None),
+        };
         return MessageTransport {
             command: m.command.clone(),
             args: m.args.clone(),
             mask: IrcMask::from_internal(&m.mask),
             ctcp: m.ctcp.clone(),
-            channel: m.channel.clone(),
+            channel: channel,
+            context: context,
+            tags: m.tags.clone(),
         };
     }
 
+    /// Gets the value of a message tag. Returns None if the tag is missing or has no value.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|&&(ref k, _)| k == key).and_then(|&(_, ref v)| v.as_ref())
+            .map(|v| &**v)
+    }
+
     #[inline(always)]
     pub fn command(&self) -> &str {
         &self.command
@@ -202,21 +478,37 @@ impl MessageTransport {
     pub fn channel(&self) -> Option<&str> {
         self.channel.as_ref().map(|s| &**s)
     }
+
+    #[inline(always)]
+    pub fn context(&self) -> Option<MessageContext> {
+        self.context
+    }
 }
 
 #[derive(Clone)]
 pub struct CommandTransport {
+    /// The channel the command was used in, or the sender's nick if it was used in a private
+    /// message.
     pub channel: String,
+    pub context: MessageContext,
     pub args: Vec<String>,
     pub mask: IrcMask,
+    /// Arguments parsed using the command's `ArgSpec`. Empty if the command has none.
+    pub parsed: args::ParsedArgs,
+    /// The `msgid` tag of the message the command was used in, if the server gave one.
+    pub msgid: Option<String>,
 }
 
 impl CommandTransport {
-    pub fn new(channel: &str, args: Vec<String>, mask: &irc::IrcMask) -> CommandTransport {
+    pub fn new(channel: &str, context: MessageContext, args: Vec<String>, mask: &irc::IrcMask)
+            -> CommandTransport {
         return CommandTransport {
             channel: channel.to_string(),
+            context: context,
             args: args,
             mask: IrcMask::from_internal(mask),
+            parsed: args::ParsedArgs::new(),
+            msgid: None,
         }
     }
 
@@ -230,10 +522,20 @@ impl CommandTransport {
         &self.channel
     }
 
+    #[inline(always)]
+    pub fn context(&self) -> MessageContext {
+        self.context
+    }
+
     #[inline(always)]
     pub fn args(&self) -> &[String] {
         &self.args
     }
+
+    #[inline(always)]
+    pub fn parsed(&self) -> &args::ParsedArgs {
+        &self.parsed
+    }
 }
 
 #[derive(Clone)]
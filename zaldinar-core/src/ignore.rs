@@ -0,0 +1,203 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::path;
+use std::time;
+
+use regex;
+
+use client::ClientState;
+use data;
+use duration;
+use errors::ThrowInitError;
+use masks::{self, Mask};
+
+/// What an ignore blocks.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IgnoreScope {
+    /// Only commands from the user are ignored.
+    Commands,
+    /// Commands, messages, notices and CTCPs from the user are all ignored. Joins, parts, nick
+    /// and mode changes are still tracked, so that the bot's view of its channels stays correct.
+    All,
+}
+
+impl fmt::Display for IgnoreScope {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(match self {
+            &IgnoreScope::Commands => "commands",
+            &IgnoreScope::All => "all",
+        })
+    }
+}
+
+/// Who an ignore applies to.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IgnoreTarget {
+    /// A mask matched against the user's `nick!user@host`.
+    Mask(String),
+    /// A services account.
+    Account(String),
+    /// A nick, which stops applying once the user changes nick.
+    Nick(String),
+}
+
+impl IgnoreTarget {
+    /// Parses a target as written in the `ignore` command: `account:<name>`, `nick:<name>`, or a
+    /// mask. Anything else containing `!`, `@`, `*` or `?` is treated as a mask, and a plain
+    /// word as a nick.
+    pub fn parse(input: &str) -> IgnoreTarget {
+        if input.starts_with("account:") {
+            IgnoreTarget::Account(input["account:".len()..].to_string())
+        } else if input.starts_with("nick:") {
+            IgnoreTarget::Nick(input["nick:".len()..].to_string())
+        } else if input.starts_with(masks::REGEX_PREFIX)
+                || input.contains(|c| c == '!' || c == '@' || c == '*' || c == '?') {
+            IgnoreTarget::Mask(input.to_string())
+        } else {
+            IgnoreTarget::Nick(input.to_string())
+        }
+    }
+
+    fn same_as(&self, other: &IgnoreTarget) -> bool {
+        match (self, other) {
+            (&IgnoreTarget::Mask(ref a), &IgnoreTarget::Mask(ref b)) => a == b,
+            (&IgnoreTarget::Account(ref a), &IgnoreTarget::Account(ref b)) |
+            (&IgnoreTarget::Nick(ref a), &IgnoreTarget::Nick(ref b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IgnoreTarget {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &IgnoreTarget::Mask(ref mask) => fmt.write_str(mask),
+            &IgnoreTarget::Account(ref account) => write!(fmt, "account:{}", account),
+            &IgnoreTarget::Nick(ref nick) => write!(fmt, "nick:{}", nick),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ignore {
+    pub target: IgnoreTarget,
+    pub scope: IgnoreScope,
+    /// Unix time in seconds at which the ignore stops applying, or None if it never does.
+    #[serde(default)]
+    pub expires: Option<u64>,
+    /// Nick of whoever added the ignore.
+    pub added_by: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Ignore {
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.expires {
+            Some(expires) => expires <= now,
+            None => false,
+        }
+    }
+
+    /// Gets how long is left until the ignore expires, or None if it never does.
+    pub fn remaining(&self, now: u64) -> Option<time::Duration> {
+        self.expires.map(|expires| time::Duration::from_secs(expires.saturating_sub(now)))
+    }
+}
+
+struct IgnoreEntry {
+    ignore: Ignore,
+    /// Compiled form of the target, if it is a mask.
+    mask: Option<Mask>,
+}
+
+impl IgnoreEntry {
+    fn new(ignore: Ignore) -> Result<IgnoreEntry, regex::Error> {
+        let mask = match ignore.target {
+            IgnoreTarget::Mask(ref m) => Some(try!(Mask::parse(m))),
+            _ => None,
+        };
+        return Ok(IgnoreEntry {
+            ignore: ignore,
+            mask: mask,
+        });
+    }
+}
+
+/// The ignore list, stored in `ignores.json` in the data directory.
+pub struct IgnoreList {
+    path: path::PathBuf,
+    entries: Vec<IgnoreEntry>,
+}
+
+impl IgnoreList {
+    /// Loads the ignore list stored at `path`, or creates an empty one if it doesn't exist yet.
+    pub fn load(path: &path::Path) -> Result<IgnoreList, ThrowInitError> {
+        let ignores: Vec<Ignore> = up!(data::load_json(path)).unwrap_or_else(Vec::new);
+        let mut entries = Vec::with_capacity(ignores.len());
+        for ignore in ignores {
+            entries.push(throw!(IgnoreEntry::new(ignore)));
+        }
+        return Ok(IgnoreList {
+            path: path.to_path_buf(),
+            entries: entries,
+        });
+    }
+
+    pub fn save(&self) -> Result<(), ThrowInitError> {
+        let ignores = self.entries.iter().map(|e| &e.ignore).collect::<Vec<&Ignore>>();
+        return data::save_json(&self.path, &ignores);
+    }
+
+    /// Adds an ignore, replacing any existing ignore of the same target.
+    ///
+    /// Fails if the target is a `re:` mask which isn't a valid regex.
+    pub fn add(&mut self, ignore: Ignore) -> Result<(), regex::Error> {
+        let entry = try!(IgnoreEntry::new(ignore));
+        self.entries.retain(|e| !e.ignore.target.same_as(&entry.ignore.target));
+        self.entries.push(entry);
+        return Ok(());
+    }
+
+    /// Removes the ignore of `target`, returning it if there was one.
+    pub fn remove(&mut self, target: &IgnoreTarget) -> Option<Ignore> {
+        match self.entries.iter().position(|e| e.ignore.target.same_as(target)) {
+            Some(index) => Some(self.entries.remove(index).ignore),
+            None => None,
+        }
+    }
+
+    /// Removes every ignore which has expired, returning how many were removed.
+    pub fn remove_expired(&mut self, now: u64) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| !e.ignore.is_expired(now));
+        return before - self.entries.len();
+    }
+
+    pub fn ignores(&self) -> Vec<&Ignore> {
+        return self.entries.iter().map(|e| &e.ignore).collect();
+    }
+
+    /// Finds what is ignored from a user. If several ignores apply, the widest scope is returned.
+    pub fn check(&self, state: &ClientState, mask: Option<&str>, nick: Option<&str>,
+            account: Option<&str>) -> Option<IgnoreScope> {
+        let casemapping = state.support.casemapping;
+        let now = duration::unix_now();
+        return self.entries.iter()
+            .filter(|e| !e.ignore.is_expired(now))
+            .filter(|e| match (&e.ignore.target, &e.mask) {
+                (_, &Some(ref matcher)) => mask.map_or(false, |m| matcher.matches(m, casemapping)),
+                (&IgnoreTarget::Account(ref ignored), _) => {
+                    account.map_or(false, |a| a.eq_ignore_ascii_case(ignored))
+                },
+                (&IgnoreTarget::Nick(ref ignored), _) => {
+                    nick.map_or(false, |n| casemapping.equals(n, ignored))
+                },
+                (&IgnoreTarget::Mask(_), &None) => false,
+            })
+            .map(|e| e.ignore.scope)
+            .max();
+    }
+}
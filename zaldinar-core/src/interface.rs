@@ -1,11 +1,23 @@
 use std::borrow::{Borrow, Cow};
+use std::panic;
 use std::sync;
 use std::sync::mpsc;
 use std::ops;
+use std::time;
 
-use regex;
+use serde;
 
-use errors::ThrowInitError;
+use errors::{ThrowInitError, ThrowRuntimeError};
+use permissions::{self, ADMIN_PERMISSION};
+use ignore;
+use channel_settings;
+use aliases;
+use storage;
+use scheduler;
+use queries::{self, QueryError};
+use breaker;
+use bus;
+use commands;
 use client;
 use events;
 use irc;
@@ -14,20 +26,42 @@ use irc;
 pub struct IrcInterface {
     data_out: mpsc::Sender<Option<String>>,
     pub client: client::Client,
-    admins: sync::Arc<Vec<regex::Regex>>,
+    permissions: sync::Arc<permissions::Permissions>,
+    ignores: sync::Arc<sync::RwLock<ignore::IgnoreList>>,
+    channel_settings: sync::Arc<sync::RwLock<channel_settings::ChannelSettings>>,
+    aliases: sync::Arc<sync::RwLock<aliases::AliasTable>>,
+    storage: sync::Arc<storage::Storage>,
+    scheduler: sync::Arc<scheduler::Scheduler>,
+    queries: sync::Arc<sync::Mutex<queries::PendingQueries>>,
+    circuit_breaker: sync::Arc<sync::Mutex<breaker::CircuitBreaker>>,
+    bus: sync::Arc<bus::EventBus>,
 }
 
 impl IrcInterface {
     pub fn new(data_out: mpsc::Sender<Option<String>>, client: client::Client)
             -> Result<IrcInterface, ThrowInitError> {
-        let mut admins = Vec::new();
-        for admin_str in client.admins.iter() {
-            admins.push(throw!(regex::Regex::new(&format!("^{}$", &admin_str))));
-        }
+        let permissions = up!(permissions::Permissions::from_config(client.config()));
+        let ignores = up!(ignore::IgnoreList::load(&client.config().data_path("ignores.json")));
+        let channel_settings = up!(channel_settings::ChannelSettings::load(client.config(),
+            &client.config().data_path("channel_settings.json")));
+        let aliases = up!(aliases::AliasTable::load(client.config(),
+            &client.config().data_path("aliases.json")));
+        let storage = sync::Arc::new(storage::Storage::new(client.config().data_path("plugins")));
+        client.plugins().read().unwrap().set_storage(storage.clone());
+        let scheduler = up!(scheduler::Scheduler::load(&client.config().data_path("jobs.json")));
+        let circuit_breaker = breaker::CircuitBreaker::new(&client.config().circuit_breaker);
         let interface = IrcInterface {
             data_out: data_out,
             client: client,
-            admins: sync::Arc::new(admins),
+            permissions: sync::Arc::new(permissions),
+            ignores: sync::Arc::new(sync::RwLock::new(ignores)),
+            channel_settings: sync::Arc::new(sync::RwLock::new(channel_settings)),
+            aliases: sync::Arc::new(sync::RwLock::new(aliases)),
+            storage: storage,
+            scheduler: sync::Arc::new(scheduler),
+            queries: sync::Arc::new(sync::Mutex::new(queries::PendingQueries::new())),
+            circuit_breaker: sync::Arc::new(sync::Mutex::new(circuit_breaker)),
+            bus: sync::Arc::new(bus::EventBus::new()),
         };
         return Ok(interface);
     }
@@ -96,6 +130,7 @@ impl IrcInterface {
     }
 
     pub fn quit<T: Borrow<str>>(&self, message: Option<T>, restart: client::ExecutingState) {
+        self.run_lifecycle_hooks(events::Lifecycle::Shutdown);
         let line = match message {
             Some(m) => format!("QUIT :{}", m.borrow()),
             None => format!("QUIT"),
@@ -110,31 +145,270 @@ impl IrcInterface {
         }
     }
 
+    /// Runs the hooks plugins registered for `stage`, in the order they were registered. They
+    /// run on the calling thread, so that they have finished when this returns.
+    pub fn run_lifecycle_hooks(&self, stage: events::Lifecycle) {
+        let hooks = {
+            let plugins = self.client.plugins().read().unwrap();
+            plugins.lifecycle_hooks.get(&stage).map_or(Vec::new(), |hooks| hooks.clone())
+        };
+        let event = events::LifecycleEvent::new(self, stage);
+        for hook in hooks {
+            let plugin = hook.plugin.as_ref().map(|s| &**s);
+            if plugin.map_or(false, |plugin| self.plugin_disabled(plugin)) {
+                continue;
+            }
+            let description = format!("{:?} hook from plugin {}", stage, hook.plugin_name());
+            self.run_plugin_code(plugin, &description, || (hook.listener)(&event));
+        }
+    }
+
+    /// Runs `f`, code from `plugin`, catching any panic so that it counts towards disabling the
+    /// plugin rather than taking down the thread. `description` says what is being run and
+    /// which plugin it is from, for the log. Returns None if `f` panicked.
+    ///
+    /// Plugin code is treated as unwind safe. A panic while holding a lock, such as the one from
+    /// `state()`, poisons the lock, and every later use of it panics as well, so plugins must
+    /// not panic while holding locks.
+    pub fn run_plugin_code<T, F>(&self, plugin: Option<&str>, description: &str, f: F)
+            -> Option<T> where F: FnOnce() -> T {
+        let payload = match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+            Ok(v) => return Some(v),
+            Err(payload) => payload,
+        };
+        error!("The {} panicked: {}", description, breaker::panic_message(&payload));
+        if let Some(plugin) = plugin {
+            if self.circuit_breaker.lock().unwrap().record_failure(plugin) {
+                warn!("Disabling plugin {} after it failed repeatedly.", plugin);
+            }
+        }
+        return None;
+    }
+
+    /// Checks whether the circuit breaker has disabled `plugin`.
+    pub fn plugin_disabled(&self, plugin: &str) -> bool {
+        return self.circuit_breaker.lock().unwrap().disabled_for(plugin).is_some();
+    }
+
+    pub fn permissions(&self) -> &permissions::Permissions {
+        return &self.permissions;
+    }
+
+    /// Checks whether the user with the given mask has `permission` in `channel`. If `channel`
+    /// is None, only roles granted in every channel are considered.
+    pub fn has_permission(&self, mask: &events::IrcMask, channel: Option<&str>,
+            permission: &str) -> bool {
+        let state = self.client.state().read().unwrap();
+        return self.permissions.has_permission(&state, mask.mask(), mask.nick(), channel,
+            permission);
+    }
+
+    /// Gets the names of every role the user with the given mask holds in `channel`, or only
+    /// those held in all channels if it is None.
+    pub fn roles(&self, mask: &events::IrcMask, channel: Option<&str>) -> Vec<&str> {
+        let state = self.client.state().read().unwrap();
+        return self.permissions.roles(&state, mask.mask(), mask.nick(), channel);
+    }
+
+    pub fn has_internal_permission(&self, mask: &irc::IrcMask, channel: Option<&str>,
+            permission: &str) -> bool {
+        let state = self.client.state().read().unwrap();
+        return self.permissions.has_permission(&state, mask.mask(), mask.nick(), channel,
+            permission);
+    }
+
+    /// Gets the permission required to use a command, taking `command_permissions` from the
+    /// configuration into account. Subcommands without a permission of their own use their
+    /// group's. Returns None if anyone may use the command.
+    pub fn command_permission<'a>(&'a self, plugins: &'a client::PluginRegister,
+            descriptor: &'a commands::CommandDescriptor) -> Option<&'a str> {
+        for descriptor in plugins.command_chain(descriptor) {
+            if let Some(permission) = self.permissions.command_override(&descriptor.name) {
+                return permission;
+            }
+            if let Some(ref permission) = descriptor.permission {
+                return Some(permission);
+            }
+        }
+        return None;
+    }
+
+    /// Checks whether the user with the given mask may use a command in `channel`.
+    pub fn can_use_command(&self, mask: &events::IrcMask, channel: Option<&str>,
+            plugins: &client::PluginRegister, descriptor: &commands::CommandDescriptor) -> bool {
+        match self.command_permission(plugins, descriptor) {
+            Some(permission) => self.has_permission(mask, channel, permission),
+            None => true,
+        }
+    }
+
+    pub fn channel_settings(&self) -> &sync::RwLock<channel_settings::ChannelSettings> {
+        return &self.channel_settings;
+    }
+
+    pub fn aliases(&self) -> &sync::RwLock<aliases::AliasTable> {
+        return &self.aliases;
+    }
+
+    /// Gets a persistent key-value store shared by every plugin which uses `namespace`. Each
+    /// plugin's own store comes from `PluginRegister::storage`; this is only for data plugins
+    /// mean to share.
+    pub fn shared_storage(&self, namespace: &str)
+            -> Result<sync::Arc<sync::Mutex<storage::Store>>, ThrowRuntimeError> {
+        return self.storage.shared(namespace);
+    }
+
+    /// Gets the scheduler, for running job handlers later or repeatedly.
+    pub fn scheduler(&self) -> &scheduler::Scheduler {
+        return &self.scheduler;
+    }
+
+    pub fn circuit_breaker(&self) -> &sync::Mutex<breaker::CircuitBreaker> {
+        return &self.circuit_breaker;
+    }
+
+    pub fn bus(&self) -> &bus::EventBus {
+        return &self.bus;
+    }
+
+    /// Publishes an event on `topic` for other plugins' subscribers. This returns straight away,
+    /// and the subscribers run later on the worker threads. Fails if too many events are already
+    /// waiting to be delivered.
+    pub fn publish<T>(&self, topic: &str, payload: &T) -> Result<(), ThrowRuntimeError>
+            where T: serde::Serialize {
+        return self.bus.publish(topic, payload);
+    }
+
+    /// Passes a message from the server to the query it is a reply to, if any.
+    ///
+    /// This is called by the dispatch thread for every message, and shouldn't be called from
+    /// plugins.
+    pub fn feed_queries(&self, message: &irc::IrcMessage) {
+        let casemapping = self.client.state().read().unwrap().support.casemapping;
+        self.queries.lock().unwrap().feed(message, casemapping);
+    }
+
+    /// Sends a query to the server and waits up to `timeout` for the whole reply.
+    ///
+    /// This blocks until the reply arrives, so it must only be used from plugins, which run on
+    /// the worker threads, and never from the dispatch thread which reads the reply.
+    pub fn query(&self, spec: queries::QuerySpec, timeout: time::Duration)
+            -> queries::QueryResult {
+        let labeled = {
+            let state = self.client.state().read().unwrap();
+            state.has_capability("labeled-response") && state.has_capability("batch")
+        };
+        let (reply_to, replies) = mpsc::channel();
+        let (id, line) = self.queries.lock().unwrap().add(spec, labeled, reply_to);
+        if let Err(_) = self.data_out.send(Some(line)) {
+            self.queries.lock().unwrap().remove(id);
+            return Err(QueryError::Disconnected);
+        }
+        return match replies.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                self.queries.lock().unwrap().remove(id);
+                Err(QueryError::Timeout)
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(QueryError::Disconnected),
+        };
+    }
+
+    /// Looks up a user with WHOIS.
+    pub fn whois(&self, nick: &str, timeout: time::Duration)
+            -> Result<queries::WhoisInfo, QueryError> {
+        let lines = try!(self.query(queries::whois_spec(nick), timeout));
+        return queries::parse_whois(&lines);
+    }
+
+    /// Lists the users matching a mask or in a channel with WHO.
+    pub fn who(&self, mask: &str, timeout: time::Duration)
+            -> Result<Vec<queries::WhoEntry>, QueryError> {
+        let spec = {
+            let state = self.client.state().read().unwrap();
+            queries::who_spec(mask, &state.support)
+        };
+        let lines = try!(self.query(spec, timeout));
+        return Ok(queries::parse_who(&lines));
+    }
+
+    /// Lists the users in a channel with NAMES, each with any prefix symbols such as `@`.
+    pub fn names(&self, channel: &str, timeout: time::Duration)
+            -> Result<Vec<String>, QueryError> {
+        let lines = try!(self.query(queries::names_spec(channel), timeout));
+        return Ok(queries::parse_names(&lines));
+    }
+
+    /// Lists the entries of a channel list mode: `b` for bans, `e` for ban exceptions or `I` for
+    /// invite exceptions.
+    pub fn mode_list(&self, channel: &str, mode: char, timeout: time::Duration)
+            -> Result<Vec<queries::ListEntry>, QueryError> {
+        let lines = try!(self.query(try!(queries::mode_list_spec(channel, mode)), timeout));
+        return Ok(queries::parse_mode_list(&lines));
+    }
+
+    /// Checks whether a command is enabled in `channel` by `channel_settings`.
+    pub fn command_enabled(&self, channel: &str, plugins: &client::PluginRegister,
+            descriptor: &commands::CommandDescriptor) -> bool {
+        let casemapping = self.client.state().read().unwrap().support.casemapping;
+        let settings = self.channel_settings.read().unwrap();
+        return settings.is_enabled(channel, &plugins.command_chain(descriptor), casemapping);
+    }
+
+    /// Checks whether the listeners of `plugin` run for messages in `channel`, by
+    /// `channel_settings`.
+    pub fn plugin_enabled(&self, channel: &str, plugin: &str) -> bool {
+        let casemapping = self.client.state().read().unwrap().support.casemapping;
+        let settings = self.channel_settings.read().unwrap();
+        return settings.plugin_enabled(channel, plugin, casemapping);
+    }
+
+    /// Gets the services account `nick` is logged in to, if it is known. Returns `Some(None)` if
+    /// the user is known not to be logged in.
+    pub fn account(&self, nick: &str) -> Option<Option<String>> {
+        let state = self.client.state().read().unwrap();
+        return state.known_account(nick, self.permissions.account_cache())
+            .map(|account| account.map(|s| s.to_string()));
+    }
+
+    pub fn ignores(&self) -> &sync::RwLock<ignore::IgnoreList> {
+        return &self.ignores;
+    }
+
+    /// Finds what is ignored from the user with the given mask, if anything.
+    ///
+    /// Users with the admin permission are never ignored, so that an ignore matching them can't
+    /// stop them from removing it.
+    pub fn ignore_scope(&self, mask: &irc::IrcMask) -> Option<ignore::IgnoreScope> {
+        let state = self.client.state().read().unwrap();
+        if self.permissions.has_permission(&state, mask.mask(), mask.nick(), None,
+                ADMIN_PERMISSION) {
+            return None;
+        }
+        let account = match mask.nick()
+                .and_then(|n| state.known_account(n, self.permissions.account_cache())) {
+            Some(Some(account)) => Some(account),
+            _ => None,
+        };
+        let ignores = self.ignores.read().unwrap();
+        return ignores.check(&state, mask.mask(), mask.nick(), account);
+    }
+
     pub fn is_admin(&self, event: &events::CommandEvent) -> bool {
-        if self.is_mask_admin(event.mask()) {
+        if self.has_permission(event.mask(), Some(event.channel()), ADMIN_PERMISSION) {
             return true;
         } else {
-            self.send_message(event.channel(), "Permission denied");
+            event.reply_error("Permission denied");
             return false;
         }
     }
 
     pub fn is_mask_admin(&self, mask: &events::IrcMask) -> bool {
-        if let Some(mask_internal) = mask.mask() {
-            if self.admins.iter().any(|r| r.is_match(mask_internal)) {
-                return true;
-            }
-        }
-        return false;
+        return self.has_permission(mask, None, ADMIN_PERMISSION);
     }
 
     pub fn is_internal_mask_admin(&self, mask: &irc::IrcMask) -> bool {
-        if let Some(mask_internal) = mask.mask() {
-            if self.admins.iter().any(|r| r.is_match(mask_internal)) {
-                return true;
-            }
-        }
-        return false;
+        return self.has_internal_permission(mask, None, ADMIN_PERMISSION);
     }
 }
 
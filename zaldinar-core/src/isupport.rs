@@ -0,0 +1,124 @@
+use masks::CaseMapping;
+use modes::ModeKind;
+
+/// Server features advertised through RPL_ISUPPORT (numeric 005).
+///
+/// The defaults are those given by RFC 1459, and are used until the server tells us otherwise.
+#[derive(Clone, Debug)]
+pub struct ServerSupport {
+    /// Type A modes from CHANMODES: modes which add or remove an entry to or from a list.
+    pub list_modes: String,
+    /// Type B modes from CHANMODES: modes which always take a parameter.
+    pub always_parameter_modes: String,
+    /// Type C modes from CHANMODES: modes which only take a parameter when being set.
+    pub set_only_parameter_modes: String,
+    /// Type D modes from CHANMODES: modes which never take a parameter.
+    pub flag_modes: String,
+    /// Prefix modes from PREFIX, as `(mode, symbol)` pairs ordered from highest to lowest rank.
+    pub prefixes: Vec<(char, char)>,
+    /// Characters which channel names may start with, from CHANTYPES.
+    pub channel_types: String,
+    /// Status prefixes which can be put before a channel name to message only users with that
+    /// status or higher, from STATUSMSG.
+    pub statusmsg: String,
+    /// How nicks and channel names are compared, from CASEMAPPING.
+    pub casemapping: CaseMapping,
+}
+
+impl Default for ServerSupport {
+    fn default() -> ServerSupport {
+        return ServerSupport {
+            list_modes: "b".to_string(),
+            always_parameter_modes: "k".to_string(),
+            set_only_parameter_modes: "l".to_string(),
+            flag_modes: "imnpst".to_string(),
+            prefixes: vec![('o', '@'), ('v', '+')],
+            channel_types: "#&".to_string(),
+            statusmsg: String::new(),
+            casemapping: CaseMapping::default(),
+        };
+    }
+}
+
+impl ServerSupport {
+    /// Applies the tokens of a single RPL_ISUPPORT line, excluding the leading target nick.
+    ///
+    /// Parsing stops at the trailing ":are supported by this server" text.
+    pub fn apply_tokens<S: AsRef<str>>(&mut self, tokens: &[S]) {
+        for token in tokens {
+            let token = token.as_ref();
+            if token.starts_with(":") {
+                break;
+            }
+            let mut split = token.splitn(2, '=');
+            let key = split.next().unwrap_or("");
+            let value = split.next().unwrap_or("");
+            match key {
+                "CHANMODES" => self.apply_chanmodes(value),
+                "PREFIX" => self.apply_prefix(value),
+                "CHANTYPES" => self.channel_types = value.to_string(),
+                "STATUSMSG" => self.statusmsg = value.to_string(),
+                "CASEMAPPING" => match CaseMapping::from_name(value) {
+                    Some(casemapping) => self.casemapping = casemapping,
+                    None => warn!("Unknown CASEMAPPING `{}`, keeping {:?}.", value,
+                        self.casemapping),
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn apply_chanmodes(&mut self, value: &str) {
+        let mut groups = value.split(',');
+        self.list_modes = groups.next().unwrap_or("").to_string();
+        self.always_parameter_modes = groups.next().unwrap_or("").to_string();
+        self.set_only_parameter_modes = groups.next().unwrap_or("").to_string();
+        self.flag_modes = groups.next().unwrap_or("").to_string();
+    }
+
+    fn apply_prefix(&mut self, value: &str) {
+        // Format is `(modes)symbols`, for example `(qaohv)~&@%+`.
+        self.prefixes.clear();
+        if !value.starts_with("(") {
+            return;
+        }
+        if let Some(close) = value.find(')') {
+            let modes = &value[1..close];
+            let symbols = &value[close + 1..];
+            self.prefixes.extend(modes.chars().zip(symbols.chars()));
+        }
+    }
+
+    /// Finds which kind of channel mode `mode` is. Unknown modes are treated as flags.
+    pub fn mode_kind(&self, mode: char) -> ModeKind {
+        if self.prefixes.iter().any(|&(m, _)| m == mode) {
+            ModeKind::Prefix
+        } else if self.list_modes.contains(mode) {
+            ModeKind::List
+        } else if self.always_parameter_modes.contains(mode) {
+            ModeKind::AlwaysParameter
+        } else if self.set_only_parameter_modes.contains(mode) {
+            ModeKind::SetOnlyParameter
+        } else {
+            ModeKind::Flag
+        }
+    }
+
+    /// Gets the prefix mode represented by a NAMES symbol, such as `o` for `@`.
+    pub fn prefix_mode(&self, symbol: char) -> Option<char> {
+        self.prefixes.iter().find(|&&(_, s)| s == symbol).map(|&(m, _)| m)
+    }
+
+    /// Gets the rank of a prefix mode, where 0 is the highest rank.
+    pub fn prefix_rank(&self, mode: char) -> Option<usize> {
+        self.prefixes.iter().position(|&(m, _)| m == mode)
+    }
+
+    /// Checks whether `target` names a channel rather than a user.
+    pub fn is_channel(&self, target: &str) -> bool {
+        match target.chars().next() {
+            Some(c) => self.channel_types.contains(c),
+            None => false,
+        }
+    }
+}
@@ -16,12 +16,31 @@ pub use events::{
     MessageEvent,
     CommandEvent,
     CtcpEvent,
+    JobEvent,
+    LifecycleEvent,
 };
 
 pub mod errors;
 pub mod config;
 pub mod interface;
 pub mod client;
+pub mod commands;
+pub mod args;
 pub mod events;
+pub mod isupport;
+pub mod modes;
+pub mod masks;
+pub mod permissions;
+pub mod duration;
+pub mod data;
+pub mod ignore;
+pub mod channel_settings;
+pub mod aliases;
+pub mod storage;
+pub mod cron;
+pub mod scheduler;
+pub mod queries;
+pub mod breaker;
+pub mod bus;
 
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use regex;
+
+/// Masks starting with this are regexes rather than globs.
+pub const REGEX_PREFIX: &'static str = "re:";
+
+/// How the server compares nicks and channel names case insensitively, from ISUPPORT's
+/// CASEMAPPING.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CaseMapping {
+    /// Only `A-Z` and `a-z` are equivalent.
+    Ascii,
+    /// Like Ascii, but `[]\~` are also the uppercase forms of `{}|^`. This is the default.
+    Rfc1459,
+    /// Like Rfc1459, but without `~` and `^`.
+    StrictRfc1459,
+}
+
+impl Default for CaseMapping {
+    fn default() -> CaseMapping {
+        CaseMapping::Rfc1459
+    }
+}
+
+impl CaseMapping {
+    /// Gets the casemapping with the given CASEMAPPING name, or None if it's unknown.
+    pub fn from_name(name: &str) -> Option<CaseMapping> {
+        match name {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            _ => None,
+        }
+    }
+
+    pub fn to_lower_char(self, c: char) -> char {
+        match (self, c) {
+            (_, 'A'...'Z') => ((c as u8) + (b'a' - b'A')) as char,
+            (CaseMapping::Rfc1459, '[') | (CaseMapping::StrictRfc1459, '[') => '{',
+            (CaseMapping::Rfc1459, ']') | (CaseMapping::StrictRfc1459, ']') => '}',
+            (CaseMapping::Rfc1459, '\\') | (CaseMapping::StrictRfc1459, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c,
+        }
+    }
+
+    pub fn to_lower(self, s: &str) -> String {
+        s.chars().map(|c| self.to_lower_char(c)).collect()
+    }
+
+    /// Compares two nicks or channel names case insensitively.
+    pub fn equals(self, a: &str, b: &str) -> bool {
+        a.len() == b.len() && a.chars().zip(b.chars())
+            .all(|(x, y)| self.to_lower_char(x) == self.to_lower_char(y))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum GlobToken {
+    /// `*`
+    AnyMany,
+    /// `?`
+    AnyOne,
+    Literal(char),
+}
+
+/// A mask for matching users' `nick!user@host`.
+///
+/// By default masks are IRC-style globs, where `*` matches any number of characters, `?`
+/// matches exactly one character, and `\` makes the next character literal. Globs are matched
+/// case insensitively using the server's casemapping.
+///
+/// Masks starting with `re:` are instead regexes which must match the whole subject.
+#[derive(Clone)]
+pub struct Mask(MaskKind);
+
+#[derive(Clone)]
+enum MaskKind {
+    Glob(Vec<GlobToken>),
+    Regex(regex::Regex),
+}
+
+impl Mask {
+    pub fn parse(pattern: &str) -> Result<Mask, regex::Error> {
+        if pattern.starts_with(REGEX_PREFIX) {
+            let regex = try!(regex::Regex::new(&format!("^(?:{})$",
+                &pattern[REGEX_PREFIX.len()..])));
+            return Ok(Mask(MaskKind::Regex(regex)));
+        }
+
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                '*' => GlobToken::AnyMany,
+                '?' => GlobToken::AnyOne,
+                '\\' => match chars.next() {
+                    Some(escaped) => GlobToken::Literal(escaped),
+                    None => GlobToken::Literal('\\'),
+                },
+                other => GlobToken::Literal(other),
+            });
+        }
+        return Ok(Mask(MaskKind::Glob(tokens)));
+    }
+
+    pub fn matches(&self, subject: &str, casemapping: CaseMapping) -> bool {
+        match self.0 {
+            MaskKind::Glob(ref tokens) => {
+                let subject = subject.chars().collect::<Vec<char>>();
+                glob_matches(tokens, &subject, casemapping)
+            },
+            MaskKind::Regex(ref regex) => regex.is_match(subject),
+        }
+    }
+}
+
+impl fmt::Debug for Mask {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.0 {
+            MaskKind::Glob(ref tokens) => {
+                for token in tokens {
+                    match token {
+                        &GlobToken::AnyMany => try!(fmt.write_str("*")),
+                        &GlobToken::AnyOne => try!(fmt.write_str("?")),
+                        &GlobToken::Literal(c) => {
+                            if c == '*' || c == '?' || c == '\\' {
+                                try!(fmt.write_str("\\"));
+                            }
+                            try!(write!(fmt, "{}", c));
+                        },
+                    }
+                }
+                Ok(())
+            },
+            MaskKind::Regex(ref regex) => write!(fmt, "{}{}", REGEX_PREFIX, regex.as_str()),
+        }
+    }
+}
+
+/// Matches glob tokens against a subject, backtracking to the most recent `*` on mismatch.
+fn glob_matches(pattern: &[GlobToken], subject: &[char], casemapping: CaseMapping) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+    // Position in the pattern just after the last `*` seen, and the subject position it was
+    // matched against.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < subject.len() {
+        let token_matches = match pattern.get(p) {
+            Some(&GlobToken::AnyMany) => {
+                p += 1;
+                backtrack = Some((p, s));
+                continue;
+            },
+            Some(&GlobToken::AnyOne) => true,
+            Some(&GlobToken::Literal(c)) => {
+                casemapping.to_lower_char(c) == casemapping.to_lower_char(subject[s])
+            },
+            None => false,
+        };
+        if token_matches {
+            p += 1;
+            s += 1;
+        } else if let Some((star_p, star_s)) = backtrack {
+            // Let the last `*` swallow one more character and try again.
+            p = star_p;
+            s = star_s + 1;
+            backtrack = Some((star_p, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+
+    // Any remaining pattern must be all `*` to match the empty remainder of the subject.
+    return pattern[p..].iter().all(|t| match t {
+        &GlobToken::AnyMany => true,
+        _ => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CaseMapping, Mask};
+
+    fn matches(pattern: &str, subject: &str) -> bool {
+        Mask::parse(pattern).unwrap().matches(subject, CaseMapping::default())
+    }
+
+    #[test]
+    fn globs() {
+        assert!(matches("Dabo!*@me.dabo.guru", "dabo!~d@me.dabo.guru"));
+        assert!(!matches("Dabo!*@me.dabo.guru", "dabo!~d@meXdabo.guru"));
+        assert!(matches("*!*@*.example.com", "a!b@c.d.example.com"));
+        assert!(matches("a?c*", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*a*b", "xxaxxbxb"));
+        assert!(!matches("*a*b", "xxaxxbxc"));
+    }
+
+    #[test]
+    fn escaped_globs() {
+        assert!(matches("a\\*b", "a*b"));
+        assert!(!matches("a\\*b", "axb"));
+        assert!(matches("a\\?b", "a?b"));
+        assert!(!matches("a\\?b", "axb"));
+    }
+
+    #[test]
+    fn globs_use_casemapping() {
+        assert!(matches("[x]!*@*", "{X}!a@b"));
+        let mask = Mask::parse("[x]!*@*").unwrap();
+        assert!(!mask.matches("{X}!a@b", CaseMapping::Ascii));
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(matches("re:Dabo!.*@host", "Dabo!x@host"));
+        // Regexes must match the whole mask.
+        assert!(!matches("re:Dabo", "Dabo!x@host"));
+        assert!(Mask::parse("re:(").is_err());
+    }
+
+    #[test]
+    fn casemappings() {
+        assert_eq!(CaseMapping::from_name("strict-rfc1459"), Some(CaseMapping::StrictRfc1459));
+        assert_eq!(CaseMapping::from_name("unknown"), None);
+        assert!(CaseMapping::Ascii.equals("#A", "#a"));
+        assert!(!CaseMapping::Ascii.equals("[", "{"));
+        assert!(CaseMapping::Rfc1459.equals("Nick[]\\~", "nick{}|^"));
+        assert!(CaseMapping::StrictRfc1459.equals("Nick[]\\", "nick{}|"));
+        assert!(!CaseMapping::StrictRfc1459.equals("~", "^"));
+        assert_eq!(CaseMapping::Rfc1459.to_lower("#Chan[1]"), "#chan{1}");
+    }
+}
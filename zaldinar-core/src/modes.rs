@@ -0,0 +1,138 @@
+use std::fmt;
+
+use isupport::ServerSupport;
+
+/// The kind of a channel mode, which decides when the mode takes a parameter.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ModeKind {
+    /// A list mode, such as bans (`b`). Always takes a mask, except when the list is requested.
+    List,
+    /// A mode which always takes a parameter, such as the channel key (`k`).
+    AlwaysParameter,
+    /// A mode which only takes a parameter when set, such as the user limit (`l`).
+    SetOnlyParameter,
+    /// A mode which never takes a parameter, such as moderated (`m`).
+    Flag,
+    /// A mode giving a user a status in the channel, such as op (`o`). Always takes a nick.
+    Prefix,
+}
+
+/// A single mode being set or unset, such as `+o nick`, `-b mask` or `+k key`.
+#[derive(Clone, Debug)]
+pub struct ModeChange {
+    /// true if the mode is being set, false if it is being unset.
+    pub adding: bool,
+    pub mode: char,
+    pub kind: ModeKind,
+    pub argument: Option<String>,
+}
+
+impl fmt::Display for ModeChange {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(fmt, "{}{}", if self.adding { '+' } else { '-' }, self.mode));
+        if let Some(ref argument) = self.argument {
+            try!(write!(fmt, " {}", argument));
+        }
+        Ok(())
+    }
+}
+
+/// Removes the `:` marking a trailing IRC argument, if there is one.
+fn strip_trailing_marker(arg: &str) -> &str {
+    if arg.starts_with(":") {
+        &arg[1..]
+    } else {
+        arg
+    }
+}
+
+/// Parses the arguments of a channel MODE line (everything after the channel name), assigning
+/// parameters to modes using the server's CHANMODES and PREFIX support.
+pub fn parse_channel_modes<S: AsRef<str>>(support: &ServerSupport, args: &[S])
+        -> Vec<ModeChange> {
+    return parse_modes(args, |mode| support.mode_kind(mode));
+}
+
+/// Parses the arguments of a user MODE line (everything after the nick). User modes never take
+/// parameters.
+pub fn parse_user_modes<S: AsRef<str>>(args: &[S]) -> Vec<ModeChange> {
+    return parse_modes(args, |_| ModeKind::Flag);
+}
+
+fn parse_modes<S, F>(args: &[S], kind_of: F) -> Vec<ModeChange>
+        where S: AsRef<str>, F: Fn(char) -> ModeKind {
+    let mut changes = Vec::new();
+    let mode_string = match args.first() {
+        Some(s) => strip_trailing_marker(s.as_ref()),
+        None => return changes,
+    };
+    let mut parameters = args[1..].iter().map(|s| strip_trailing_marker(s.as_ref()));
+    let mut adding = true;
+
+    for c in mode_string.chars() {
+        match c {
+            '+' => adding = true,
+            '-' => adding = false,
+            mode => {
+                let kind = kind_of(mode);
+                let takes_parameter = match kind {
+                    ModeKind::List | ModeKind::AlwaysParameter | ModeKind::Prefix => true,
+                    ModeKind::SetOnlyParameter => adding,
+                    ModeKind::Flag => false,
+                };
+                let argument = if takes_parameter {
+                    parameters.next().map(|s| s.to_string())
+                } else {
+                    None
+                };
+                changes.push(ModeChange {
+                    adding: adding,
+                    mode: mode,
+                    kind: kind,
+                    argument: argument,
+                });
+            },
+        }
+    }
+
+    return changes;
+}
+
+#[cfg(test)]
+mod tests {
+    use isupport::ServerSupport;
+    use super::{parse_channel_modes, parse_user_modes, ModeChange, ModeKind};
+
+    fn strings(changes: &[ModeChange]) -> Vec<String> {
+        changes.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn channel_modes_take_parameters_by_kind() {
+        let mut support = ServerSupport::default();
+        support.apply_tokens(&["CHANMODES=beI,k,l,imnpst", "PREFIX=(qaohv)~&@%+"]);
+        let changes = parse_channel_modes(&support,
+            &["+ob-lk+bl", "nick", "*!*@x", "key", "ban!*@*", ":10"]);
+        assert_eq!(strings(&changes),
+            vec!["+o nick", "+b *!*@x", "-l", "-k key", "+b ban!*@*", "+l 10"]);
+        assert_eq!(changes[0].kind, ModeKind::Prefix);
+        assert_eq!(changes[2].kind, ModeKind::SetOnlyParameter);
+        assert_eq!(changes[5].argument, Some("10".to_string()));
+    }
+
+    #[test]
+    fn channel_modes_missing_parameters() {
+        let support = ServerSupport::default();
+        // A list mode without a mask is a request for the list.
+        let changes = parse_channel_modes(&support, &["+bm"]);
+        assert_eq!(strings(&changes), vec!["+b", "+m"]);
+        assert_eq!(changes[0].argument, None);
+    }
+
+    #[test]
+    fn user_modes_never_take_parameters() {
+        let changes = parse_user_modes(&[":+iw-x", "extra"]);
+        assert_eq!(strings(&changes), vec!["+i", "+w", "-x"]);
+        assert!(changes.iter().all(|c| c.kind == ModeKind::Flag));
+    }
+}
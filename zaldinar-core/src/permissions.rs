@@ -0,0 +1,237 @@
+use std::ascii::AsciiExt;
+use std::collections;
+use std::time;
+
+use client::ClientState;
+use commands;
+use config;
+use errors::ThrowInitError;
+use masks::{self, Mask};
+
+/// The permission required by commands registered with `register_admin_command`.
+pub const ADMIN_PERMISSION: &'static str = "admin";
+
+/// A compiled `RoleMemberConf`.
+struct Grant {
+    mask: Option<Mask>,
+    /// Lowercase account name.
+    account: Option<String>,
+    channel_status: Option<char>,
+    /// Channel names, or empty if this grant applies everywhere.
+    channels: Vec<String>,
+}
+
+impl Grant {
+    /// Whether this grant trusts a user's mask alone, which can be spoofed on networks without
+    /// cloaks.
+    fn identifies_by_mask_only(&self) -> bool {
+        self.account.is_none() && self.channel_status.is_none()
+    }
+
+    fn applies(&self, state: &ClientState, mask: Option<&str>, nick: Option<&str>,
+            account: Option<&str>, channel: Option<&str>) -> bool {
+        let casemapping = state.support.casemapping;
+        if !self.channels.is_empty() {
+            match channel {
+                Some(c) if self.channels.iter().any(|g| casemapping.equals(g, c)) => (),
+                _ => return false,
+            }
+        }
+        if let Some(ref matcher) = self.mask {
+            match mask {
+                Some(m) if matcher.matches(m, casemapping) => (),
+                _ => return false,
+            }
+        }
+        if let Some(ref required) = self.account {
+            match account {
+                Some(a) if a.eq_ignore_ascii_case(required) => (),
+                _ => return false,
+            }
+        }
+        if let Some(status) = self.channel_status {
+            match (channel, nick) {
+                (Some(c), Some(n)) if has_status(state, c, n, status) => (),
+                _ => return false,
+            }
+        }
+        return true;
+    }
+}
+
+/// Checks whether `nick` holds `status` or any higher prefix mode in `channel`.
+fn has_status(state: &ClientState, channel: &str, nick: &str, status: char) -> bool {
+    let required_rank = match state.support.prefix_rank(status) {
+        Some(v) => v,
+        None => return false,
+    };
+    let prefix_modes = match state.channel(channel).and_then(|c| c.user_modes(nick)) {
+        Some(v) => v,
+        None => return false,
+    };
+    return prefix_modes.iter().any(|m| match state.support.prefix_rank(*m) {
+        Some(rank) => rank <= required_rank,
+        None => false,
+    });
+}
+
+struct Role {
+    name: String,
+    /// Permissions of this role, including those of every role it inherits.
+    permissions: Vec<String>,
+    grants: Vec<Grant>,
+}
+
+impl Role {
+    fn grants_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| {
+            p == "*" || p == permission
+                || (p.ends_with(".*") && permission.starts_with(&p[..p.len() - 1]))
+        })
+    }
+}
+
+/// Roles and per-command permission requirements, compiled from the configuration.
+pub struct Permissions {
+    roles: Vec<Role>,
+    /// Whether grants identifying users only by mask are ignored.
+    require_account: bool,
+    /// How long accounts learned from WHOIS are trusted for.
+    account_cache: time::Duration,
+    /// Lowercase command path -> required permission, or an empty string for none.
+    command_permissions: collections::HashMap<String, String>,
+}
+
+impl Permissions {
+    pub fn from_config(config: &config::ClientConfiguration)
+            -> Result<Permissions, ThrowInitError> {
+        let mut roles = Vec::new();
+
+        if !config.admins.is_empty() {
+            let mut grants = Vec::new();
+            for admin_str in &config.admins {
+                if admin_str.contains(".*") && !admin_str.starts_with(masks::REGEX_PREFIX) {
+                    warn!("Admin mask `{}` looks like a regex, but masks are now globs. Prefix it \
+                        with `{}` to keep using it as a regex.", admin_str, masks::REGEX_PREFIX);
+                }
+                grants.push(Grant {
+                    mask: Some(throw!(Mask::parse(admin_str))),
+                    account: None,
+                    channel_status: None,
+                    channels: Vec::new(),
+                });
+            }
+            roles.push(Role {
+                name: "admins".to_string(),
+                permissions: vec!["*".to_string()],
+                grants: grants,
+            });
+        }
+
+        for role_conf in &config.roles {
+            let mut grants = Vec::new();
+            for member in &role_conf.members {
+                if member.mask.is_none() && member.account.is_none()
+                        && member.channel_status.is_none() {
+                    throw_new!(format!("A member of role `{}` has none of `mask`, `account` \
+                        or `channel_status`.", role_conf.name));
+                }
+                let mask = match member.mask {
+                    Some(ref m) => Some(throw!(Mask::parse(m))),
+                    None => None,
+                };
+                grants.push(Grant {
+                    mask: mask,
+                    account: member.account.as_ref().map(|a| a.to_ascii_lowercase()),
+                    channel_status: member.channel_status,
+                    channels: member.channels.clone(),
+                });
+            }
+
+            let mut permissions = Vec::new();
+            let mut visited = collections::HashSet::new();
+            throw!(collect_permissions(config, &role_conf.name, &mut visited, &mut permissions));
+
+            roles.push(Role {
+                name: role_conf.name.clone(),
+                permissions: permissions,
+                grants: grants,
+            });
+        }
+
+        let command_permissions = config.command_permissions.iter()
+            .map(|(command, permission)| (commands::command_path(command), permission.clone()))
+            .collect();
+
+        return Ok(Permissions {
+            roles: roles,
+            require_account: config.accounts.require_account,
+            account_cache: time::Duration::from_secs(config.accounts.cache_seconds),
+            command_permissions: command_permissions,
+        });
+    }
+
+    /// How long accounts learned from WHOIS are trusted for.
+    pub fn account_cache(&self) -> time::Duration {
+        return self.account_cache;
+    }
+
+    fn holds_role(&self, role: &Role, state: &ClientState, mask: Option<&str>,
+            nick: Option<&str>, channel: Option<&str>) -> bool {
+        let account = match nick.and_then(|n| state.known_account(n, self.account_cache)) {
+            Some(Some(account)) => Some(account),
+            _ => None,
+        };
+        return role.grants.iter()
+            .filter(|g| !(self.require_account && g.identifies_by_mask_only()))
+            .any(|g| g.applies(state, mask, nick, account, channel));
+    }
+
+    /// Gets the names of every role the user holds. If `channel` is None, only roles granted in
+    /// all channels are included.
+    pub fn roles(&self, state: &ClientState, mask: Option<&str>, nick: Option<&str>,
+            channel: Option<&str>) -> Vec<&str> {
+        return self.roles.iter()
+            .filter(|role| self.holds_role(role, state, mask, nick, channel))
+            .map(|role| &*role.name)
+            .collect();
+    }
+
+    /// Checks whether the user has `permission` through any of their roles. If `channel` is
+    /// None, only roles granted in all channels are considered.
+    pub fn has_permission(&self, state: &ClientState, mask: Option<&str>, nick: Option<&str>,
+            channel: Option<&str>, permission: &str) -> bool {
+        return self.roles.iter().any(|role| {
+            role.grants_permission(permission)
+                && self.holds_role(role, state, mask, nick, channel)
+        });
+    }
+
+    /// Gets the permission `command_permissions` in the configuration requires for `command`.
+    ///
+    /// Returns None if the configuration doesn't mention the command, and `Some(None)` if it
+    /// allows anyone to use it.
+    pub fn command_override(&self, command: &str) -> Option<Option<&str>> {
+        self.command_permissions.get(&commands::command_path(command)).map(|permission| {
+            if permission.is_empty() { None } else { Some(&**permission) }
+        })
+    }
+}
+
+/// Collects the permissions of `role_name` and every role it inherits into `permissions`.
+fn collect_permissions<'a>(config: &'a config::ClientConfiguration, role_name: &'a str,
+        visited: &mut collections::HashSet<&'a str>, permissions: &mut Vec<String>)
+        -> Result<(), String> {
+    if !visited.insert(role_name) {
+        return Ok(()); // already collected, or an inheritance cycle
+    }
+    let role = match config.roles.iter().find(|r| r.name == role_name) {
+        Some(v) => v,
+        None => return Err(format!("Unknown role `{}` in role inheritance.", role_name)),
+    };
+    permissions.extend(role.permissions.iter().cloned());
+    for parent in &role.inherits {
+        try!(collect_permissions(config, parent, visited, permissions));
+    }
+    return Ok(());
+}
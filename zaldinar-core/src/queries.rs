@@ -0,0 +1,461 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::sync::mpsc;
+use std::time;
+
+use irc;
+use isupport::ServerSupport;
+use masks::CaseMapping;
+
+/// Why a query didn't get an answer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum QueryError {
+    /// The server didn't finish replying in time.
+    Timeout,
+    /// The server replied with an error numeric, such as ERR_NOSUCHNICK. Holds its message.
+    Failed(String),
+    /// The query can't be sent, because the bot is shutting down.
+    Disconnected,
+    /// The query isn't something this server can answer, such as an unknown list mode.
+    Unsupported(String),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &QueryError::Timeout => fmt.write_str("The server didn't reply in time."),
+            &QueryError::Failed(ref message) => fmt.write_str(message),
+            &QueryError::Disconnected => fmt.write_str("Not connected."),
+            &QueryError::Unsupported(ref message) => fmt.write_str(message),
+        }
+    }
+}
+
+/// A command to send to the server, and how to recognise its replies.
+///
+/// Numerics normally start with our own nick, followed by the target they are about. Replies
+/// are matched on that target, unless labeled-response is available, in which case the server
+/// marks them for us.
+#[derive(Clone, Debug)]
+pub struct QuerySpec {
+    /// The line to send, such as `WHOIS somenick`.
+    pub line: String,
+    /// The nick, channel or mask replies must be about.
+    pub target: String,
+    /// Numerics making up the reply, with the index of their argument holding the target, or
+    /// None if it isn't checked.
+    pub replies: Vec<(&'static str, Option<usize>)>,
+    /// Numerics which finish the reply, with the target as their second argument.
+    pub end: Vec<&'static str>,
+    /// Numerics which mean the query failed, with the target as their second argument.
+    pub errors: Vec<&'static str>,
+}
+
+impl QuerySpec {
+    /// Checks whether `message` is part of the reply to this query, sent without a label.
+    fn matches(&self, message: &irc::IrcMessage, casemapping: CaseMapping) -> bool {
+        let index = if is_one_of(&self.end, &message.command)
+                || is_one_of(&self.errors, &message.command) {
+            Some(1)
+        } else {
+            match self.replies.iter().find(|&&(numeric, _)| numeric == message.command) {
+                Some(&(_, index)) => index,
+                None => return false,
+            }
+        };
+        return match index {
+            Some(index) => message.args.get(index)
+                .map_or(false, |arg| casemapping.equals(arg, &self.target)),
+            None => true,
+        };
+    }
+}
+
+/// The final outcome of a query: every message in the reply, or why there wasn't one.
+pub type QueryResult = Result<Vec<irc::IrcMessage>, QueryError>;
+
+struct PendingQuery {
+    id: u64,
+    spec: QuerySpec,
+    /// The label sent with the query, if labeled-response is in use.
+    label: Option<String>,
+    /// The batch the server opened for a labeled reply spanning several messages.
+    batch: Option<String>,
+    lines: Vec<irc::IrcMessage>,
+    reply_to: mpsc::Sender<QueryResult>,
+}
+
+/// Queries waiting on the server.
+///
+/// Every incoming message is passed to `feed` from the dispatch thread, while the plugins waiting
+/// on replies block in worker threads.
+pub struct PendingQueries {
+    next_id: u64,
+    pending: Vec<PendingQuery>,
+}
+
+impl PendingQueries {
+    pub fn new() -> PendingQueries {
+        return PendingQueries {
+            next_id: 1,
+            pending: Vec::new(),
+        };
+    }
+
+    /// Starts waiting for the reply to `spec`. Returns the query's id, and the line to send,
+    /// which carries a label if `labeled` is true.
+    pub fn add(&mut self, spec: QuerySpec, labeled: bool, reply_to: mpsc::Sender<QueryResult>)
+            -> (u64, String) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let label = if labeled { Some(format!("zq{}", id)) } else { None };
+        let line = match label {
+            Some(ref label) => format!("@label={} {}", label, spec.line),
+            None => spec.line.clone(),
+        };
+        self.pending.push(PendingQuery {
+            id: id,
+            spec: spec,
+            label: label,
+            batch: None,
+            lines: Vec::new(),
+            reply_to: reply_to,
+        });
+        return (id, line);
+    }
+
+    /// Stops waiting for a query, after it has timed out.
+    pub fn remove(&mut self, id: u64) {
+        self.pending.retain(|query| query.id != id);
+    }
+
+    /// Passes a message from the server to the query it is a reply to, if any.
+    pub fn feed(&mut self, message: &irc::IrcMessage, casemapping: CaseMapping) {
+        let is_batch = message.command.eq_ignore_ascii_case("BATCH");
+        let position = match message.tag("label") {
+            Some(label) => self.pending.iter()
+                .position(|query| query.label.as_ref().map_or(false, |l| l == label)),
+            None => match message.tag("batch") {
+                Some(batch) => self.pending.iter()
+                    .position(|query| query.batch.as_ref().map_or(false, |b| b == batch)),
+                // Batches are closed by `BATCH -<reference>`, which has no tags of its own.
+                None if is_batch => self.pending.iter().position(|query| {
+                    let reference = message.args.first().map(|s| s.trim_left_matches('-'));
+                    query.batch.is_some() && query.batch.as_ref().map(|s| &**s) == reference
+                }),
+                // The oldest unlabeled query the message fits, since the server answers in
+                // order.
+                None => self.pending.iter().position(|query| query.label.is_none()
+                    && query.spec.matches(message, casemapping)),
+            },
+        };
+        let index = match position {
+            Some(v) => v,
+            None => return,
+        };
+
+        let finished = {
+            let query = &mut self.pending[index];
+            if is_one_of(&query.spec.errors, &message.command) {
+                Some(Err(QueryError::Failed(trailing(message, 2))))
+            } else if query.label.is_some() && message.tag("label").is_some() {
+                if is_batch && message.args.first().map_or(false, |s| s.starts_with('+')) {
+                    query.batch = Some(message.args[0][1..].to_string());
+                    None
+                } else {
+                    // A labeled reply of a single message, or ACK for no reply at all.
+                    if !message.command.eq_ignore_ascii_case("ACK") {
+                        query.lines.push(message.clone());
+                    }
+                    Some(Ok(query.lines.split_off(0)))
+                }
+            } else if is_batch || (query.label.is_none()
+                    && is_one_of(&query.spec.end, &message.command)) {
+                Some(Ok(query.lines.split_off(0)))
+            } else {
+                query.lines.push(message.clone());
+                None
+            }
+        };
+        if let Some(result) = finished {
+            let query = self.pending.remove(index);
+            // The plugin may have given up waiting already.
+            let _ = query.reply_to.send(result);
+        }
+    }
+}
+
+fn is_one_of(numerics: &[&str], command: &str) -> bool {
+    numerics.iter().any(|numeric| *numeric == command)
+}
+
+/// Joins the arguments of `message` from `start` on, without the `:` marking the last one.
+fn trailing(message: &irc::IrcMessage, start: usize) -> String {
+    if message.args.len() <= start {
+        return String::new();
+    }
+    return message.args[start..].join(" ").trim_left_matches(':').to_string();
+}
+
+/// What WHOIS says about a user.
+#[derive(Clone, Debug, Default)]
+pub struct WhoisInfo {
+    pub nick: String,
+    pub user: String,
+    pub host: String,
+    pub real_name: String,
+    pub server: Option<String>,
+    /// The services account the user is logged in to.
+    pub account: Option<String>,
+    /// Channels the user is visibly in, with any prefix symbols such as `@`.
+    pub channels: Vec<String>,
+    pub away: Option<String>,
+    pub operator: bool,
+    pub idle: Option<time::Duration>,
+}
+
+pub fn whois_spec(nick: &str) -> QuerySpec {
+    return QuerySpec {
+        line: format!("WHOIS {}", nick),
+        target: nick.to_string(),
+        replies: vec![("301", Some(1)), ("311", Some(1)), ("312", Some(1)), ("313", Some(1)),
+            ("317", Some(1)), ("319", Some(1)), ("330", Some(1))],
+        end: vec!["318"],
+        errors: vec!["401", "402"],
+    };
+}
+
+pub fn parse_whois(lines: &[irc::IrcMessage]) -> Result<WhoisInfo, QueryError> {
+    let mut info = WhoisInfo::default();
+    for line in lines {
+        let args = &line.args;
+        match &*line.command {
+            // RPL_WHOISUSER: `<our nick> <nick> <user> <host> * :<real name>`
+            "311" if args.len() > 4 => {
+                info.nick = args[1].clone();
+                info.user = args[2].clone();
+                info.host = args[3].clone();
+                info.real_name = trailing(line, 5);
+            },
+            // RPL_WHOISSERVER: `<our nick> <nick> <server> :<server info>`
+            "312" if args.len() > 2 => info.server = Some(args[2].clone()),
+            // RPL_WHOISOPERATOR
+            "313" => info.operator = true,
+            // RPL_WHOISIDLE: `<our nick> <nick> <seconds> <signon> :seconds idle, signon time`
+            "317" if args.len() > 2 => {
+                info.idle = args[2].parse().ok().map(time::Duration::from_secs);
+            },
+            // RPL_WHOISCHANNELS: `<our nick> <nick> :[prefix]<channel> ...`
+            "319" => info.channels.extend(trailing(line, 2).split(' ')
+                .filter(|s| !s.is_empty()).map(|s| s.to_string())),
+            // RPL_WHOISACCOUNT: `<our nick> <nick> <account> :is logged in as`
+            "330" if args.len() > 2 => info.account = Some(args[2].clone()),
+            // RPL_AWAY: `<our nick> <nick> :<message>`
+            "301" => info.away = Some(trailing(line, 2)),
+            _ => (),
+        }
+    }
+    if info.nick.is_empty() {
+        return Err(QueryError::Failed("The server sent no WHOIS information.".to_string()));
+    }
+    return Ok(info);
+}
+
+/// One user matched by WHO.
+#[derive(Clone, Debug)]
+pub struct WhoEntry {
+    pub nick: String,
+    pub user: String,
+    pub host: String,
+    pub server: String,
+    /// A channel the user is in, or None if the server didn't pick one.
+    pub channel: Option<String>,
+    /// Flags such as `H` for here, `G` for gone, `*` for operators and prefix symbols.
+    pub flags: String,
+    pub real_name: String,
+}
+
+impl WhoEntry {
+    pub fn is_away(&self) -> bool {
+        self.flags.starts_with('G')
+    }
+}
+
+pub fn who_spec(mask: &str, support: &ServerSupport) -> QuerySpec {
+    // RPL_WHOREPLY holds a channel and a nick rather than the mask. Replies about a channel hold
+    // that channel, and replies about a single nick hold that nick, but replies to a mask with
+    // wildcards can't be told apart from others.
+    let target_index = if support.is_channel(mask) {
+        Some(1)
+    } else if !mask.contains(|c| "*?!@".contains(c)) {
+        Some(5)
+    } else {
+        None
+    };
+    return QuerySpec {
+        line: format!("WHO {}", mask),
+        target: mask.to_string(),
+        replies: vec![("352", target_index)],
+        end: vec!["315"],
+        errors: vec!["403"],
+    };
+}
+
+pub fn parse_who(lines: &[irc::IrcMessage]) -> Vec<WhoEntry> {
+    // RPL_WHOREPLY:
+    // `<our nick> <channel> <user> <host> <server> <nick> <flags> :<hopcount> <real name>`
+    return lines.iter().filter(|line| line.command == "352" && line.args.len() > 7)
+        .map(|line| WhoEntry {
+            nick: line.args[5].clone(),
+            user: line.args[2].clone(),
+            host: line.args[3].clone(),
+            server: line.args[4].clone(),
+            channel: if line.args[1] == "*" { None } else { Some(line.args[1].clone()) },
+            flags: line.args[6].clone(),
+            real_name: trailing(line, 8),
+        }).collect();
+}
+
+pub fn names_spec(channel: &str) -> QuerySpec {
+    return QuerySpec {
+        line: format!("NAMES {}", channel),
+        target: channel.to_string(),
+        replies: vec![("353", Some(2))],
+        end: vec!["366"],
+        errors: vec![],
+    };
+}
+
+/// Reads the nicks from a NAMES reply, each with any prefix symbols, such as `@` or `+`.
+pub fn parse_names(lines: &[irc::IrcMessage]) -> Vec<String> {
+    // RPL_NAMREPLY: `<our nick> <channel type> <channel> :[prefixes]nick [prefixes]nick ...`
+    return lines.iter().filter(|line| line.command == "353")
+        .flat_map(|line| trailing(line, 3).split(' ').filter(|name| !name.is_empty())
+            .map(|name| name.to_string()).collect::<Vec<String>>())
+        .collect();
+}
+
+/// One entry of a channel list mode, such as a ban.
+#[derive(Clone, Debug)]
+pub struct ListEntry {
+    pub mask: String,
+    pub set_by: Option<String>,
+    /// Unix time in seconds when the entry was set.
+    pub set_at: Option<u64>,
+}
+
+/// Gets the numerics used to list a mode's entries and end the list, for the list modes with
+/// standard numerics.
+fn list_numerics(mode: char) -> Option<(&'static str, &'static str)> {
+    return match mode {
+        'b' => Some(("367", "368")),
+        'e' => Some(("348", "349")),
+        'I' => Some(("346", "347")),
+        _ => None,
+    };
+}
+
+pub fn mode_list_spec(channel: &str, mode: char) -> Result<QuerySpec, QueryError> {
+    let (reply, end) = match list_numerics(mode) {
+        Some(v) => v,
+        None => return Err(QueryError::Unsupported(format!("Listing mode `{}` isn't supported.",
+            mode))),
+    };
+    return Ok(QuerySpec {
+        line: format!("MODE {} {}", channel, mode),
+        target: channel.to_string(),
+        replies: vec![(reply, Some(1))],
+        end: vec![end],
+        errors: vec!["403", "442", "472", "482"],
+    });
+}
+
+pub fn parse_mode_list(lines: &[irc::IrcMessage]) -> Vec<ListEntry> {
+    // RPL_BANLIST and the like: `<our nick> <channel> <mask> [<set by> <set at>]`
+    return lines.iter().filter(|line| line.args.len() > 2).map(|line| ListEntry {
+        mask: line.args[2].clone(),
+        set_by: line.args.get(3).map(|s| s.clone()),
+        set_at: line.args.get(4).and_then(|s| s.parse().ok()),
+    }).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use irc;
+    use isupport::ServerSupport;
+    use masks::CaseMapping;
+    use super::{who_spec, PendingQueries, QueryError};
+
+    fn message(command: &str, args: &[&str]) -> irc::IrcMessage {
+        return irc::IrcMessage {
+            command: command.to_string(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            mask: irc::IrcMask::Nonexistent,
+            ctcp: None,
+            channel: None,
+            tags: Vec::new(),
+        };
+    }
+
+    fn who_reply(channel: &str, nick: &str) -> irc::IrcMessage {
+        message("352", &["bot", channel, "user", "host", "server", nick, "H", ":0", "Real"])
+    }
+
+    #[test]
+    fn who_replies_match_their_channel() {
+        let support = ServerSupport::default();
+        let mut queries = PendingQueries::new();
+        let (first_to, first) = mpsc::channel();
+        let (second_to, second) = mpsc::channel();
+        queries.add(who_spec("#one", &support), false, first_to);
+        queries.add(who_spec("#Two", &support), false, second_to);
+
+        // Replies for the second channel aren't taken by the first query.
+        queries.feed(&who_reply("#two", "b"), CaseMapping::default());
+        queries.feed(&who_reply("#one", "a"), CaseMapping::default());
+        queries.feed(&message("315", &["bot", "#one", ":End of WHO"]), CaseMapping::default());
+        queries.feed(&message("315", &["bot", "#two", ":End of WHO"]), CaseMapping::default());
+
+        let first = first.try_recv().unwrap().unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].args[5], "a");
+        let second = second.try_recv().unwrap().unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].args[5], "b");
+    }
+
+    #[test]
+    fn who_replies_match_their_nick() {
+        let support = ServerSupport::default();
+        assert_eq!(who_spec("#chan", &support).replies, vec![("352", Some(1))]);
+        assert_eq!(who_spec("Nick", &support).replies, vec![("352", Some(5))]);
+        assert_eq!(who_spec("*!*@host", &support).replies, vec![("352", None)]);
+
+        let mut queries = PendingQueries::new();
+        let (reply_to, replies) = mpsc::channel();
+        queries.add(who_spec("Nick[1]", &support), false, reply_to);
+        queries.feed(&who_reply("*", "other"), CaseMapping::default());
+        queries.feed(&who_reply("*", "nick{1}"), CaseMapping::default());
+        queries.feed(&message("315", &["bot", "nick[1]", ":End of WHO"]),
+            CaseMapping::default());
+        let lines = replies.try_recv().unwrap().unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].args[5], "nick{1}");
+    }
+
+    #[test]
+    fn error_numerics_fail_the_query() {
+        let support = ServerSupport::default();
+        let mut queries = PendingQueries::new();
+        let (reply_to, replies) = mpsc::channel();
+        queries.add(who_spec("#gone", &support), false, reply_to);
+        queries.feed(&message("403", &["bot", "#gone", ":No such channel"]),
+            CaseMapping::default());
+        match replies.try_recv().unwrap() {
+            Err(e) => assert_eq!(e, QueryError::Failed("No such channel".to_string())),
+            Ok(_) => panic!("The query should have failed."),
+        }
+    }
+}
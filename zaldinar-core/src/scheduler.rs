@@ -0,0 +1,332 @@
+use std::collections;
+use std::fmt;
+use std::path;
+use std::sync;
+use std::time;
+
+use serde;
+use serde_json;
+
+use cron::CronSchedule;
+use data;
+use duration;
+use errors::{ThrowInitError, ThrowRuntimeError};
+
+/// Identifies a scheduled job, so that it can be cancelled.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub struct JobId(u64);
+
+impl fmt::Display for JobId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "#{}", self.0)
+    }
+}
+
+/// When a job runs.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    /// Runs once, and is then removed.
+    Once,
+    /// Runs every `seconds` seconds.
+    Every(u64),
+    /// Runs whenever a cron expression matches. See `CronSchedule`.
+    Cron(String),
+}
+
+impl Schedule {
+    /// Finds when a job with this schedule next runs after `now`, or None if it doesn't.
+    ///
+    /// Fails if the next run is too far in the future to represent.
+    fn next_after(&self, now: u64) -> Result<Option<u64>, String> {
+        return match self {
+            &Schedule::Once => Ok(None),
+            &Schedule::Every(seconds) => match now.checked_add(seconds) {
+                Some(next) => Ok(Some(next)),
+                None => Err(format!("The interval of {} is too long.", self)),
+            },
+            &Schedule::Cron(ref expression) => match CronSchedule::parse(expression) {
+                Ok(cron) => Ok(cron.next_after(now)),
+                Err(_) => Ok(None),
+            },
+        };
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Schedule::Once => fmt.write_str("once"),
+            &Schedule::Every(seconds) => write!(fmt, "every {}",
+                duration::format(time::Duration::from_secs(seconds))),
+            &Schedule::Cron(ref expression) => write!(fmt, "at `{}`", expression),
+        }
+    }
+}
+
+/// A job waiting to run the job handler named `handler`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: JobId,
+    pub handler: String,
+    pub schedule: Schedule,
+    /// Unix time in seconds when the job next runs.
+    pub next_run: u64,
+    /// Data given when the job was scheduled.
+    pub payload: serde_json::Value,
+    /// Whether the job is saved in `jobs.json`, and so survives restarts. Jobs scheduled by
+    /// plugins at runtime are; jobs registered along with their handler are recreated at startup
+    /// instead.
+    #[serde(skip)]
+    pub persistent: bool,
+}
+
+impl Job {
+    /// Reads the job's payload as a `T`.
+    pub fn payload<T>(&self) -> Result<T, ThrowRuntimeError> where T: serde::de::DeserializeOwned {
+        return Ok(throw!(serde_json::from_value(self.payload.clone())));
+    }
+}
+
+struct SchedulerState {
+    next_id: u64,
+    jobs: collections::BTreeMap<JobId, Job>,
+    stopped: bool,
+}
+
+/// Jobs which plugins have scheduled to run later, on the plugin worker threads.
+///
+/// Jobs name the handler which runs them, registered with `PluginRegister::register_job`, rather
+/// than holding a closure, so that they can be saved in `jobs.json` in the data directory and
+/// picked up again after a restart. Jobs which were due while the bot wasn't running run as soon
+/// as it starts.
+pub struct Scheduler {
+    path: path::PathBuf,
+    state: sync::Mutex<SchedulerState>,
+    /// Notified when jobs are added or the scheduler is stopped, so that `wait_for_due` notices.
+    changed: sync::Condvar,
+}
+
+impl Scheduler {
+    pub fn load(path: &path::Path) -> Result<Scheduler, ThrowInitError> {
+        let saved: Vec<Job> = up!(data::load_json(path)).unwrap_or_else(Vec::new);
+        let next_id = saved.iter().map(|job| job.id.0 + 1).max().unwrap_or(1);
+        let jobs = saved.into_iter().map(|mut job| {
+            job.persistent = true;
+            (job.id, job)
+        }).collect();
+        return Ok(Scheduler {
+            path: path.to_path_buf(),
+            state: sync::Mutex::new(SchedulerState {
+                next_id: next_id,
+                jobs: jobs,
+                stopped: false,
+            }),
+            changed: sync::Condvar::new(),
+        });
+    }
+
+    fn save(&self, state: &SchedulerState) {
+        let jobs = state.jobs.values().filter(|job| job.persistent).collect::<Vec<&Job>>();
+        if let Err(e) = data::save_json(&self.path, &jobs) {
+            error!("Failed to save scheduled jobs: {}", e);
+        }
+    }
+
+    /// Schedules `handler` to run once after `delay`, given `payload`.
+    pub fn after<T>(&self, handler: &str, delay: time::Duration, payload: &T)
+            -> Result<JobId, ThrowRuntimeError> where T: serde::Serialize {
+        let payload = throw!(serde_json::to_value(payload));
+        let run_at = match duration::unix_now().checked_add(delay.as_secs()) {
+            Some(v) => v,
+            None => throw_new!(format!("The delay of {} is too long.", duration::format(delay))),
+        };
+        return self.schedule_at(handler, Schedule::Once, run_at, payload, true);
+    }
+
+    /// Schedules `handler` to run every `interval`, given `payload`. The first run is after one
+    /// interval. Intervals are rounded down to whole seconds, with a minimum of one.
+    pub fn every<T>(&self, handler: &str, interval: time::Duration, payload: &T)
+            -> Result<JobId, ThrowRuntimeError> where T: serde::Serialize {
+        return self.schedule(handler, Schedule::Every(interval.as_secs()), payload, true);
+    }
+
+    /// Schedules `handler` to run whenever the cron expression `expression` matches, given
+    /// `payload`.
+    pub fn cron<T>(&self, handler: &str, expression: &str, payload: &T)
+            -> Result<JobId, ThrowRuntimeError> where T: serde::Serialize {
+        return self.schedule(handler, Schedule::Cron(expression.to_string()), payload, true);
+    }
+
+    /// Schedules a repeating job, which is saved to survive restarts if `persistent` is true.
+    pub fn schedule<T>(&self, handler: &str, mut schedule: Schedule, payload: &T,
+            persistent: bool) -> Result<JobId, ThrowRuntimeError> where T: serde::Serialize {
+        let payload = throw!(serde_json::to_value(payload));
+        if let Schedule::Every(ref mut seconds) = schedule {
+            if *seconds == 0 {
+                *seconds = 1;
+            }
+        }
+        if let Schedule::Cron(ref expression) = schedule {
+            if let Err(e) = CronSchedule::parse(expression) {
+                throw_new!(e);
+            }
+        }
+        let run_at = match schedule.next_after(duration::unix_now()) {
+            Ok(Some(v)) => v,
+            Ok(None) => throw_new!(format!("The schedule {} never runs.", schedule)),
+            Err(e) => throw_new!(e),
+        };
+        return self.schedule_at(handler, schedule, run_at, payload, persistent);
+    }
+
+    fn schedule_at(&self, handler: &str, schedule: Schedule, run_at: u64,
+            payload: serde_json::Value, persistent: bool) -> Result<JobId, ThrowRuntimeError> {
+        let mut state = self.state.lock().unwrap();
+        let id = JobId(state.next_id);
+        state.next_id += 1;
+        state.jobs.insert(id, Job {
+            id: id,
+            handler: handler.to_string(),
+            schedule: schedule,
+            next_run: run_at,
+            payload: payload,
+            persistent: persistent,
+        });
+        if persistent {
+            self.save(&state);
+        }
+        self.changed.notify_all();
+        return Ok(id);
+    }
+
+    /// Cancels a job, returning it if it hadn't finished yet.
+    pub fn cancel(&self, id: JobId) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.remove(&id);
+        if job.as_ref().map_or(false, |job| job.persistent) {
+            self.save(&state);
+        }
+        return job;
+    }
+
+    /// Gets the jobs which run `handler`, in the order they were scheduled.
+    pub fn jobs(&self, handler: &str) -> Vec<Job> {
+        let state = self.state.lock().unwrap();
+        return state.jobs.values().filter(|job| job.handler == handler).cloned().collect();
+    }
+
+    /// Blocks until at least one job is due, then returns every due job. One-off jobs are
+    /// removed, and repeating ones moved on to their next run.
+    ///
+    /// Returns None once the scheduler has been stopped.
+    pub fn wait_for_due(&self) -> Option<Vec<Job>> {
+        let mut state = self.state.lock().unwrap();
+        let now = loop {
+            if state.stopped {
+                return None;
+            }
+            let now = duration::unix_now();
+            let next = state.jobs.values().map(|job| job.next_run).min();
+            state = match next {
+                Some(next) if next <= now => break now,
+                Some(next) => self.changed.wait_timeout(state,
+                    time::Duration::from_secs(next - now)).unwrap().0,
+                None => self.changed.wait(state).unwrap(),
+            };
+        };
+
+        let due_ids = state.jobs.values().filter(|job| job.next_run <= now).map(|job| job.id)
+            .collect::<Vec<JobId>>();
+        let mut due = Vec::with_capacity(due_ids.len());
+        let mut changed_persistent = false;
+        for id in due_ids {
+            let next_run = match state.jobs[&id].schedule.next_after(now) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Job {} won't run again: {}", id, e);
+                    None
+                },
+            };
+            let job = match next_run {
+                Some(next_run) => {
+                    let job = state.jobs.get_mut(&id).unwrap();
+                    job.next_run = next_run;
+                    job.clone()
+                },
+                None => state.jobs.remove(&id).unwrap(),
+            };
+            changed_persistent |= job.persistent;
+            due.push(job);
+        }
+        if changed_persistent {
+            self.save(&state);
+        }
+        return Some(due);
+    }
+
+    /// Stops the scheduler, so that `wait_for_due` returns None. Jobs are kept in `jobs.json`.
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::time;
+    use std::u64;
+
+    use super::{Schedule, Scheduler};
+
+    fn scheduler(name: &str) -> Scheduler {
+        let path = env::temp_dir().join(format!("zaldinar-scheduler-{}.json", name));
+        let _ = fs::remove_file(&path);
+        return Scheduler::load(&path).unwrap();
+    }
+
+    #[test]
+    fn next_after_overflow() {
+        assert_eq!(Schedule::Once.next_after(10), Ok(None));
+        assert_eq!(Schedule::Every(5).next_after(10), Ok(Some(15)));
+        assert!(Schedule::Every(u64::MAX).next_after(10).is_err());
+        assert_eq!(Schedule::Cron("bad".to_string()).next_after(10), Ok(None));
+    }
+
+    #[test]
+    fn too_long_delays_are_errors() {
+        let scheduler = scheduler("overflow");
+        assert!(scheduler.after("h", time::Duration::from_secs(u64::MAX), &()).is_err());
+        assert!(scheduler.schedule("h", Schedule::Every(u64::MAX), &(), false).is_err());
+        assert!(scheduler.schedule("h", Schedule::Cron("0 0 31 2 *".to_string()), &(), false)
+            .is_err());
+        assert!(scheduler.jobs("h").is_empty());
+    }
+
+    #[test]
+    fn due_jobs_and_persistence() {
+        let scheduler = scheduler("due");
+        let due_id = scheduler.after("h", time::Duration::from_secs(0), &"x").unwrap();
+        let later_id = scheduler.after("h", time::Duration::from_secs(1000), &1u32).unwrap();
+        scheduler.schedule("temporary", Schedule::Every(1000), &(), false).unwrap();
+
+        let due = scheduler.wait_for_due().unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, due_id);
+        assert_eq!(due[0].payload::<String>().unwrap(), "x");
+        assert_eq!(scheduler.jobs("h").len(), 1);
+
+        // Only persistent jobs are loaded again.
+        let reloaded = Scheduler::load(&scheduler.path).unwrap();
+        assert_eq!(reloaded.jobs("h").len(), 1);
+        assert!(reloaded.jobs("temporary").is_empty());
+
+        assert!(scheduler.cancel(later_id).is_some());
+        assert!(scheduler.cancel(later_id).is_none());
+        scheduler.stop();
+        assert!(scheduler.wait_for_due().is_none());
+    }
+}
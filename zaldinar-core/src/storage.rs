@@ -0,0 +1,210 @@
+use std::collections;
+use std::path;
+use std::sync;
+
+use serde;
+use serde_json;
+
+use data;
+use errors::ThrowRuntimeError;
+
+/// A key-value store, saved in `plugins/<plugin>.json` in the data directory for a plugin's own
+/// store, or in `plugins/shared/<namespace>.json` for a shared one.
+///
+/// Values can be anything serde can serialize. Every change is written to disk straight away,
+/// replacing the old file atomically.
+pub struct Store {
+    path: path::PathBuf,
+    values: collections::BTreeMap<String, serde_json::Value>,
+}
+
+impl Store {
+    fn load(path: path::PathBuf) -> Result<Store, ThrowRuntimeError> {
+        let values = up!(data::load_json(&path)).unwrap_or_else(collections::BTreeMap::new);
+        return Ok(Store {
+            path: path,
+            values: values,
+        });
+    }
+
+    pub fn save(&self) -> Result<(), ThrowRuntimeError> {
+        return Ok(up!(data::save_json(&self.path, &self.values)));
+    }
+
+    /// Gets the value stored under `key`, or None if there isn't one.
+    ///
+    /// Fails if the value can't be read as a `T`.
+    pub fn get<T>(&self, key: &str) -> Result<Option<T>, ThrowRuntimeError>
+            where T: serde::de::DeserializeOwned {
+        return match self.values.get(key) {
+            Some(value) => Ok(Some(throw!(serde_json::from_value(value.clone())))),
+            None => Ok(None),
+        };
+    }
+
+    /// Stores `value` under `key`, replacing any old value, and saves the store.
+    ///
+    /// If saving fails, the store is left as it was.
+    pub fn set<T>(&mut self, key: &str, value: &T) -> Result<(), ThrowRuntimeError>
+            where T: serde::Serialize {
+        let value = throw!(serde_json::to_value(value));
+        let mut values = self.values.clone();
+        values.insert(key.to_string(), value);
+        up!(data::save_json(&self.path, &values));
+        self.values = values;
+        return Ok(());
+    }
+
+    /// Removes the value stored under `key` and saves the store. Returns whether there was one.
+    ///
+    /// If saving fails, the store is left as it was.
+    pub fn remove(&mut self, key: &str) -> Result<bool, ThrowRuntimeError> {
+        if !self.values.contains_key(key) {
+            return Ok(false);
+        }
+        let mut values = self.values.clone();
+        values.remove(key);
+        up!(data::save_json(&self.path, &values));
+        self.values = values;
+        return Ok(true);
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Lists the keys which have values, in order.
+    pub fn keys(&self) -> Vec<&str> {
+        return self.values.keys().map(|s| &**s).collect();
+    }
+}
+
+/// The key-value stores of every plugin, loaded when first used.
+pub struct Storage {
+    directory: path::PathBuf,
+    /// Loaded stores, by path relative to `directory`.
+    stores: sync::Mutex<collections::HashMap<path::PathBuf, sync::Arc<sync::Mutex<Store>>>>,
+}
+
+impl Storage {
+    pub fn new(directory: path::PathBuf) -> Storage {
+        return Storage {
+            directory: directory,
+            stores: sync::Mutex::new(collections::HashMap::new()),
+        };
+    }
+
+    /// Gets the store shared by every plugin which uses `namespace`. Shared stores are kept
+    /// apart from plugins' own stores, so a namespace can be the same as a plugin's name.
+    pub fn shared(&self, namespace: &str) -> Result<sync::Arc<sync::Mutex<Store>>,
+            ThrowRuntimeError> {
+        let file = up!(file_name(namespace));
+        return self.open(path::Path::new("shared").join(file));
+    }
+
+    /// Gets `plugin`'s own store. This is only reached through `PluginStorage`, so that plugins
+    /// can't open each other's stores.
+    fn plugin(&self, plugin: &str) -> Result<sync::Arc<sync::Mutex<Store>>, ThrowRuntimeError> {
+        let file = up!(file_name(plugin));
+        return self.open(path::PathBuf::from(file));
+    }
+
+    fn open(&self, relative: path::PathBuf) -> Result<sync::Arc<sync::Mutex<Store>>,
+            ThrowRuntimeError> {
+        let mut stores = self.stores.lock().unwrap();
+        if let Some(store) = stores.get(&relative) {
+            return Ok(store.clone());
+        }
+        let store = sync::Arc::new(sync::Mutex::new(up!(Store::load(self.directory
+            .join(&relative)))));
+        stores.insert(relative, store.clone());
+        return Ok(store);
+    }
+}
+
+/// Gets the name of the file a store called `name` is saved in.
+///
+/// Names may only contain ASCII letters, digits, `-` and `_`, so that they are valid file names.
+fn file_name(name: &str) -> Result<String, ThrowRuntimeError> {
+    let valid = !name.is_empty() && name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        throw_new!(format!("Invalid storage namespace `{}`.", name));
+    }
+    return Ok(format!("{}.json", name));
+}
+
+/// A plugin's handle to its own key-value store, from `PluginRegister::storage`. The store is
+/// named after the plugin, so plugins can't overwrite each other's data.
+#[derive(Clone)]
+pub struct PluginStorage {
+    plugin: String,
+    storage: sync::Arc<sync::RwLock<Option<sync::Arc<Storage>>>>,
+}
+
+impl PluginStorage {
+    pub fn new(plugin: &str, storage: sync::Arc<sync::RwLock<Option<sync::Arc<Storage>>>>)
+            -> PluginStorage {
+        return PluginStorage {
+            plugin: plugin.to_string(),
+            storage: storage,
+        };
+    }
+
+    /// Gets the plugin's store, loading it the first time.
+    ///
+    /// Fails if the bot hasn't started yet, which can only happen when called from a plugin's
+    /// `register` function.
+    pub fn get(&self) -> Result<sync::Arc<sync::Mutex<Store>>, ThrowRuntimeError> {
+        let storage = self.storage.read().unwrap();
+        return match *storage {
+            Some(ref storage) => storage.plugin(&self.plugin),
+            None => throw_new!(format!("Storage for plugin {} used before it was loaded.",
+                self.plugin)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections;
+    use std::path;
+    use std::sync;
+
+    use serde_json;
+
+    use super::{PluginStorage, Storage, Store};
+
+    #[test]
+    fn failed_saves_leave_the_store_unchanged() {
+        // A directory can't be created inside /dev/null, so saving always fails.
+        let mut store = Store {
+            path: path::PathBuf::from("/dev/null/store.json"),
+            values: collections::BTreeMap::new(),
+        };
+        store.values.insert("kept".to_string(), serde_json::Value::from(1));
+
+        assert!(store.set("new", &2).is_err());
+        assert!(!store.contains_key("new"));
+        assert!(store.remove("kept").is_err());
+        assert_eq!(store.get::<u32>("kept").unwrap(), Some(1));
+        assert_eq!(store.remove("missing").unwrap(), false);
+    }
+
+    #[test]
+    fn plugins_get_their_own_stores() {
+        let slot = sync::Arc::new(sync::RwLock::new(None));
+        let quotes = PluginStorage::new("quotes", slot.clone());
+        let remind = PluginStorage::new("remind", slot.clone());
+        assert!(quotes.get().is_err());
+
+        let storage = sync::Arc::new(Storage::new(path::PathBuf::from("/nonexistent/plugins")));
+        *slot.write().unwrap() = Some(storage.clone());
+        let store = quotes.get().unwrap();
+        assert!(sync::Arc::ptr_eq(&store, &quotes.get().unwrap()));
+        assert!(!sync::Arc::ptr_eq(&store, &remind.get().unwrap()));
+        assert!(!sync::Arc::ptr_eq(&store, &storage.shared("quotes").unwrap()));
+        assert!(PluginStorage::new("<unknown>", slot.clone()).get().is_err());
+        assert!(storage.shared("../quotes").is_err());
+    }
+}
@@ -79,7 +79,15 @@ impl <T: io::BufRead, C: HasNick> IrcRead<T, C> {
                 break; // end of file
             }
             let input = irc_color_regex.replace_all(whole_input.trim_right(), "");
-            let message_split: Vec<&str> = input.split(' ').collect();
+            // IRCv3 message tags come before everything else, as `@key=value;key2 `.
+            let (tags, untagged_input) = if input.starts_with("@") {
+                let mut split = input.splitn(2, ' ');
+                let tags = parse_tags(&split.next().unwrap()[1..]);
+                (tags, split.next().unwrap_or(""))
+            } else {
+                (Vec::new(), &*input)
+            };
+            let message_split: Vec<&str> = untagged_input.split(' ').collect();
             let (command, args, possible_mask): (&str, &[&str], IrcMask) = if message_split[0]
                     .starts_with(":") {
                 (
@@ -131,7 +139,7 @@ impl <T: io::BufRead, C: HasNick> IrcRead<T, C> {
             // TODO: Change channel to sender nick if channel is our current nick.
             let args_owned: Vec<String> = args.iter().map(|s: &&str| s.to_string()).collect();
             let message = IrcMessage::new(command.to_string(), args_owned, possible_mask, ctcp,
-                channel);
+                channel, tags);
             if let Err(_) = self.data_out.send(message) {
                 error!("Failed to send to data_out from IrcRead.");
                 return;
@@ -140,6 +148,52 @@ impl <T: io::BufRead, C: HasNick> IrcRead<T, C> {
     }
 }
 
+/// Parses the tags section of a message (without the leading `@`) into `(key, value)` pairs.
+fn parse_tags(tags: &str) -> Vec<(String, Option<String>)> {
+    tags.split(';').filter(|tag| !tag.is_empty()).map(|tag| {
+        let mut split = tag.splitn(2, '=');
+        let key = split.next().unwrap().to_string();
+        let value = split.next().map(unescape_tag_value);
+        (key, value)
+    }).collect()
+}
+
+fn unescape_tag_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => result.push(';'),
+            Some('s') => result.push(' '),
+            Some('r') => result.push('\r'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => break,
+        }
+    }
+    return result;
+}
+
+/// Escapes a value for use in the tags section of a message sent to the server.
+pub fn escape_tag_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => result.push_str("\\:"),
+            ' ' => result.push_str("\\s"),
+            '\\' => result.push_str("\\\\"),
+            '\r' => result.push_str("\\r"),
+            '\n' => result.push_str("\\n"),
+            _ => result.push(c),
+        }
+    }
+    return result;
+}
+
 pub struct IrcWrite<T: io::Write> {
     socket: T,
     data_in: mpsc::Receiver<Option<String>>,
@@ -173,13 +227,14 @@ impl <T: io::Write> IrcWrite<T> {
     }
 }
 
-
+#[derive(Clone)]
 pub enum IrcMask {
     Full(FullIrcMask),
     Unparseable(String),
     Nonexistent,
 }
 
+#[derive(Clone)]
 pub struct FullIrcMask {
     pub mask: String,
     pub nick: String,
@@ -187,6 +242,7 @@ pub struct FullIrcMask {
     pub host: String,
 }
 
+#[derive(Clone)]
 pub struct IrcMessage {
     pub command: String,
     pub args: Vec<String>,
@@ -194,19 +250,28 @@ pub struct IrcMessage {
     /// Option<(command, message)>
     pub ctcp: Option<(String, String)>,
     pub channel: Option<String>,
+    /// IRCv3 message tags, as (key, value) pairs with escaping already removed from values.
+    pub tags: Vec<(String, Option<String>)>,
 }
 
 impl IrcMessage {
     fn new(command: String, args: Vec<String>, mask: IrcMask, ctcp: Option<(String, String)>,
-            channel: Option<String>) -> IrcMessage {
+            channel: Option<String>, tags: Vec<(String, Option<String>)>) -> IrcMessage {
         return IrcMessage {
             command: command,
             args: args,
             mask: mask,
             ctcp: ctcp,
             channel: channel,
+            tags: tags,
         };
     }
+
+    /// Gets the value of a message tag. Returns None if the tag is missing or has no value.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|&&(ref k, _)| k == key).and_then(|&(_, ref v)| v.as_ref())
+            .map(|v| &**v)
+    }
 }
 
 impl IrcMask {
@@ -224,13 +289,13 @@ impl IrcMask {
     }
 
     fn parse_from_str(mask: &str) -> IrcMask {
-        let mask_split = mask.splitn(1, '!').collect::<Vec<&str>>();
+        let mask_split = mask.splitn(2, '!').collect::<Vec<&str>>();
         if mask_split.len() < 2 {
             return IrcMask::new_mask_only(mask.to_string());
         }
         let nick = mask_split[0];
         let user_and_host = mask_split[1];
-        let user_and_host_split = user_and_host.splitn(1, '@').collect::<Vec<&str>>();
+        let user_and_host_split = user_and_host.splitn(2, '@').collect::<Vec<&str>>();
         if user_and_host_split.len() < 2 {
             return IrcMask::new_mask_only(mask.to_string());
         }
@@ -256,3 +321,38 @@ impl IrcMask {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_tag_value, parse_tags};
+
+    fn tag(key: &str, value: Option<&str>) -> (String, Option<String>) {
+        (key.to_string(), value.map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn parse_tags_keys_and_values() {
+        assert_eq!(parse_tags("msgid=abc;account=someone;+draft/typing"), vec![
+            tag("msgid", Some("abc")),
+            tag("account", Some("someone")),
+            tag("+draft/typing", None),
+        ]);
+        // Only the first `=` separates the key from the value.
+        assert_eq!(parse_tags("a=b=c;;empty="), vec![
+            tag("a", Some("b=c")),
+            tag("empty", Some("")),
+        ]);
+    }
+
+    #[test]
+    fn parse_tags_unescapes_values() {
+        assert_eq!(parse_tags(r"k=a\:b\sc\\d\r\n\x\"), vec![tag("k", Some("a;b c\\d\r\nx"))]);
+    }
+
+    #[test]
+    fn escaped_values_round_trip() {
+        let value = "semi; space \\ cr\r lf\n";
+        let tags = parse_tags(&format!("k={}", escape_tag_value(value)));
+        assert_eq!(tags, vec![tag("k", Some(value))]);
+    }
+}
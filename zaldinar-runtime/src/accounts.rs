@@ -0,0 +1,157 @@
+use std::ascii::AsciiExt;
+use std::collections;
+use std::collections::hash_map;
+use std::time;
+
+use core::interface;
+use core::masks::CaseMapping;
+use dispatch::Invocation;
+use irc;
+
+/// How long to wait for the end of a WHOIS before giving up on it, in seconds.
+const LOOKUP_TIMEOUT_SECONDS: u64 = 30;
+/// The most commands from one user which can wait on a lookup at once.
+const MAX_WAITING_COMMANDS: usize = 5;
+
+struct PendingLookup {
+    /// When the WHOIS was sent.
+    started: time::Instant,
+    /// Whether RPL_WHOISACCOUNT was received, so the user is logged in.
+    account_seen: bool,
+    commands: Vec<Invocation>,
+}
+
+/// What happened to a command given to `AccountTracker::queue`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Queued {
+    /// The command is waiting on a new lookup, so a WHOIS should be sent.
+    NewLookup,
+    /// The command is waiting on a lookup which is already in progress.
+    Waiting,
+    /// Too many commands from the user are waiting already, so the command was dropped.
+    Full,
+}
+
+/// Keeps track of which services account each user is logged in to.
+///
+/// This runs synchronously in the dispatch thread, rather than as a plugin, so that the accounts
+/// used by permission checks are always up to date with the message being dispatched.
+pub struct AccountTracker {
+    /// WHOIS lookups in progress, keyed by nick lowercased with the server's casemapping.
+    pending: collections::HashMap<String, PendingLookup>,
+}
+
+impl AccountTracker {
+    pub fn new() -> AccountTracker {
+        return AccountTracker {
+            pending: collections::HashMap::new(),
+        };
+    }
+
+    /// Records any account information in `message`.
+    ///
+    /// Returns the commands which were waiting on a WHOIS lookup which this message finished,
+    /// or which has timed out. Those should now be checked without waiting on a lookup, so that
+    /// they are denied if the account still isn't known.
+    pub fn track(&mut self, interface: &interface::IrcInterface, message: &irc::IrcMessage)
+            -> Vec<Invocation> {
+        let mut state = interface.state().write().unwrap();
+        let casemapping = state.support.casemapping;
+        let mut finished = self.expire();
+
+        // account-tag
+        if let (Some(nick), Some(account)) = (message.mask.nick(), message.tag("account")) {
+            state.set_account(nick, Some(account));
+        }
+
+        match &*message.command.to_ascii_uppercase() {
+            // account-notify: `:nick!user@host ACCOUNT <account or *>`
+            "ACCOUNT" => {
+                if let (Some(nick), Some(account)) = (message.mask.nick(), message.args.first()) {
+                    state.set_account(nick, Some(account.trim_left_matches(':')));
+                }
+            },
+            // extended-join: `:nick!user@host JOIN <channel> <account or *> :<real name>`
+            "JOIN" => {
+                if let Some(nick) = message.mask.nick() {
+                    if state.has_capability("extended-join") && message.args.len() > 1 {
+                        state.set_account(nick, Some(&message.args[1]));
+                    }
+                }
+            },
+            "NICK" => {
+                if let (Some(nick), Some(new_nick)) = (message.mask.nick(), message.args.first()) {
+                    state.rename_account(nick, new_nick.trim_left_matches(':'));
+                }
+            },
+            "QUIT" => {
+                if let Some(nick) = message.mask.nick() {
+                    state.forget_account(nick);
+                }
+            },
+            // RPL_WHOISACCOUNT: `<our nick> <nick> <account> :is logged in as`
+            "330" => {
+                if message.args.len() > 2 {
+                    state.set_account(&message.args[1], Some(&message.args[2]));
+                    if let Some(lookup) = self.pending.get_mut(
+                            &casemapping.to_lower(&message.args[1])) {
+                        lookup.account_seen = true;
+                    }
+                }
+            },
+            // RPL_ENDOFWHOIS: `<our nick> <nick> :End of /WHOIS list`
+            "318" => {
+                if message.args.len() > 1 {
+                    if let Some(lookup) = self.pending.remove(
+                            &casemapping.to_lower(&message.args[1])) {
+                        if !lookup.account_seen {
+                            state.set_account(&message.args[1], None);
+                        }
+                        finished.extend(lookup.commands);
+                    }
+                }
+            },
+            _ => (),
+        }
+        return finished;
+    }
+
+    /// Gives up on lookups which have taken too long, as when the server never ends the WHOIS,
+    /// returning the commands which were waiting on them.
+    fn expire(&mut self) -> Vec<Invocation> {
+        let timeout = time::Duration::from_secs(LOOKUP_TIMEOUT_SECONDS);
+        let expired = self.pending.iter()
+            .filter(|&(_, lookup)| lookup.started.elapsed() > timeout)
+            .map(|(nick, _)| nick.clone())
+            .collect::<Vec<String>>();
+        let mut commands = Vec::new();
+        for nick in expired {
+            warn!("WHOIS lookup of {} timed out.", nick);
+            commands.extend(self.pending.remove(&nick).unwrap().commands);
+        }
+        return commands;
+    }
+
+    /// Holds a command until a WHOIS lookup of `nick` finishes.
+    pub fn queue(&mut self, nick: &str, casemapping: CaseMapping, command: Invocation)
+            -> Queued {
+        match self.pending.entry(casemapping.to_lower(nick)) {
+            hash_map::Entry::Occupied(mut e) => {
+                let commands = &mut e.get_mut().commands;
+                if commands.len() >= MAX_WAITING_COMMANDS {
+                    return Queued::Full;
+                }
+                commands.push(command);
+                return Queued::Waiting;
+            },
+            hash_map::Entry::Vacant(e) => {
+                e.insert(PendingLookup {
+                    started: time::Instant::now(),
+                    account_seen: false,
+                    commands: vec![command],
+                });
+                return Queued::NewLookup;
+            },
+        }
+    }
+}
@@ -1,36 +1,84 @@
 use std::ascii::AsciiExt;
+use std::cell;
+use std::cmp;
+use std::collections::hash_map;
+use std::hash::{Hash, Hasher};
 use std::sync;
 use std::sync::mpsc;
 use std::thread;
 use std::fmt;
+use std::panic;
+use std::time;
 
 use core::interface;
 use core::client;
+use core::config;
 use core::events;
+use core::ignore;
+use core::aliases;
+use core::commands;
+use core::duration;
+use core::scheduler;
+use core::bus;
+use core::breaker;
+use core::masks::CaseMapping;
 use irc;
+use accounts;
+use ratelimit;
+use ordering;
+use watchdog;
 
 pub struct Dispatch {
     interface: interface::IrcInterface,
     state: client::Client,
     data_in: mpsc::Receiver<irc::IrcMessage>,
-    workers_out: mpsc::Sender<PluginThunk>,
+    workers_out: WorkerQueues,
+    /// Where heavy commands are sent, or None if they run on the ordinary workers.
+    heavy_out: Option<WorkerQueues>,
+    watchdog: sync::Arc<watchdog::Watchdog>,
+    accounts: cell::RefCell<accounts::AccountTracker>,
+    rate_limiter: cell::RefCell<ratelimit::RateLimiter>,
 }
 
 impl Dispatch {
     pub fn new(interface: interface::IrcInterface, state: client::Client,
             data_in: mpsc::Receiver<irc::IrcMessage>) -> Dispatch {
-        let (dispatch_out, workers_in) = mpsc::channel();
-        let workers_in_arc = sync::Arc::new(sync::Mutex::new(workers_in));
-        for _ in 0..4 {
-            let executor = PluginExecutor::new(interface.clone(), workers_in_arc.clone());
-            executor.start_worker_thread();
+        let watchdog = sync::Arc::new(watchdog::Watchdog::new(
+            time::Duration::from_secs(state.workers.watchdog_seconds)));
+        watchdog::start_watchdog_thread(watchdog.clone());
+        let ordering = state.workers.ordering;
+        let dispatch_out = start_worker_pool(&interface, "worker_thread",
+            cmp::max(state.workers.threads, 1), ordering, &watchdog);
+        let heavy_out = match state.workers.heavy_threads {
+            0 => None,
+            threads => Some(start_worker_pool(&interface, "heavy_worker_thread", threads,
+                ordering, &watchdog)),
+        };
+
+        {
+            let plugins = state.plugins().read().unwrap();
+            for &(ref handler, ref schedule) in &plugins.startup_jobs {
+                let result = interface.scheduler().schedule(handler, schedule.clone(), &(),
+                    false);
+                if let Err(e) = result {
+                    warn!("Failed to schedule job `{}`: {}", handler, e);
+                }
+            }
         }
+        start_scheduler_thread(interface.clone(), state.clone(), dispatch_out.clone(),
+            heavy_out.clone());
+        start_bus_thread(interface.clone(), state.clone(), dispatch_out.clone(), heavy_out.clone());
 
+        let rate_limiter = ratelimit::RateLimiter::new(&state.rate_limits);
         return Dispatch {
             interface: interface,
             state: state,
             data_in: data_in,
             workers_out: dispatch_out,
+            heavy_out: heavy_out,
+            watchdog: watchdog,
+            accounts: cell::RefCell::new(accounts::AccountTracker::new()),
+            rate_limiter: cell::RefCell::new(rate_limiter),
         };
     }
 
@@ -42,9 +90,10 @@ impl Dispatch {
             };
             if let Err(_) = self.process_message(&message) {
                 error!("Failed to send to workers_out from Dispatch. Exiting.");
-                return;
+                break;
             }
         }
+        self.interface.run_lifecycle_hooks(events::Lifecycle::Disconnect);
     }
 
     fn process_message<'a>(&self, message: &'a irc::IrcMessage)
@@ -56,51 +105,119 @@ impl Dispatch {
             self.interface.send_raw(format!("PONG {}", message.args.join(" ")));
         }
 
-        let message_event = events::MessageTransport::from_internal(message);
+        // Accounts are tracked before anything else so permission checks see them.
+        let finished_lookups = self.accounts.borrow_mut().track(&self.interface, message);
+        for pending in finished_lookups {
+            if self.interface.ignore_scope(&pending.mask).is_some() {
+                continue;
+            }
+            try!(self.dispatch_command(&plugins, pending, false));
+        }
+
+        let message_event = {
+            let state = self.state.state().read().unwrap();
+            events::MessageTransport::from_internal(message, &state.support)
+        };
+
+        // State is tracked here rather than on the workers, so that plugins always see state
+        // which includes every message they are given, and so that it changes in order. These
+        // run even if their plugin is disabled, since skipping them would leave the state wrong.
+        if let Some(list) = plugins.tracking_listeners.get(&message.command.to_ascii_lowercase()) {
+            let event = events::MessageEvent::new(&self.interface, &message_event);
+            for listener in list {
+                let description = format!("tracking listener for {} from plugin {}",
+                    message.command, listener.plugin_name());
+                self.interface.run_plugin_code(listener.plugin.as_ref().map(|s| &**s),
+                    &description, || (listener.listener)(&event));
+            }
+        }
+
+        // Replies to queries are passed on before any listeners run, to wake up the plugins
+        // waiting on them.
+        self.interface.feed_queries(message);
+
+        // Ignores are checked before any listeners run. Messages and notices from users ignored
+        // entirely are dropped, but their joins, parts and other changes are still dispatched so
+        // that channel state stays correct.
+        let ignored = self.interface.ignore_scope(&message.mask);
+        let is_privmsg = (*message.command).eq_ignore_ascii_case("PRIVMSG");
+        if ignored == Some(ignore::IgnoreScope::All)
+                && (is_privmsg || (*message.command).eq_ignore_ascii_case("NOTICE")) {
+            return Ok(());
+        }
 
         // Catch all listeners
         for listener in &plugins.catch_all {
-            try!(self.execute(PluginThunk::Message(listener.clone(), message_event.clone())));
+            if self.listener_enabled(message_event.channel(), listener) {
+                try!(self.execute(PluginThunk::Message(listener.clone(), message_event.clone())));
+            }
         }
 
         // Raw listeners
         if let Some(list) = plugins.raw_listeners.get(&message.command.to_ascii_lowercase()) {
             for listener in list {
-                try!(self.execute(PluginThunk::Message(listener.clone(), message_event.clone())));
+                if self.listener_enabled(message_event.channel(), listener) {
+                    try!(self.execute(PluginThunk::Message(listener.clone(),
+                        message_event.clone())));
+                }
             }
         }
 
-        if (*message.command).eq_ignore_ascii_case("PRIVMSG") {
-            // Channel always exists for PRIVMSG
-            let channel = &message.channel.as_ref().unwrap();
+        if is_privmsg {
+            // Channel and context always exist for PRIVMSG
+            let channel = message_event.channel().unwrap();
+            let context = message_event.context().unwrap();
 
             // CTCP
             if let Some(ctcp_event) = events::CtcpTransport::from_internal(message) {
                 if let Some(list) = plugins.ctcp_listeners.get(&ctcp_event.command
                         .to_ascii_lowercase()) {
                     for ctcp_listener in list {
-                        try!(self.execute(PluginThunk::Ctcp(ctcp_listener.clone(),
-                            ctcp_event.clone())));
+                        if self.listener_enabled(Some(channel), ctcp_listener) {
+                            try!(self.execute(PluginThunk::Ctcp(ctcp_listener.clone(),
+                                ctcp_event.clone())));
+                        }
                     }
                 }
             }
 
             // Commands
-            let command_prefix = format!(":{}", &self.state.command_prefix);
+            if ignored.is_some() {
+                return Ok(());
+            }
+            let invocation = |command: &str, args: Vec<String>| Invocation {
+                command: command.to_string(),
+                channel: channel.to_string(),
+                context: context,
+                args: args,
+                mask: message.mask.clone(),
+                msgid: message.tag("msgid").map(|s| s.to_string()),
+            };
+            // The longest matching prefix is used, so that both `!` and `!!` can be prefixes.
+            let command_prefix = self.state.command_prefixes(channel, self.casemapping())
+                .into_iter()
+                .filter(|prefix| message.args[1][1..].starts_with(prefix))
+                .max_by_key(|prefix| prefix.len());
 
             // This checks for the command prefix, commands typed like '.command_name args'
-            if message.args[1].starts_with(&command_prefix) {
-                let command = &message.args[1][command_prefix.len()..];
+            if let Some(command_prefix) = command_prefix {
+                // 1 + to skip the `:` at the beginning of privmsg content.
+                let command = &message.args[1][1 + command_prefix.len()..];
                 let args = message.args[2..].iter().map(|s| s.clone())
                             .collect::<Vec<String>>();
-                try!(self.dispatch_command(&plugins, command, channel, args, &message.mask));
+                try!(self.dispatch_command(&plugins, invocation(command, args), true));
             } else {
                 // This checks for someone typing commands like 'BotName, command_name args'
                 // We store whether or not a command was matched in a variable so that we can use
                 // it below.
                 let mut command_matched = false;
-                if let Some(captures) = regex!(r"^:([^\s]+?)[:;,]?\s+(.+)$").captures(
-                                            &message.args[1..].join(" ")) {
+                let joined = message.args[1..].join(" ");
+                let captures = if self.state.nick_addressing(channel, self.casemapping()) {
+                    regex!(r"^:([^\s]+?)[:;,]?\s+(.+)$").captures(&joined)
+                } else {
+                    None
+                };
+                if let Some(captures) = captures {
                     let same = {
                         let state = self.state.state().read().unwrap();
                         match captures.get(1) {
@@ -114,104 +231,806 @@ impl Dispatch {
                             let command = split[0];
                             let args = split[1..].iter().map(|s| s.to_string())
                                         .collect::<Vec<String>>();
-                            try!(self.dispatch_command(&plugins, command, channel, args, &message.mask));
+                            try!(self.dispatch_command(&plugins, invocation(command, args),
+                                true));
                             command_matched = true;
                         }
                     }
                 }
 
                 // This checks for commands in a private message, where a prefix isn't required
-                // People can just say 'command args' in a private message. If the channel is the
-                // sender's nick, the message is being sent in a private message.
-                if !command_matched && message.mask.nick() == Some(channel) {
+                // People can just say 'command args' in a private message.
+                if !command_matched && context == events::MessageContext::Private {
                     // [1..] to remove the `:` at the beginning of privmsg content.
                     let command = &message.args[1][1..];
                     let args = message.args[2..].iter().map(|s| s.clone())
                                 .collect::<Vec<String>>();
-                    try!(self.dispatch_command(&plugins, command, channel, args, &message.mask));
+                    try!(self.dispatch_command(&plugins, invocation(command, args), true));
                 }
             }
         }
         return Ok(());
     }
 
+    /// Runs a command, or a pipeline of commands separated by `|`, if the sender has the
+    /// permissions required.
+    ///
+    /// If the sender lacks a permission, but might have it through a services account which
+    /// isn't known yet, the command is held until a WHOIS lookup finishes when `account_lookup`
+    /// is true.
     fn dispatch_command(&self, plugins: &sync::RwLockReadGuard<client::PluginRegister>,
-            command: &str, channel: &str, args: Vec<String>, mask: &irc::IrcMask)
+            invocation: Invocation, account_lookup: bool)
             -> Result<(), mpsc::SendError<PluginThunk>> {
-        if let Some(closure) = plugins.commands.get(&command.to_ascii_lowercase()) {
-            let command_event = events::CommandTransport::new(channel, args, mask);
+        let stages = split_pipeline(&invocation);
+        if stages.len() > MAX_PIPELINE_STAGES {
+            if let Some(nick) = invocation.mask.nick() {
+                self.interface.send_notice(nick, format!("Pipelines can have at most {} commands.",
+                    MAX_PIPELINE_STAGES));
+            }
+            return Ok(());
+        }
+        if stages.iter().skip(1).any(|stage| stage.command.is_empty()) {
+            if let Some(nick) = invocation.mask.nick() {
+                self.interface.send_notice(nick, "Each `|` in a pipeline must be followed by a \
+                    command. Use `\\|` for a literal `|`.");
+            }
+            return Ok(());
+        }
 
-            try!(self.execute(PluginThunk::Command(closure.clone(), command_event.clone())));
-        } else {
-            if let Some(closure) = plugins.admin_commands.get(&command.to_ascii_lowercase()) {
-                if !self.interface.is_internal_mask_admin(&mask) {
-                    if let Some(nick) = mask.nick() {
+        let mut prepared = Vec::with_capacity(stages.len());
+        for (index, stage) in stages.into_iter().enumerate() {
+            match self.prepare_command(plugins, stage, index) {
+                Prepared::Ready(registered, command_event) => {
+                    prepared.push((registered, command_event));
+                },
+                Prepared::Rejected => return Ok(()),
+                Prepared::Denied => {
+                    if let Some(nick) = invocation.mask.nick() {
+                        if account_lookup && self.state.accounts.whois_lookup
+                                && self.interface.account(nick).is_none() {
+                            let nick = nick.to_string();
+                            let queued = self.accounts.borrow_mut().queue(&nick,
+                                self.casemapping(), invocation);
+                            match queued {
+                                accounts::Queued::NewLookup => {
+                                    self.interface.send_command("WHOIS", &[nick]);
+                                },
+                                accounts::Queued::Waiting => (),
+                                accounts::Queued::Full => {
+                                    self.interface.send_notice(&*nick, "Permission denied");
+                                },
+                            }
+                            return Ok(());
+                        }
                         self.interface.send_notice(nick, "Permission denied");
                     }
-                } else {
-                    let command_event = events::CommandTransport::new(channel, args, mask);
+                    return Ok(());
+                },
+            }
+        }
 
-                    try!(self.execute(PluginThunk::Command(
-                        closure.clone(), command_event.clone())));
+        let commands = prepared.iter().map(|&(ref registered, _)| &**registered)
+            .collect::<Vec<&client::RegisteredCommand>>();
+        if self.rate_limited(plugins, &commands, &invocation) {
+            return Ok(());
+        }
+        let heavy = prepared.iter().any(|&(ref registered, _)| self.is_heavy(plugins, registered));
+        let thunk = if prepared.len() == 1 {
+            let (registered, command_event) = prepared.pop().unwrap();
+            PluginThunk::Command(registered, command_event)
+        } else {
+            PluginThunk::Pipeline(prepared)
+        };
+        match self.heavy_out {
+            Some(ref heavy_out) if heavy => try!(heavy_out.send(thunk, self.casemapping())),
+            _ => try!(self.execute(thunk)),
+        }
+
+        return Ok(());
+    }
+
+    /// Finds the command an invocation is for and checks that it may be used. `stage` is the
+    /// index of the command in its pipeline.
+    fn prepare_command(&self, plugins: &client::PluginRegister,
+            invocation: Invocation, stage: usize) -> Prepared {
+        let invocation = match self.expand_aliases(plugins, invocation, stage) {
+            Some(v) => v,
+            None => return Prepared::Rejected,
+        };
+        let (registered, path_length) = match plugins.find_command(&invocation.command,
+                &invocation.args) {
+            Some(v) => v,
+            None => return Prepared::Rejected,
+        };
+        let channel = &*invocation.channel;
+        let mask = &invocation.mask;
+        if !self.interface.command_enabled(channel, plugins, &registered.descriptor) {
+            return Prepared::Rejected;
+        }
+
+        if let Some(ref plugin) = registered.descriptor.plugin {
+            let disabled_for = self.interface.circuit_breaker().lock().unwrap()
+                .disabled_for(plugin);
+            if let Some(remaining) = disabled_for {
+                if let Some(nick) = mask.nick() {
+                    self.interface.send_notice(nick, format!("The {} plugin has been disabled \
+                        after failing repeatedly. Try again in {}.", plugin,
+                        duration::format(remaining)));
                 }
+                return Prepared::Rejected;
             }
         }
 
-        return Ok(());
+        // A subcommand may only be used where its groups may be too.
+        let unavailable = plugins.command_chain(&registered.descriptor).into_iter()
+            .find(|d| !d.availability.allows(invocation.context));
+        if let Some(descriptor) = unavailable {
+            if let Some(nick) = mask.nick() {
+                self.interface.send_notice(nick, format!("{}{} can only be used {}.",
+                    self.state.display_prefix(channel, self.casemapping()), descriptor.name,
+                    descriptor.availability));
+            }
+            return Prepared::Rejected;
+        }
+
+        if let Some(permission) = self.interface.command_permission(plugins,
+                &registered.descriptor) {
+            if !self.interface.has_internal_permission(mask, Some(channel), permission) {
+                return Prepared::Denied;
+            }
+        }
+
+        if registered.listener.is_none() {
+            // A group was used without one of its subcommands.
+            if let Some(nick) = mask.nick() {
+                let subcommands = plugins.subcommands(&registered.descriptor.name).iter()
+                    .map(|c| c.descriptor.name.rsplit(' ').next().unwrap())
+                    .collect::<Vec<&str>>().join(", ");
+                self.interface.send_notice(nick, format!("Usage: {}{} <{}>",
+                    self.state.display_prefix(channel, self.casemapping()),
+                    registered.descriptor.name, subcommands));
+            }
+            return Prepared::Rejected;
+        }
+
+        let args = invocation.args[path_length..].to_vec();
+        let mut command_event = events::CommandTransport::new(channel, invocation.context, args,
+            mask);
+        command_event.msgid = invocation.msgid.clone();
+        // Arguments of later stages are only complete once the output before them is known.
+        if stage == 0 && !parse_command_args(&self.interface, registered, &mut command_event) {
+            return Prepared::Rejected;
+        }
+        return Prepared::Ready(registered.clone(), command_event);
+    }
+
+    /// Checks the commands of a pipeline against `rate_limits`, starting their cooldowns if they
+    /// may all be used. Returns true if the user needs to wait before using them.
+    fn rate_limited(&self, plugins: &client::PluginRegister,
+            commands: &[&client::RegisteredCommand], invocation: &Invocation) -> bool {
+        let mask = &invocation.mask;
+        let channel = &*invocation.channel;
+        let nick = match mask.nick() {
+            Some(v) => v,
+            None => return false,
+        };
+        if self.interface.has_internal_permission(mask, Some(channel),
+                &self.state.rate_limits.exempt_permission) {
+            return false;
+        }
+        let chains = commands.iter().map(|registered| {
+            plugins.command_chain(&registered.descriptor).iter()
+                .map(|d| commands::command_path(&d.name))
+                .collect::<Vec<String>>()
+        }).collect::<Vec<Vec<String>>>();
+        let user = self.cooldown_identity(mask, nick);
+        let channel = self.casemapping().to_lower(channel);
+        let mut rate_limiter = self.rate_limiter.borrow_mut();
+        let wait = chains.iter()
+            .filter_map(|chain| rate_limiter.check(chain, &user, &channel).err())
+            .max();
+        if let Some(wait) = wait {
+            if rate_limiter.should_notify(&user, wait) {
+                // Round up, so that waiting the time given is always enough.
+                let seconds = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+                self.interface.send_notice(nick, format!("You're using commands too quickly, \
+                    please wait {} before trying again.",
+                    duration::format(time::Duration::from_secs(seconds))));
+            }
+            return true;
+        }
+        for chain in &chains {
+            rate_limiter.start(chain, &user, &channel);
+        }
+        return false;
+    }
+
+    /// Identifies the user of a command for cooldowns: by account if it is known, and otherwise
+    /// by `user@host`, so that changing nick doesn't escape a cooldown.
+    fn cooldown_identity(&self, mask: &irc::IrcMask, nick: &str) -> String {
+        if let Some(Some(account)) = self.interface.account(nick) {
+            return format!("account:{}", account.to_ascii_lowercase());
+        }
+        return match mask {
+            &irc::IrcMask::Full(ref full) => {
+                format!("host:{}@{}", full.user, full.host.to_ascii_lowercase())
+            },
+            _ => format!("nick:{}", self.casemapping().to_lower(nick)),
+        };
+    }
+
+    /// Expands aliases until the invocation names a command. Returns None if it names neither a
+    /// command nor an alias, or if aliases expand into other aliases too many times.
+    fn expand_aliases(&self, plugins: &client::PluginRegister,
+            mut invocation: Invocation, stage: usize) -> Option<Invocation> {
+        let aliases = self.interface.aliases().read().unwrap();
+        let resolved = aliases.resolve(&invocation.command, &invocation.args,
+            invocation.mask.nick().unwrap_or(""), &invocation.channel,
+            |command, args| plugins.find_command(command, args).is_some());
+        match resolved {
+            aliases::Resolved::Command(command, args) => {
+                invocation.command = command;
+                invocation.args = args;
+                return Some(invocation);
+            },
+            aliases::Resolved::Unknown(command) => {
+                invocation.command = command;
+                if stage == 0 {
+                    self.suggest_commands(plugins, &aliases, &invocation);
+                } else if let Some(nick) = invocation.mask.nick() {
+                    // Unlike the first command, a later one was certainly meant as a command.
+                    self.interface.send_notice(nick, format!("Unknown command `{}` at position {} \
+                        of the pipeline.", invocation.command, stage + 1));
+                }
+            },
+            aliases::Resolved::Empty => (),
+            aliases::Resolved::TooDeep(command) => {
+                if let Some(nick) = invocation.mask.nick() {
+                    self.interface.send_notice(nick, format!("Gave up on `{}` after expanding {} \
+                        aliases.", command, aliases::MAX_ALIAS_DEPTH));
+                }
+            },
+        }
+        return None;
+    }
+
+    /// Tells the user about commands similar to an unknown one they used, if the channel has
+    /// suggestions turned on. Only commands and aliases the user may use there are suggested.
+    fn suggest_commands(&self, plugins: &client::PluginRegister, aliases: &aliases::AliasTable,
+            invocation: &Invocation) {
+        let channel = &*invocation.channel;
+        let nick = match invocation.mask.nick() {
+            Some(v) => v,
+            None => return,
+        };
+        // Messages like `...` or `!!!` are more likely chat than mistyped commands.
+        let starts_alphanumeric = invocation.command.chars().next()
+            .map_or(false, |c| c.is_alphanumeric());
+        if !starts_alphanumeric || !self.state.suggest_commands(channel, self.casemapping()) {
+            return;
+        }
+
+        let mask = events::IrcMask::from_internal(&invocation.mask);
+        let usable = |descriptor: &commands::CommandDescriptor| {
+            self.interface.command_enabled(channel, plugins, descriptor)
+                && descriptor.availability.allows(invocation.context)
+                && self.interface.can_use_command(&mask, Some(channel), plugins, descriptor)
+        };
+        let mut candidates = Vec::new();
+        for command in plugins.unique_commands() {
+            let descriptor = &command.descriptor;
+            if !descriptor.name.contains(' ') && command.listener.is_some() && usable(descriptor) {
+                candidates.push(descriptor.name.clone());
+                candidates.extend(descriptor.aliases.iter().cloned());
+            }
+        }
+        for (name, expansion) in aliases.aliases() {
+            let mut words = expansion.split(' ').filter(|s| !s.is_empty());
+            let target = words.next().unwrap_or("");
+            let args = words.map(|s| s.to_string()).collect::<Vec<String>>();
+            if let Some((command, _)) = plugins.find_command(target, &args) {
+                if usable(&command.descriptor) {
+                    candidates.push(name.to_string());
+                }
+            }
+        }
+
+        let length = invocation.command.chars().count();
+        let max_distance = cmp::min(MAX_SUGGESTION_DISTANCE, cmp::max(1, length / 3));
+        let mut suggestions = candidates.into_iter()
+            .map(|name| (commands::edit_distance(&invocation.command, &name), name))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .collect::<Vec<(usize, String)>>();
+        if suggestions.is_empty() {
+            return;
+        }
+        suggestions.sort();
+        suggestions.dedup_by(|a, b| a.1 == b.1);
+        let prefix = self.state.display_prefix(channel, self.casemapping());
+        let names = suggestions.iter().take(MAX_SUGGESTIONS)
+            .map(|&(_, ref name)| format!("{}{}", prefix, name))
+            .collect::<Vec<String>>().join(", ");
+        self.interface.send_notice(nick, format!("Unknown command `{}`. Did you mean {}?",
+            invocation.command, names));
+    }
+
+    /// Checks whether a command runs on the heavy worker threads, because it or one of its
+    /// groups is marked heavy by its plugin or in `heavy_commands`.
+    fn is_heavy(&self, plugins: &client::PluginRegister, registered: &client::RegisteredCommand)
+            -> bool {
+        let heavy_commands = &self.state.workers.heavy_commands;
+        return plugins.command_chain(&registered.descriptor).into_iter().any(|descriptor| {
+            let path = commands::command_path(&descriptor.name);
+            descriptor.heavy || heavy_commands.iter().any(|c| commands::command_path(c) == path)
+        });
+    }
+
+    fn casemapping(&self) -> CaseMapping {
+        return self.state.state().read().unwrap().support.casemapping;
+    }
+
+    /// Checks whether a listener runs for a message in `channel`, by the channel's plugin
+    /// toggles. Listeners registered outside of any plugin always run.
+    fn listener_enabled<T>(&self, channel: Option<&str>, listener: &client::PluginListener<T>)
+            -> bool {
+        return match (channel, listener.plugin.as_ref()) {
+            (Some(channel), Some(plugin)) => self.interface.plugin_enabled(channel, plugin),
+            _ => true,
+        };
     }
 
     fn execute(&self, task: PluginThunk) -> Result<(), mpsc::SendError<PluginThunk>> {
-        self.workers_out.send(task)
+        self.workers_out.send(task, self.casemapping())
+    }
+}
+
+impl Drop for Dispatch {
+    fn drop(&mut self) {
+        // Stop the scheduler, bus and watchdog threads, so that they don't outlive a restart.
+        self.interface.scheduler().stop();
+        self.interface.bus().stop();
+        self.watchdog.stop();
+    }
+}
+
+/// A task for a worker, with the key of the tasks it must run in order with, if any.
+type QueuedTask = (PluginThunk, Option<u64>);
+
+/// Tasks with an order key which are running, and those parked behind them.
+type InFlightTasks = sync::Arc<ordering::InFlight<u64, PluginThunk>>;
+
+/// The queue of a pool of worker threads.
+#[derive(Clone)]
+struct WorkerQueues {
+    ordering: config::DeliveryOrder,
+    /// One queue shared by every worker in the pool.
+    sender: mpsc::Sender<QueuedTask>,
+    in_flight: InFlightTasks,
+}
+
+impl WorkerQueues {
+    /// Queues a task. Any free worker can take it, but if a task it must run in order with is
+    /// still running, it is parked instead, and run after that one by the same worker.
+    fn send(&self, task: PluginThunk, casemapping: CaseMapping)
+            -> Result<(), mpsc::SendError<PluginThunk>> {
+        let key = match self.ordering {
+            config::DeliveryOrder::None => None,
+            ordering => {
+                // Keys are hashes, so unrelated tasks only rarely end up waiting for each other.
+                let mut hasher = hash_map::DefaultHasher::new();
+                task.hash_order_key(ordering, casemapping, &mut hasher);
+                Some(hasher.finish())
+            },
+        };
+        let task = match key {
+            Some(key) => match self.in_flight.start(key, task) {
+                Some(task) => task,
+                None => return Ok(()),
+            },
+            None => task,
+        };
+        return self.sender.send((task, key)).map_err(|mpsc::SendError((task, _))| {
+            mpsc::SendError(task)
+        });
+    }
+}
+
+/// Starts `threads` worker threads, and returns the queue which feeds them.
+fn start_worker_pool(interface: &interface::IrcInterface, name: &str, threads: usize,
+        ordering: config::DeliveryOrder, watchdog: &sync::Arc<watchdog::Watchdog>)
+        -> WorkerQueues {
+    let time_budget = time::Duration::from_secs(interface.workers.time_budget_seconds);
+    let (sender, receiver) = mpsc::channel();
+    let receiver = sync::Arc::new(sync::Mutex::new(receiver));
+    let in_flight = sync::Arc::new(ordering::InFlight::new());
+    for _ in 0..threads {
+        let executor = PluginExecutor::new(interface.clone(), receiver.clone(),
+            in_flight.clone(), name, watchdog.clone(), time_budget);
+        executor.start_worker_thread();
+    }
+    return WorkerQueues {
+        ordering: ordering,
+        sender: sender,
+        in_flight: in_flight,
+    };
+}
+
+/// Starts a thread which sends scheduled jobs to the workers as they become due. Jobs for the
+/// handlers in `heavy_jobs` go to `heavy_out` if there are heavy workers.
+fn start_scheduler_thread(interface: interface::IrcInterface, state: client::Client,
+        workers_out: WorkerQueues, heavy_out: Option<WorkerQueues>) {
+    let r = thread::Builder::new().name("scheduler_thread".to_string()).spawn(move || {
+        while let Some(jobs) = interface.scheduler().wait_for_due() {
+            let plugins = state.plugins().read().unwrap();
+            for job in jobs {
+                let handler = match plugins.job_handlers.get(&job.handler) {
+                    Some(v) => v.clone(),
+                    None => {
+                        warn!("No job handler `{}` is registered. Cancelling job {}.",
+                            job.handler, job.id);
+                        interface.scheduler().cancel(job.id);
+                        continue;
+                    },
+                };
+                let casemapping = interface.state().read().unwrap().support.casemapping;
+                let queues = match heavy_out {
+                    Some(ref heavy_out) if interface.workers.heavy_jobs.contains(&job.handler) => {
+                        heavy_out
+                    },
+                    _ => &workers_out,
+                };
+                if let Err(_) = queues.send(PluginThunk::Job(handler, job), casemapping) {
+                    error!("Failed to send to workers_out from the scheduler. Exiting.");
+                    return;
+                }
+            }
+        }
+    });
+    if let Err(e) = r {
+        error!("Failed to start the scheduler thread! Scheduled jobs will not run! Error: {}", e);
+    }
+}
+
+/// Starts a thread which sends events published on the bus to the workers, once for each
+/// subscriber. Events on the topics in `heavy_topics` go to `heavy_out` if there are heavy
+/// workers.
+fn start_bus_thread(interface: interface::IrcInterface, state: client::Client,
+        workers_out: WorkerQueues, heavy_out: Option<WorkerQueues>) {
+    let r = thread::Builder::new().name("bus_thread".to_string()).spawn(move || {
+        while let Some(messages) = interface.bus().wait_for_events() {
+            let plugins = state.plugins().read().unwrap();
+            let casemapping = interface.state().read().unwrap().support.casemapping;
+            for message in messages {
+                let subscribers = match plugins.subscribers.get(&message.topic) {
+                    Some(v) => v,
+                    None => {
+                        debug!("Nothing is subscribed to `{}`. Dropping event.", message.topic);
+                        continue;
+                    },
+                };
+                let queues = match heavy_out {
+                    Some(ref heavy_out)
+                        if interface.workers.heavy_topics.contains(&message.topic) => heavy_out,
+                    _ => &workers_out,
+                };
+                for subscriber in subscribers {
+                    let thunk = PluginThunk::Bus(subscriber.clone(), message.clone());
+                    if let Err(_) = queues.send(thunk, casemapping) {
+                        error!("Failed to send to workers_out from the bus. Exiting.");
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    if let Err(e) = r {
+        error!("Failed to start the bus thread! Published events will not be delivered! Error: {}",
+            e);
+    }
+}
+
+/// The furthest edit distance at which a command is suggested in place of an unknown one.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+/// The most commands suggested in place of an unknown one.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The most commands which can be joined into one pipeline.
+const MAX_PIPELINE_STAGES: usize = 8;
+
+/// A command which was used, before it is parsed or checked. Commands are held as these while
+/// their permission check waits on a lookup of the sender's account.
+pub struct Invocation {
+    pub command: String,
+    pub channel: String,
+    pub context: events::MessageContext,
+    pub args: Vec<String>,
+    pub mask: irc::IrcMask,
+    pub msgid: Option<String>,
+}
+
+enum Prepared {
+    Ready(sync::Arc<client::RegisteredCommand>, events::CommandTransport),
+    /// The user lacks a permission the command requires.
+    Denied,
+    /// The command can't be used, and the user has been told why if needed.
+    Rejected,
+}
+
+/// Splits an invocation into the commands of a pipeline, as in `choose a, b, c | upper`, where
+/// the output of each command is added to the arguments of the next. `|` must be its own word.
+///
+/// A word of `\|` is passed on as a literal `|`, and in general one backslash is removed from a
+/// word made of backslashes followed by `|`.
+fn split_pipeline(invocation: &Invocation) -> Vec<Invocation> {
+    let mut stages = Vec::new();
+    let mut command = invocation.command.clone();
+    let mut args = Vec::new();
+    let mut words = invocation.args.iter();
+    loop {
+        match words.next() {
+            Some(word) if word != "|" => {
+                if word.starts_with('\\') && word.trim_left_matches('\\') == "|" {
+                    args.push(word[1..].to_string());
+                } else {
+                    args.push(word.clone());
+                }
+                continue;
+            },
+            end => {
+                stages.push(Invocation {
+                    command: command,
+                    channel: invocation.channel.clone(),
+                    context: invocation.context,
+                    args: args,
+                    mask: invocation.mask.clone(),
+                    msgid: invocation.msgid.clone(),
+                });
+                if end.is_none() {
+                    return stages;
+                }
+                command = words.next().cloned().unwrap_or_else(String::new);
+                args = Vec::new();
+            },
+        }
+    }
+}
+
+/// Parses a command's arguments using its `ArgSpec`, if it has one. Returns false, after
+/// telling the user the command's usage, if they don't match.
+fn parse_command_args(interface: &interface::IrcInterface, registered: &client::RegisteredCommand,
+        command_event: &mut events::CommandTransport) -> bool {
+    let spec = match registered.descriptor.args {
+        Some(ref v) => v,
+        None => return true,
+    };
+    let result = {
+        let state = interface.state().read().unwrap();
+        spec.parse(&command_event.args, &state.support)
+    };
+    match result {
+        Ok(parsed) => {
+            command_event.parsed = parsed;
+            return true;
+        },
+        Err(e) => {
+            if let Some(nick) = command_event.mask().nick() {
+                interface.send_notice(nick, format!("{} Usage: {}{} {}", e,
+                    interface.display_prefix(command_event.channel(), interface.casemapping()),
+                    registered.descriptor.name,
+                    registered.descriptor.usage.as_ref().map_or("", |s| &**s)));
+            }
+            return false;
+        },
     }
 }
 
 /// TODO: Better name for this
 enum PluginThunk {
-    Command(sync::Arc<client::CommandListener>, events::CommandTransport),
-    Message(sync::Arc<client::MessageListener>, events::MessageTransport),
-    Ctcp(sync::Arc<client::CtcpListener>, events::CtcpTransport),
+    Command(sync::Arc<client::RegisteredCommand>, events::CommandTransport),
+    /// Commands to run in order, each given the output of the one before.
+    Pipeline(Vec<(sync::Arc<client::RegisteredCommand>, events::CommandTransport)>),
+    Message(sync::Arc<client::PluginListener<client::MessageListener>>,
+        events::MessageTransport),
+    Ctcp(sync::Arc<client::PluginListener<client::CtcpListener>>, events::CtcpTransport),
+    Job(sync::Arc<client::PluginListener<client::JobListener>>, scheduler::Job),
+    Bus(sync::Arc<client::PluginListener<client::BusListener>>, bus::BusMessage),
 }
 
 impl PluginThunk {
     fn execute(self, interface: &interface::IrcInterface) {
+        let description = self.to_string();
         match self {
-            PluginThunk::Command(closure, event) => {
-                (*closure)(&events::CommandEvent::new(interface, &event));
+            PluginThunk::Command(command, event) => {
+                run_command(interface, &command, &events::CommandEvent::new(interface, &event));
+            },
+            PluginThunk::Pipeline(stages) => {
+                let last = stages.len() - 1;
+                let mut input: Option<Vec<String>> = None;
+                for (index, (command, mut event)) in stages.into_iter().enumerate() {
+                    if let Some(lines) = input.take() {
+                        event.args.extend(lines.iter().flat_map(|line| line.split(' '))
+                            .filter(|s| !s.is_empty()).map(|s| s.to_string()));
+                        if !parse_command_args(interface, &command, &mut event) {
+                            return;
+                        }
+                    }
+                    if index == last {
+                        run_command(interface, &command,
+                            &events::CommandEvent::new(interface, &event));
+                        return;
+                    }
+                    let output = cell::RefCell::new(Vec::new());
+                    if !run_command(interface, &command,
+                            &events::CommandEvent::capturing(interface, &event, &output)) {
+                        return;
+                    }
+                    let lines = output.into_inner();
+                    // Stop if the command failed, or sent its output itself.
+                    if lines.is_empty() {
+                        return;
+                    }
+                    input = Some(lines);
+                }
             },
             PluginThunk::Message(closure, event) => {
-                (*closure)(&events::MessageEvent::new(interface, &event));
+                run_listener(interface, &closure, &description,
+                    || (closure.listener)(&events::MessageEvent::new(interface, &event)));
             },
             PluginThunk::Ctcp(closure, event) => {
-                (*closure)(&events::CtcpEvent::new(interface, &event));
+                run_listener(interface, &closure, &description,
+                    || (closure.listener)(&events::CtcpEvent::new(interface, &event)));
+            },
+            PluginThunk::Job(closure, job) => {
+                run_listener(interface, &closure, &description,
+                    || (closure.listener)(&events::JobEvent::new(interface, &job)));
+            },
+            PluginThunk::Bus(closure, message) => {
+                run_listener(interface, &closure, &description,
+                    || (closure.listener)(&events::BusEvent::new(interface, &message)));
             },
         }
     }
 }
 
+/// Runs a listener other than a command, unless its plugin has been disabled by the circuit
+/// breaker.
+fn run_listener<T, F>(interface: &interface::IrcInterface, listener: &client::PluginListener<T>,
+        description: &str, f: F) where F: FnOnce() {
+    let plugin = listener.plugin.as_ref().map(|s| &**s);
+    if plugin.map_or(false, |plugin| interface.plugin_disabled(plugin)) {
+        debug!("Skipping the {}, since its plugin is disabled.", description);
+        return;
+    }
+    interface.run_plugin_code(plugin, description, f);
+}
+
+/// Runs a command's listener, telling the user about any error it returns. Panics are caught, so
+/// that they count towards disabling the command's plugin rather than taking down the worker.
+///
+/// Returns whether the command succeeded.
+fn run_command(interface: &interface::IrcInterface, command: &client::RegisteredCommand,
+        event: &events::CommandEvent) -> bool {
+    let listener = match command.listener {
+        Some(ref v) => v,
+        None => return false,
+    };
+    let descriptor = &command.descriptor;
+    let description = format!("command `{}` from plugin {}", descriptor.name,
+        descriptor.plugin_name());
+    let result = interface.run_plugin_code(descriptor.plugin.as_ref().map(|s| &**s),
+        &description, || listener(event));
+    let error = match result {
+        Some(Ok(())) => return true,
+        Some(Err(e)) => {
+            debug!("Command `{}` from plugin {} failed: {}", descriptor.name,
+                descriptor.plugin_name(), e);
+            e.to_string()
+        },
+        None => "Something went wrong.".to_string(),
+    };
+    event.reply_error(format!("{}: {}", descriptor.name, error));
+    return false;
+}
+
+impl PluginThunk {
+    /// Gets how long this should take at most, given the default time budget.
+    fn time_budget(&self, default: time::Duration) -> time::Duration {
+        return match self {
+            &PluginThunk::Command(ref command, _) => {
+                command.descriptor.time_budget.unwrap_or(default)
+            },
+            &PluginThunk::Pipeline(ref stages) => {
+                stages.iter().fold(time::Duration::from_secs(0), |total, &(ref command, _)| {
+                    total + command.descriptor.time_budget.unwrap_or(default)
+                })
+            },
+            _ => default,
+        };
+    }
+}
+
+impl PluginThunk {
+    /// Hashes what decides which tasks must run in order with this one.
+    fn hash_order_key<H: Hasher>(&self, ordering: config::DeliveryOrder,
+            casemapping: CaseMapping, hasher: &mut H) {
+        if ordering == config::DeliveryOrder::Listener {
+            // Each listener is identified by where it is stored.
+            let address = match self {
+                &PluginThunk::Command(ref command, _) => address_of(command),
+                &PluginThunk::Pipeline(ref stages) => address_of(&stages[0].0),
+                &PluginThunk::Message(ref listener, _) => address_of(listener),
+                &PluginThunk::Ctcp(ref listener, _) => address_of(listener),
+                &PluginThunk::Job(ref listener, _) => address_of(listener),
+                &PluginThunk::Bus(ref listener, _) => address_of(listener),
+            };
+            address.hash(hasher);
+            return;
+        }
+        let channel = match self {
+            &PluginThunk::Command(_, ref event) => event.channel(),
+            &PluginThunk::Pipeline(ref stages) => stages[0].1.channel(),
+            &PluginThunk::Message(_, ref event) => event.channel().unwrap_or(""),
+            &PluginThunk::Ctcp(_, ref event) => event.channel(),
+            // Jobs don't belong to a channel, so each handler's jobs are kept in order instead.
+            &PluginThunk::Job(_, ref job) => &job.handler,
+            // Likewise, events on each topic are kept in order.
+            &PluginThunk::Bus(_, ref message) => &message.topic,
+        };
+        for c in channel.chars() {
+            casemapping.to_lower_char(c).hash(hasher);
+        }
+    }
+}
+
+fn address_of<T>(value: &sync::Arc<T>) -> usize {
+    return &**value as *const T as usize;
+}
+
 impl fmt::Display for PluginThunk {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        fmt.write_str(match self {
-            &PluginThunk::Command(..) => "command",
-            &PluginThunk::Message(..) => "message",
-            &PluginThunk::Ctcp(..) => "ctcp",
-        })
+        match self {
+            &PluginThunk::Command(ref command, _) => write!(fmt, "command `{}` from plugin {}",
+                command.descriptor.name, command.descriptor.plugin_name()),
+            &PluginThunk::Pipeline(ref stages) => write!(fmt, "pipeline `{}`", stages.iter()
+                .map(|&(ref command, _)| &*command.descriptor.name)
+                .collect::<Vec<&str>>().join(" | ")),
+            &PluginThunk::Message(ref listener, ref event) => write!(fmt,
+                "listener for {} from plugin {}", event.command(), listener.plugin_name()),
+            &PluginThunk::Ctcp(ref listener, ref event) => write!(fmt,
+                "ctcp listener for {} from plugin {}", event.command(), listener.plugin_name()),
+            &PluginThunk::Job(ref handler, ref job) => write!(fmt, "job `{}` from plugin {}",
+                job.handler, handler.plugin_name()),
+            &PluginThunk::Bus(ref subscriber, ref message) => write!(fmt,
+                "subscriber to `{}` from plugin {}", message.topic, subscriber.plugin_name()),
+        }
     }
 }
 
 
 struct PluginExecutor {
     interface: interface::IrcInterface,
-    data_in: sync::Arc<sync::Mutex<mpsc::Receiver<PluginThunk>>>,
+    data_in: sync::Arc<sync::Mutex<mpsc::Receiver<QueuedTask>>>,
+    in_flight: InFlightTasks,
+    /// Name of the thread, which tells which pool it is in.
+    name: String,
+    watchdog: sync::Arc<watchdog::Watchdog>,
+    /// How long listeners and commands without their own time budget should take at most.
+    time_budget: time::Duration,
     active: bool,
 }
 
 impl PluginExecutor {
     fn new(interface: interface::IrcInterface,
-            data_in: sync::Arc<sync::Mutex<mpsc::Receiver<PluginThunk>>>)
-            -> PluginExecutor {
+            data_in: sync::Arc<sync::Mutex<mpsc::Receiver<QueuedTask>>>,
+            in_flight: InFlightTasks, name: &str, watchdog: sync::Arc<watchdog::Watchdog>,
+            time_budget: time::Duration) -> PluginExecutor {
         return PluginExecutor {
             interface: interface,
             data_in: data_in,
+            in_flight: in_flight,
+            name: name.to_string(),
+            watchdog: watchdog,
+            time_budget: time_budget,
             active: true,
         };
     }
@@ -224,11 +1043,14 @@ impl PluginExecutor {
                 lock.recv()
             };
             match message {
-                Ok(next) => {
-                    let desc = format!("{}", &next);
-                    debug!("Executing {}", desc);
-                    next.execute(&self.interface);
-                    debug!("Done executing {}", desc);
+                Ok((next, key)) => {
+                    self.run(next);
+                    if let Some(key) = key {
+                        // Run whatever was parked behind this while it ran, in order.
+                        while let Some(next) = self.in_flight.finish(&key) {
+                            self.run(next);
+                        }
+                    }
                 },
                 Err(_) => {
                     self.active = false;
@@ -238,8 +1060,28 @@ impl PluginExecutor {
         }
     }
 
+    fn run(&self, next: PluginThunk) {
+        let desc = format!("{}", &next);
+        let time_budget = next.time_budget(self.time_budget);
+        debug!("Executing {}", desc);
+        let token = self.watchdog.start(&desc);
+        let interface = &self.interface;
+        // Panics from plugins are caught as they run, so that the right plugin is blamed. This
+        // only catches those from the dispatch code itself.
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| next.execute(interface)));
+        if let Err(payload) = result {
+            error!("Running the {} panicked: {}", desc, breaker::panic_message(&payload));
+        }
+        let elapsed = self.watchdog.finish(token);
+        if elapsed > time_budget {
+            warn!("The {} took {}, longer than its time budget of {}.", desc,
+                duration::format(elapsed), duration::format(time_budget));
+        }
+        debug!("Done executing {}", desc);
+    }
+
     fn start_worker_thread(mut self) {
-        let r = thread::Builder::new().name("worker_thread".to_string()).spawn(move ||
+        let r = thread::Builder::new().name(self.name.clone()).spawn(move ||
             self.worker_loop());
         if let Err(e) = r {
             error!("Failed to start new worker thread! Plugins will no longer have a full set of \
@@ -252,8 +1094,47 @@ impl Drop for PluginExecutor {
     fn drop(&mut self) {
         if self.active {
             warn!("Worker panicked!");
-            PluginExecutor::new(self.interface.clone(), self.data_in.clone(),)
+            PluginExecutor::new(self.interface.clone(), self.data_in.clone(),
+                self.in_flight.clone(), &self.name, self.watchdog.clone(), self.time_budget)
                 .start_worker_thread();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::events::MessageContext;
+    use irc;
+    use super::{split_pipeline, Invocation};
+
+    fn stages(line: &str) -> Vec<(String, Vec<String>)> {
+        let mut words = line.split(' ').map(|s| s.to_string());
+        let invocation = Invocation {
+            command: words.next().unwrap(),
+            channel: "#c".to_string(),
+            context: MessageContext::Channel,
+            args: words.collect(),
+            mask: irc::IrcMask::Nonexistent,
+            msgid: None,
+        };
+        return split_pipeline(&invocation).into_iter().map(|s| (s.command, s.args)).collect();
+    }
+
+    fn stage(command: &str, args: &[&str]) -> (String, Vec<String>) {
+        (command.to_string(), args.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn pipelines_split_on_bars() {
+        assert_eq!(stages("choose a b | upper | rev x"),
+            vec![stage("choose", &["a", "b"]), stage("upper", &[]), stage("rev", &["x"])]);
+        assert_eq!(stages("choose a|b"), vec![stage("choose", &["a|b"])]);
+        assert_eq!(stages("choose a |"), vec![stage("choose", &["a"]), stage("", &[])]);
+    }
+
+    #[test]
+    fn escaped_bars_are_literal() {
+        assert_eq!(stages(r"say a \| b"), vec![stage("say", &["a", "|", "b"])]);
+        assert_eq!(stages(r"say \\| \x"), vec![stage("say", &[r"\|", r"\x"])]);
+    }
+}
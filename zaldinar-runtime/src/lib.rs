@@ -18,7 +18,7 @@ extern crate generated_plugins_crate;
 macro_rules! regex {
     ($s:expr) => ({
         lazy_static! {
-            static ref REGEX: ::regex::Regex = ::regex::Regex::new("...").unwrap();
+            static ref REGEX: ::regex::Regex = ::regex::Regex::new($s).unwrap();
         }
         &REGEX
     })
@@ -36,6 +36,10 @@ pub use startup::run_with_plugins;
 
 pub mod startup;
 pub mod dispatch;
+mod accounts;
+mod ratelimit;
+mod ordering;
+mod watchdog;
 mod plugins;
 #[cfg(feature = "binary-filewatch")]
 mod filewatch;
@@ -0,0 +1,80 @@
+use std::collections;
+use std::collections::hash_map;
+use std::hash::Hash;
+use std::sync;
+
+/// Keeps tasks which must run in order from running at once, without tying them to one worker
+/// thread. Tasks are grouped by a key. While a task for a key is running, later ones with the
+/// same key are parked here rather than queued, and are handed back one at a time as the running
+/// one finishes, so any worker in the pool can run any key.
+pub struct InFlight<K, T> {
+    /// The keys with a task running, and the tasks waiting behind it.
+    parked: sync::Mutex<collections::HashMap<K, collections::VecDeque<T>>>,
+}
+
+impl<K: Hash + Eq, T> InFlight<K, T> {
+    pub fn new() -> InFlight<K, T> {
+        return InFlight {
+            parked: sync::Mutex::new(collections::HashMap::new()),
+        };
+    }
+
+    /// Returns `task` if nothing else with `key` is running, marking `key` as running. Otherwise
+    /// parks the task until the ones before it have finished, and returns None.
+    pub fn start(&self, key: K, task: T) -> Option<T> {
+        let mut parked = self.parked.lock().unwrap();
+        return match parked.entry(key) {
+            hash_map::Entry::Occupied(mut entry) => {
+                entry.get_mut().push_back(task);
+                None
+            },
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(collections::VecDeque::new());
+                Some(task)
+            },
+        };
+    }
+
+    /// Marks the running task with `key` as finished, and returns the next one parked behind it,
+    /// which the caller must then run and finish in turn. Returns None, and stops tracking
+    /// `key`, if there is no such task.
+    pub fn finish(&self, key: &K) -> Option<T> {
+        let mut parked = self.parked.lock().unwrap();
+        let next = match parked.get_mut(key) {
+            Some(waiting) => waiting.pop_front(),
+            None => None,
+        };
+        if next.is_none() {
+            parked.remove(key);
+        }
+        return next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InFlight;
+
+    #[test]
+    fn tasks_with_the_same_key_wait_their_turn() {
+        let in_flight = InFlight::new();
+        assert_eq!(in_flight.start("#a", 1), Some(1));
+        assert_eq!(in_flight.start("#a", 2), None);
+        assert_eq!(in_flight.start("#a", 3), None);
+        assert_eq!(in_flight.finish(&"#a"), Some(2));
+        assert_eq!(in_flight.finish(&"#a"), Some(3));
+        assert_eq!(in_flight.finish(&"#a"), None);
+        assert_eq!(in_flight.start("#a", 4), Some(4));
+    }
+
+    #[test]
+    fn different_keys_run_at_once() {
+        let in_flight = InFlight::new();
+        assert_eq!(in_flight.start("#a", 1), Some(1));
+        assert_eq!(in_flight.start("#b", 2), Some(2));
+        assert_eq!(in_flight.start("#a", 3), None);
+        assert_eq!(in_flight.finish(&"#b"), None);
+        assert_eq!(in_flight.finish(&"#a"), Some(3));
+        assert_eq!(in_flight.finish(&"#a"), None);
+    }
+}
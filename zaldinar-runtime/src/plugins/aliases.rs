@@ -0,0 +1,86 @@
+use core::aliases::{Alias, AliasTable};
+use core::args::{ArgSpec, ArgKind};
+use core::commands::CommandDescriptor;
+use core::permissions::ADMIN_PERMISSION;
+use client::PluginRegister;
+use events::CommandEvent;
+
+fn save(event: &CommandEvent, aliases: &AliasTable) {
+    if let Err(e) = aliases.save() {
+        error!("Failed to save aliases: {}", e);
+        event.client.reply_notice(event, "Failed to save aliases, so the change will be lost on \
+            restart.");
+    }
+}
+
+fn alias(event: &CommandEvent) {
+    let name = event.parsed.text("name").unwrap();
+    let expansion = event.parsed.text("expansion").unwrap();
+    {
+        let plugins = event.client.plugins().read().unwrap();
+        if plugins.find_command(name, &[] as &[String]).is_some() {
+            event.client.reply_notice(event, format!("`{}` is already a command.", name));
+            return;
+        }
+    }
+    let mut aliases = event.client.aliases().write().unwrap();
+    aliases.add(Alias {
+        name: name.to_string(),
+        expansion: expansion.to_string(),
+        added_by: event.mask().nick().unwrap_or("*unknown*").to_string(),
+    });
+    save(event, &aliases);
+    event.client.reply_notice(event, format!("`{}` now expands to `{}`.", name, expansion));
+}
+
+fn unalias(event: &CommandEvent) {
+    let name = event.parsed.text("name").unwrap();
+    let mut aliases = event.client.aliases().write().unwrap();
+    match aliases.remove(name) {
+        Some(_) => {
+            save(event, &aliases);
+            event.client.reply_notice(event, format!("Removed alias `{}`.", name));
+        },
+        None if aliases.is_configured(name) => event.client.reply_notice(event,
+            format!("`{}` is set in the configuration, and can't be removed here.", name)),
+        None => event.client.reply_notice(event, format!("There is no alias `{}`.", name)),
+    }
+}
+
+fn aliases(event: &CommandEvent) {
+    let aliases = event.client.aliases().read().unwrap();
+    if let Some(name) = event.parsed.text("name") {
+        match aliases.get(name) {
+            Some(expansion) => event.client.reply_notice(event,
+                format!("{}: {}", name, expansion)),
+            None => event.client.reply_notice(event, format!("There is no alias `{}`.", name)),
+        }
+        return;
+    }
+    let list = aliases.aliases();
+    if list.is_empty() {
+        event.client.reply_notice(event, "There are no aliases.");
+        return;
+    }
+    let names = list.iter().map(|&(name, _)| name).collect::<Vec<&str>>().join(", ");
+    event.client.reply_notice(event, format!("Aliases: {}", names));
+}
+
+pub fn register(register: &mut PluginRegister) {
+    register.register_described_command(CommandDescriptor::new("alias")
+        .summary("Creates a command which expands to another. $1 to $9 and $* are replaced by \
+            arguments, and $nick and $channel by who used it and where.")
+        .args(ArgSpec::new()
+            .required("name", ArgKind::Word)
+            .required("expansion", ArgKind::Rest))
+        .example("cake randomcake")
+        .example("greet admin say $channel Welcome, $1!")
+        .permission(ADMIN_PERMISSION), alias);
+    register.register_described_command(CommandDescriptor::new("unalias")
+        .summary("Removes an alias.")
+        .args(ArgSpec::new().required("name", ArgKind::Word))
+        .permission(ADMIN_PERMISSION), unalias);
+    register.register_described_command(CommandDescriptor::new("aliases")
+        .summary("Lists aliases, or shows what one expands to.")
+        .args(ArgSpec::new().optional("name", ArgKind::Word)), aliases);
+}
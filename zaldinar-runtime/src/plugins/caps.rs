@@ -0,0 +1,72 @@
+use std::ascii::AsciiExt;
+
+use client::PluginRegister;
+use events::MessageEvent;
+
+/// IRCv3 capabilities which are requested whenever the server supports them.
+const WANTED_CAPABILITIES: &'static [&'static str] = &[
+    "account-notify",
+    "account-tag",
+    "batch",
+    "extended-join",
+    "labeled-response",
+    "message-tags",
+];
+
+fn on_cap(event: &MessageEvent) {
+    // `CAP <target> <subcommand> [*] :<capabilities>`, where `*` means more lines follow.
+    if event.args.len() < 3 {
+        return;
+    }
+    let subcommand = event.args[1].to_ascii_uppercase();
+    let (more_follow, list_start) = if event.args[2] == "*" { (true, 3) } else { (false, 2) };
+    let capabilities = event.args[list_start..].iter()
+        .map(|s| s.trim_left_matches(':'))
+        .filter(|s| !s.is_empty())
+        // CAP LS 302 may give values, as in `sasl=PLAIN`.
+        .map(|s| s.split('=').next().unwrap().to_string());
+
+    match &*subcommand {
+        "LS" => {
+            let mut state = event.client.state().write().unwrap();
+            state.available_capabilities.extend(capabilities);
+            if more_follow {
+                return;
+            }
+            let wanted = WANTED_CAPABILITIES.iter()
+                .filter(|c| state.available_capabilities.iter().any(|a| a == *c))
+                .map(|c| *c)
+                .collect::<Vec<&str>>();
+            if wanted.is_empty() {
+                event.client.send_raw("CAP END".to_string());
+            } else {
+                event.client.send_raw(format!("CAP REQ :{}", wanted.join(" ")));
+            }
+        },
+        "ACK" => {
+            {
+                let mut state = event.client.state().write().unwrap();
+                for capability in capabilities {
+                    if capability.starts_with("-") {
+                        state.capabilities.retain(|c| *c != capability[1..]);
+                    } else {
+                        state.capabilities.push(capability);
+                    }
+                }
+            }
+            if !more_follow {
+                event.client.send_raw("CAP END".to_string());
+            }
+        },
+        "NAK" => {
+            if !more_follow {
+                event.client.send_raw("CAP END".to_string());
+            }
+        },
+        _ => (),
+    }
+}
+
+pub fn register(register: &mut PluginRegister) {
+    register.register_tracking("cap", on_cap);
+}
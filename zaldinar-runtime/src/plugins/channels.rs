@@ -0,0 +1,95 @@
+use std::ascii::AsciiExt;
+
+use core::args::{ArgSpec, ArgKind};
+use core::channel_settings::ToggleKind;
+use core::commands::{self, CommandDescriptor};
+use core::permissions::ADMIN_PERMISSION;
+use client::PluginRegister;
+use events::CommandEvent;
+
+fn toggle(event: &CommandEvent, enabled: bool) {
+    let channel = event.parsed.text("channel").unwrap_or(event.channel());
+    let name = event.parsed.text("name").unwrap();
+    let kind = match &*event.parsed.text("kind").unwrap().to_ascii_lowercase() {
+        "plugin" => ToggleKind::Plugin,
+        "command" => ToggleKind::Command,
+        other => {
+            event.client.reply_notice(event, format!("Unknown kind `{}`, expected `plugin` or \
+                `command`.", other));
+            return;
+        },
+    };
+
+    let exists = {
+        let plugins = event.client.plugins().read().unwrap();
+        match kind {
+            ToggleKind::Plugin => plugins.has_plugin(name),
+            ToggleKind::Command => {
+                let path = commands::command_path(name);
+                plugins.commands.contains_key(&path)
+            },
+        }
+    };
+    if !exists {
+        event.client.reply_notice(event, format!("There is no {} named `{}`.", kind, name));
+        return;
+    }
+
+    let casemapping = event.client.state().read().unwrap().support.casemapping;
+    let mut settings = event.client.channel_settings().write().unwrap();
+    settings.set(channel, kind, name, enabled, casemapping);
+    if let Err(e) = settings.save() {
+        error!("Failed to save channel settings: {}", e);
+        event.client.reply_notice(event, "Failed to save channel settings, so the change will be \
+            lost on restart.");
+    }
+    event.client.reply_notice(event, format!("{} {} {} in {}.",
+        if enabled { "Enabled" } else { "Disabled" }, kind, name, channel));
+}
+
+fn enable(event: &CommandEvent) {
+    toggle(event, true);
+}
+
+fn disable(event: &CommandEvent) {
+    toggle(event, false);
+}
+
+fn status(event: &CommandEvent) {
+    let channel = event.parsed.text("channel").unwrap_or(event.channel());
+    let casemapping = event.client.state().read().unwrap().support.casemapping;
+    let lines = event.client.channel_settings().read().unwrap().describe(channel, casemapping);
+    if lines.is_empty() {
+        event.client.reply_notice(event, format!("Everything is enabled in {}.", channel));
+    }
+    for line in lines {
+        event.client.reply_notice(event, format!("[{}] {}", channel, line));
+    }
+}
+
+fn toggle_args() -> ArgSpec {
+    return ArgSpec::new()
+        .optional("channel", ArgKind::Channel)
+        .required("kind", ArgKind::Word)
+        .required("name", ArgKind::Rest);
+}
+
+pub fn register(register: &mut PluginRegister) {
+    register.register_group(CommandDescriptor::new("channel")
+        .summary("Enables and disables plugins and commands in channels.")
+        .category("admin")
+        .permission(ADMIN_PERMISSION));
+    register.register_described_command(CommandDescriptor::new("channel enable")
+        .summary("Enables a plugin or command in a channel.")
+        .args(toggle_args())
+        .usage("[channel] <plugin|command> <name>")
+        .example("#zaldinar plugin brainfuck"), enable);
+    register.register_described_command(CommandDescriptor::new("channel disable")
+        .summary("Disables a plugin or command in a channel.")
+        .args(toggle_args())
+        .usage("[channel] <plugin|command> <name>")
+        .example("#zaldinar command admin say"), disable);
+    register.register_described_command(CommandDescriptor::new("channel status")
+        .summary("Shows what is enabled and disabled in a channel.")
+        .args(ArgSpec::new().optional("channel", ArgKind::Channel)), status);
+}
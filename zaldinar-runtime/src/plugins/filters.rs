@@ -0,0 +1,52 @@
+use core::args::{ArgSpec, ArgKind};
+use core::commands::CommandDescriptor;
+use client::PluginRegister;
+use events::CommandEvent;
+
+fn text<'a>(event: &'a CommandEvent) -> &'a str {
+    event.parsed.text("text").unwrap()
+}
+
+fn upper(event: &CommandEvent) {
+    event.reply(text(event).to_uppercase());
+}
+
+fn lower(event: &CommandEvent) {
+    event.reply(text(event).to_lowercase());
+}
+
+fn reverse(event: &CommandEvent) {
+    event.reply(text(event).chars().rev().collect::<String>());
+}
+
+fn rot13(event: &CommandEvent) {
+    let rotated = text(event).chars().map(|c| match c {
+        'a'...'m' | 'A'...'M' => ((c as u8) + 13) as char,
+        'n'...'z' | 'N'...'Z' => ((c as u8) - 13) as char,
+        _ => c,
+    }).collect::<String>();
+    event.reply(rotated);
+}
+
+fn count(event: &CommandEvent) {
+    let text = text(event);
+    event.reply(format!("{} words, {} characters", text.split_whitespace().count(),
+        text.chars().count()));
+}
+
+fn filter(name: &str, summary: &str) -> CommandDescriptor {
+    return CommandDescriptor::new(name)
+        .summary(summary)
+        .args(ArgSpec::new().required("text", ArgKind::Rest))
+        .category("text");
+}
+
+pub fn register(register: &mut PluginRegister) {
+    register.register_described_command(filter("upper", "Converts text to upper case.")
+        .example("hello world"), upper);
+    register.register_described_command(filter("lower", "Converts text to lower case."), lower);
+    register.register_described_command(filter("reverse", "Reverses text."), reverse);
+    register.register_described_command(filter("rot13", "Applies ROT13 to text."), rot13);
+    register.register_described_command(filter("count", "Counts the words and characters in \
+        text.").alias("wc"), count);
+}
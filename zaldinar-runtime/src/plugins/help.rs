@@ -1,34 +1,128 @@
+use std::ascii::AsciiExt;
+use std::collections;
+
+use core::commands::{Availability, CommandDescriptor};
 use client::PluginRegister;
 use events::CommandEvent;
 
+/// Checks whether the user may use a command in the current channel, and so should be told about
+/// it.
+fn can_see(event: &CommandEvent, plugins: &PluginRegister, descriptor: &CommandDescriptor)
+        -> bool {
+    return event.client.can_use_command(event.mask(), Some(event.channel()), plugins, descriptor)
+        && event.client.command_enabled(event.channel(), plugins, descriptor);
+}
+
+/// Gets the descriptors of every top level command the user may use in the current channel,
+/// grouped by category.
+fn visible_commands<'a>(event: &CommandEvent, plugins: &'a PluginRegister)
+        -> collections::BTreeMap<String, Vec<&'a CommandDescriptor>> {
+    let mut categories = collections::BTreeMap::new();
+    for command in plugins.unique_commands() {
+        let descriptor = &command.descriptor;
+        if descriptor.name.contains(' ') {
+            continue;
+        }
+        if can_see(event, plugins, descriptor) {
+            categories.entry(descriptor.category_name().to_ascii_lowercase())
+                .or_insert_with(Vec::new).push(descriptor);
+        }
+    }
+    return categories;
+}
+
+fn command_help(event: &CommandEvent, plugins: &PluginRegister,
+        descriptor: &CommandDescriptor) {
+    let prefix = event.client.display_prefix(event.channel(), event.client.casemapping());
+    match descriptor.summary {
+        Some(ref summary) => {
+            event.client.reply_notice(event, format!("{}: {}", descriptor.name, summary));
+        },
+        None => event.client.reply_notice(event, format!("{}: No description available.",
+            descriptor.name)),
+    }
+    if let Some(ref usage) = descriptor.usage {
+        event.client.reply_notice(event, format!("Usage: {}{} {}", prefix, descriptor.name,
+            usage));
+    }
+    for example in &descriptor.examples {
+        event.client.reply_notice(event, format!("Example: {}{} {}", prefix, descriptor.name,
+            example));
+    }
+    if !descriptor.aliases.is_empty() {
+        event.client.reply_notice(event, format!("Aliases: {}", descriptor.aliases.join(", ")));
+    }
+    let subcommands = plugins.subcommands(&descriptor.name);
+    if !subcommands.is_empty() {
+        let names = subcommands.iter().map(|c| c.descriptor.name.rsplit(' ').next().unwrap())
+            .collect::<Vec<&str>>().join(", ");
+        event.client.reply_notice(event, format!("Subcommands: {} (use `{}help {} <subcommand>` \
+            for details)", names, prefix, descriptor.name));
+    }
+    if descriptor.availability != Availability::Anywhere {
+        event.client.reply_notice(event, format!("Can only be used {}.",
+            descriptor.availability));
+    }
+    if let Some(permission) = event.client.command_permission(plugins, descriptor) {
+        event.client.reply_notice(event, format!("Requires the `{}` permission.", permission));
+    }
+}
+
 fn help(event: &CommandEvent) {
-    {
-        let plugins = event.client.plugins().read().unwrap();
-        // TODO: `.map(|s| &**s)` is a workaround for changes made in
-        // https://github.com/rust-lang/rust/pull/25162 - it should be removed when (if) it
-        // becomes no longer required
-        let commands = plugins.commands.keys().map(|s| &**s).collect::<Vec<_>>().join(", ");
-
-        event.client.reply_notice(event, format!("Available commands: {}", commands));
-        if event.client.is_mask_admin(event.mask()) {
-            let plugins = event.client.plugins().read().unwrap();
-            // TODO: `.map(|s| &**s)` is a workaround for changes made in
-            // https://github.com/rust-lang/rust/pull/25162 - it should be removed when (if) it
-            // becomes no longer required
-            let admin_commands = plugins.admin_commands.keys().map(|s| &**s)
-                .collect::<Vec<_>>().join(", ");
-
-            event.client.reply_notice(event, format!("Admin commands: {}", admin_commands));
+    let plugins = event.client.plugins().read().unwrap();
+    let categories = visible_commands(event, &plugins);
+
+    if let Some(first) = event.args.first() {
+        let prefixes = event.client.command_prefixes(event.channel(), event.client.casemapping());
+        let command = prefixes.iter().filter(|prefix| first.starts_with(**prefix))
+            .max_by_key(|prefix| prefix.len())
+            .map_or(&**first, |prefix| &first[prefix.len()..]);
+        let rest = &event.args[1..];
+        let topic = event.args.join(" ").to_ascii_lowercase();
+        match plugins.find_command(command, rest) {
+            Some((command, path_length)) if path_length == rest.len()
+                    && can_see(event, &plugins, &command.descriptor) => {
+                command_help(event, &plugins, &command.descriptor);
+            },
+            _ => match categories.get(&topic) {
+                Some(descriptors) => for descriptor in descriptors {
+                    match descriptor.summary {
+                        Some(ref summary) => event.client.reply_notice(event,
+                            format!("{}: {}", descriptor.name, summary)),
+                        None => event.client.reply_notice(event, descriptor.name.clone()),
+                    }
+                },
+                None => event.client.reply_notice(event,
+                    format!("No command or category named `{}`.", topic)),
+            },
         }
+        return;
+    }
+
+    for (category, descriptors) in &categories {
+        let names = descriptors.iter().map(|d| &*d.name).collect::<Vec<&str>>().join(", ");
+        event.client.reply_notice(event, format!("{}: {}", category, names));
     }
     {
         let state = event.client.state().read().unwrap();
+        let channel = event.channel();
+        let casemapping = state.support.casemapping;
+        let prefix = event.client.display_prefix(channel, casemapping);
+        let mut forms = event.client.command_prefixes(channel, casemapping).iter()
+            .map(|prefix| format!("`{}command_name`", prefix)).collect::<Vec<String>>();
+        if event.client.nick_addressing(channel, casemapping) {
+            forms.push(format!("`{}, command_name`", state.nick));
+        }
         event.client.reply_notice(event,
-            format!("Use a command with `{}command_name` or `{}, command_name`",
-                event.client.command_prefix, state.nick));
+            format!("Use a command with {}, and `{}help <command or category>` for details. \
+                Join commands with ` | ` to give the output of one to the next.",
+                forms.join(" or "), prefix));
     }
 }
 
 pub fn register(register: &mut PluginRegister) {
-    register.register_command("help", help);
+    register.register_described_command(CommandDescriptor::new("help")
+        .summary("Lists commands, or describes a command or category.")
+        .usage("[command or category]")
+        .alias("commands"), help);
 }
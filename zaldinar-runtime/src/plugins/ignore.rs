@@ -0,0 +1,104 @@
+use core::args::{ArgSpec, ArgKind};
+use core::commands::CommandDescriptor;
+use core::duration;
+use core::ignore::{Ignore, IgnoreList, IgnoreScope, IgnoreTarget};
+use core::permissions::ADMIN_PERMISSION;
+use client::PluginRegister;
+use events::CommandEvent;
+
+fn save(event: &CommandEvent, ignores: &IgnoreList) {
+    if let Err(e) = ignores.save() {
+        error!("Failed to save ignore list: {}", e);
+        event.client.reply_notice(event, "Failed to save the ignore list, so the change will be \
+            lost on restart.");
+    }
+}
+
+fn ignore(event: &CommandEvent) {
+    let scope = if event.parsed.flag("all") { IgnoreScope::All } else { IgnoreScope::Commands };
+    let length = event.parsed.duration("for");
+    let target = IgnoreTarget::parse(event.parsed.text("target").unwrap());
+    let reason = event.parsed.text("reason").map(|s| s.to_string());
+
+    let description = format!("{}", target);
+    let new_ignore = Ignore {
+        target: target,
+        scope: scope,
+        expires: length.map(|d| duration::unix_now().saturating_add(d.as_secs())),
+        added_by: event.mask().nick().unwrap_or("*unknown*").to_string(),
+        reason: reason,
+    };
+    let mut ignores = event.client.ignores().write().unwrap();
+    ignores.remove_expired(duration::unix_now());
+    if let Err(e) = ignores.add(new_ignore) {
+        event.client.reply_notice(event, format!("Invalid mask regex: {}", e));
+        return;
+    }
+    save(event, &ignores);
+    let length_str = match length {
+        Some(d) => format!(" for {}", duration::format(d)),
+        None => String::new(),
+    };
+    event.client.reply_notice(event, format!("Ignoring {} from {}{}.", scope, description,
+        length_str));
+}
+
+fn unignore(event: &CommandEvent) {
+    let target = IgnoreTarget::parse(event.parsed.text("target").unwrap());
+    let mut ignores = event.client.ignores().write().unwrap();
+    ignores.remove_expired(duration::unix_now());
+    match ignores.remove(&target) {
+        Some(_) => {
+            save(event, &ignores);
+            event.client.reply_notice(event, format!("No longer ignoring {}.", target));
+        },
+        None => event.client.reply_notice(event, format!("{} isn't ignored.", target)),
+    }
+}
+
+fn ignores(event: &CommandEvent) {
+    let mut ignores = event.client.ignores().write().unwrap();
+    let now = duration::unix_now();
+    if ignores.remove_expired(now) > 0 {
+        save(event, &ignores);
+    }
+    let list = ignores.ignores();
+    if list.is_empty() {
+        event.client.reply_notice(event, "Nobody is ignored.");
+        return;
+    }
+    for ignored in list {
+        let mut line = format!("{} ({}, by {}", ignored.target, ignored.scope, ignored.added_by);
+        if let Some(remaining) = ignored.remaining(now) {
+            line.push_str(&format!(", expires in {}", duration::format(remaining)));
+        }
+        line.push(')');
+        if let Some(ref reason) = ignored.reason {
+            line.push_str(&format!(": {}", reason));
+        }
+        event.client.reply_notice(event, line);
+    }
+}
+
+pub fn register(register: &mut PluginRegister) {
+    register.register_described_command(CommandDescriptor::new("ignore")
+        .summary("Ignores commands, or with -all everything, from a user.")
+        .args(ArgSpec::new()
+            .flag("all")
+            .flag_with_value("for", ArgKind::Duration)
+            .required("target", ArgKind::Word)
+            .optional("reason", ArgKind::Rest))
+        .example("-for 1h SpamBot")
+        .example("-all *!*@spam.example.com flooding")
+        .category("moderation")
+        .permission(ADMIN_PERMISSION), ignore);
+    register.register_described_command(CommandDescriptor::new("unignore")
+        .summary("Stops ignoring a user.")
+        .args(ArgSpec::new().required("target", ArgKind::Word))
+        .category("moderation")
+        .permission(ADMIN_PERMISSION), unignore);
+    register.register_described_command(CommandDescriptor::new("ignores")
+        .summary("Lists ignored users.")
+        .category("moderation")
+        .permission(ADMIN_PERMISSION), ignores);
+}
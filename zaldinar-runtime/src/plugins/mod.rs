@@ -1,15 +1,37 @@
 use client::PluginRegister;
 
 mod tracker;
+mod caps;
 mod log;
 mod ctcp;
 mod help;
+mod ignore;
+mod channels;
+mod aliases;
+mod filters;
+mod remind;
 
 pub fn register_plugins(register: &mut PluginRegister) {
+    register.begin_plugin("tracker");
     tracker::register(register);
+    register.begin_plugin("caps");
+    caps::register(register);
+    register.begin_plugin("log");
     log::register(register);
+    register.begin_plugin("ctcp");
     ctcp::register(register);
+    register.begin_plugin("help");
     help::register(register);
+    register.begin_plugin("ignore");
+    ignore::register(register);
+    register.begin_plugin("channels");
+    channels::register(register);
+    register.begin_plugin("aliases");
+    aliases::register(register);
+    register.begin_plugin("filters");
+    filters::register(register);
+    register.begin_plugin("remind");
+    remind::register(register);
 }
 
 // TODO: Implement commands from http://sprunge.us/KSSH
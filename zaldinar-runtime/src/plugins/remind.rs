@@ -0,0 +1,74 @@
+use std::time;
+
+use core::args::{ArgSpec, ArgKind};
+use core::commands::CommandDescriptor;
+use core::duration;
+use client::PluginRegister;
+use events::{CommandEvent, JobEvent};
+
+/// Handler name for reminder jobs, which are saved so that reminders survive restarts.
+const REMINDER_JOB: &'static str = "remind";
+/// The most reminders one nick can have waiting at once.
+const MAX_REMINDERS_PER_USER: usize = 10;
+/// The longest a reminder can be set for, in seconds: a year.
+const MAX_DELAY_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// A reminder's payload: where to remind, who to remind and what to say.
+type Reminder = (String, String, String);
+
+/// Counts the reminders waiting for `nick`.
+fn waiting_reminders(event: &CommandEvent, nick: &str) -> usize {
+    let casemapping = event.client.state().read().unwrap().support.casemapping;
+    return event.client.scheduler().jobs(REMINDER_JOB).iter()
+        .filter_map(|job| job.payload::<Reminder>().ok())
+        .filter(|&(_, ref reminded, _)| casemapping.equals(reminded, nick))
+        .count();
+}
+
+fn remind(event: &CommandEvent) {
+    let nick = match event.mask().nick() {
+        Some(v) => v,
+        None => return,
+    };
+    let delay = event.parsed.duration("in").unwrap();
+    let message = event.parsed.text("message").unwrap();
+    if delay.as_secs() > MAX_DELAY_SECONDS {
+        event.reply_error(format!("Reminders can be set for at most {}.",
+            duration::format(time::Duration::from_secs(MAX_DELAY_SECONDS))));
+        return;
+    }
+    if waiting_reminders(event, nick) >= MAX_REMINDERS_PER_USER {
+        event.reply_error(format!("You already have {} reminders waiting.",
+            MAX_REMINDERS_PER_USER));
+        return;
+    }
+    let reminder = (event.channel(), nick, message);
+    match event.client.scheduler().after(REMINDER_JOB, delay, &reminder) {
+        Ok(_) => event.reply(format!("I'll remind you in {}.", duration::format(delay))),
+        Err(e) => {
+            error!("Failed to schedule reminder: {}", e);
+            event.reply_error("Failed to schedule the reminder.");
+        },
+    }
+}
+
+fn send_reminder(event: &JobEvent) {
+    let (channel, nick, message) = match event.payload::<Reminder>() {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Invalid reminder {}: {}", event.id, e);
+            return;
+        },
+    };
+    event.client.send_message(channel, format!("{}: {}", nick, message));
+}
+
+pub fn register(register: &mut PluginRegister) {
+    register.register_described_command(CommandDescriptor::new("remind")
+        .summary("Reminds you of something after a while.")
+        .args(ArgSpec::new()
+            .required("in", ArgKind::Duration)
+            .required("message", ArgKind::Rest))
+        .example("10m check the oven"), remind);
+    register.register_job(REMINDER_JOB, send_reminder);
+}
@@ -1,5 +1,6 @@
 use client::PluginRegister;
-use events::MessageEvent;
+use events::{MessageEvent, Lifecycle};
+use core::modes;
 
 fn on_connect(event: &MessageEvent) {
     for command in &event.client.on_connect {
@@ -24,6 +25,8 @@ fn on_connect(event: &MessageEvent) {
         let mut state = event.client.state().write().unwrap();
         state.channels.extend(event.client.channels.iter().map(|s: &String| s.clone()));
     }
+
+    event.client.run_lifecycle_hooks(Lifecycle::Connect);
 }
 
 fn on_join(event: &MessageEvent) {
@@ -31,32 +34,141 @@ fn on_join(event: &MessageEvent) {
         return;
     }
     if let Some(nick) = event.mask.nick() {
-        let same = {
-            let state = event.client.state().read().unwrap();
-            nick == state.nick
-        };
-        if same {
-            let mut state = event.client.state().write().unwrap();
-            state.channels.push(event.channel().unwrap().to_string());
+        let mut state = event.client.state().write().unwrap();
+        let channel = event.channel().unwrap().trim_left_matches(':');
+        if state.is_own_nick(nick) {
+            state.channels.push(channel.to_string());
+            state.add_channel(channel);
+        } else if let Some(channel_state) = state.channel_mut(channel) {
+            channel_state.add_user(nick, Vec::new());
+        }
+    }
+}
+
+/// Removes either the bot from a channel, or a user from the bot's record of a channel.
+fn leave_channel(event: &MessageEvent, channel: &str, nick: &str) {
+    let mut state = event.client.state().write().unwrap();
+    if state.is_own_nick(nick) {
+        let casemapping = state.support.casemapping;
+        state.channels.retain(|c| !casemapping.equals(c, channel));
+        state.remove_channel(channel);
+    } else if let Some(channel_state) = state.channel_mut(channel) {
+        channel_state.remove_user(nick);
+    }
+}
+
+fn on_part(event: &MessageEvent) {
+    if let Some(nick) = event.mask.nick() {
+        leave_channel(event, event.args[0].trim_left_matches(':'), nick);
+    }
+}
+
+fn on_kick(event: &MessageEvent) {
+    if event.args.len() > 1 {
+        leave_channel(event, &event.args[0], &event.args[1]);
+    }
+}
+
+fn on_quit(event: &MessageEvent) {
+    if let Some(nick) = event.mask.nick() {
+        let mut state = event.client.state().write().unwrap();
+        for channel_state in state.channel_states.values_mut() {
+            channel_state.remove_user(nick);
+        }
+    }
+}
+
+fn on_names(event: &MessageEvent) {
+    // RPL_NAMREPLY: `<our nick> <channel type> <channel> :[prefixes]nick [prefixes]nick ...`
+    if event.args.len() < 4 {
+        return;
+    }
+    let mut state = event.client.state().write().unwrap();
+    let users = event.args[3..].iter().map(|s| s.trim_left_matches(':')).map(|name| {
+        // Servers with multi-prefix enabled may send several prefix symbols per user.
+        let nick = name.trim_left_matches(|c: char| state.support.prefix_mode(c).is_some());
+        let prefix_modes = name[..name.len() - nick.len()].chars()
+            .filter_map(|c| state.support.prefix_mode(c)).collect::<Vec<char>>();
+        (nick.to_string(), prefix_modes)
+    }).collect::<Vec<_>>();
+    if let Some(channel_state) = state.channel_mut(&event.args[2]) {
+        for (nick, prefix_modes) in users {
+            channel_state.add_user(&nick, prefix_modes);
+        }
+    }
+}
+
+fn on_isupport(event: &MessageEvent) {
+    if event.args.len() > 1 {
+        let mut state = event.client.state().write().unwrap();
+        state.support.apply_tokens(&event.args[1..]);
+    }
+}
+
+fn apply_channel_modes(event: &MessageEvent, channel: &str, args: &[String]) {
+    let mut state = event.client.state().write().unwrap();
+    let changes = modes::parse_channel_modes(&state.support, args);
+    if let Some(channel_state) = state.channel_mut(channel) {
+        for change in &changes {
+            channel_state.apply_mode_change(change);
+        }
+    }
+}
+
+fn on_mode(event: &MessageEvent) {
+    if event.args.len() < 2 {
+        return;
+    }
+    let is_channel = {
+        let state = event.client.state().read().unwrap();
+        state.support.is_channel(&event.args[0])
+    };
+    if is_channel {
+        apply_channel_modes(event, &event.args[0], &event.args[1..]);
+    } else {
+        let mut state = event.client.state().write().unwrap();
+        if state.is_own_nick(&event.args[0]) {
+            for change in modes::parse_user_modes(&event.args[1..]) {
+                state.modes.retain(|m| *m != change.mode);
+                if change.adding {
+                    state.modes.push(change.mode);
+                }
+            }
         }
     }
 }
 
+fn on_channel_mode_is(event: &MessageEvent) {
+    // RPL_CHANNELMODEIS: `<our nick> <channel> <modes> [parameters...]`
+    if event.args.len() > 2 {
+        apply_channel_modes(event, &event.args[1], &event.args[2..]);
+    }
+}
+
 fn on_nick(event: &MessageEvent) {
     if let Some(nick) = event.mask.nick() {
-        let same = {
-            let state = event.client.state().read().unwrap();
-            nick == state.nick
-        };
-        if same {
-            let mut state = event.client.state().write().unwrap();
-            state.nick = event.args[0].to_string();
+        let new_nick = event.args[0].trim_left_matches(':');
+        let mut state = event.client.state().write().unwrap();
+        if state.is_own_nick(nick) {
+            state.nick = new_nick.to_string();
+        }
+        for channel_state in state.channel_states.values_mut() {
+            channel_state.rename_user(nick, new_nick);
         }
     }
 }
 
 pub fn register(register: &mut PluginRegister) {
+    // This runs the connect hooks, which may wait on the server, so it can't be a tracking
+    // listener.
     register.register_irc("004", on_connect);
-    register.register_irc("join", on_join);
-    register.register_irc("nick", on_nick);
+    register.register_tracking("005", on_isupport);
+    register.register_tracking("324", on_channel_mode_is);
+    register.register_tracking("353", on_names);
+    register.register_tracking("join", on_join);
+    register.register_tracking("part", on_part);
+    register.register_tracking("kick", on_kick);
+    register.register_tracking("quit", on_quit);
+    register.register_tracking("nick", on_nick);
+    register.register_tracking("mode", on_mode);
 }
@@ -0,0 +1,167 @@
+use std::collections;
+use std::time;
+
+use core::commands;
+use core::config::{CooldownConf, RateLimitConf};
+
+/// Once this many cooldowns are stored, expired ones are removed.
+const PRUNE_THRESHOLD: usize = 1000;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Scope {
+    /// A user, identified as given to `RateLimiter::check`.
+    User(String),
+    Channel(String),
+    Global,
+}
+
+/// Key of a running cooldown: the command path it is for, or None for `all_commands`, and who
+/// or where it applies to.
+type CooldownKey = (Option<String>, Scope);
+
+/// Keeps track of command cooldowns, as configured in `rate_limits`.
+///
+/// Like account tracking, this runs in the dispatch thread so that a command is throttled before
+/// it ever reaches a worker.
+pub struct RateLimiter {
+    all_commands: CooldownConf,
+    /// Cooldowns by normalized command path.
+    commands: collections::HashMap<String, CooldownConf>,
+    /// When each running cooldown ends.
+    cooldowns: collections::HashMap<CooldownKey, time::Instant>,
+    /// User -> time until which the user won't be told about being throttled again.
+    quiet_until: collections::HashMap<String, time::Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConf) -> RateLimiter {
+        return RateLimiter {
+            all_commands: config.all_commands.clone(),
+            commands: config.commands.iter()
+                .map(|(path, cooldown)| (commands::command_path(path), cooldown.clone()))
+                .collect(),
+            cooldowns: collections::HashMap::new(),
+            quiet_until: collections::HashMap::new(),
+        };
+    }
+
+    /// Finds the cooldowns which using a command starts.
+    ///
+    /// `user` identifies the user in a way which doesn't change with their nick, and `channel`
+    /// is lowercased. `chain` is the command's path followed by the paths of the groups it is in,
+    /// innermost first.
+    fn cooldowns_for(&self, chain: &[String], user: &str, channel: &str)
+            -> Vec<(CooldownKey, time::Duration)> {
+        let mut cooldowns = Vec::new();
+        add_cooldowns(&mut cooldowns, None, &self.all_commands, user, channel);
+        if let Some(path) = chain.iter().find(|path| self.commands.contains_key(*path)) {
+            add_cooldowns(&mut cooldowns, Some(path), &self.commands[path], user, channel);
+        }
+        return cooldowns;
+    }
+
+    /// Checks whether `user` may use a command in `channel` now, without starting its cooldowns.
+    /// Returns how much longer the user needs to wait if the command may not be used.
+    ///
+    /// The arguments are as for `start`. A command which passes should be given to `start` once
+    /// it is certain to run, so that a pipeline can check all of its commands first.
+    pub fn check(&self, chain: &[String], user: &str, channel: &str)
+            -> Result<(), time::Duration> {
+        let now = time::Instant::now();
+        let wait = self.cooldowns_for(chain, user, channel).iter()
+            .filter_map(|&(ref key, _)| self.cooldowns.get(key))
+            .filter(|end| **end > now)
+            .map(|end| *end - now)
+            .max();
+        return match wait {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        };
+    }
+
+    /// Starts the cooldowns of a command `user` used in `channel`.
+    ///
+    /// `user` identifies the user in a way which doesn't change with their nick, and `channel`
+    /// is lowercased. `chain` is the command's path followed by the paths of the groups it is in,
+    /// innermost first.
+    pub fn start(&mut self, chain: &[String], user: &str, channel: &str) {
+        let now = time::Instant::now();
+        for (key, length) in self.cooldowns_for(chain, user, channel) {
+            self.cooldowns.insert(key, now + length);
+        }
+        if self.cooldowns.len() > PRUNE_THRESHOLD {
+            self.cooldowns.retain(|_, end| *end > now);
+            self.quiet_until.retain(|_, end| *end > now);
+        }
+    }
+
+    /// Checks whether a throttled user should be told so. This is true at most once for each
+    /// time they are throttled, so that spamming commands doesn't also make the bot spam.
+    pub fn should_notify(&mut self, user: &str, wait: time::Duration) -> bool {
+        let now = time::Instant::now();
+        if let Some(end) = self.quiet_until.get(user) {
+            if *end > now {
+                return false;
+            }
+        }
+        self.quiet_until.insert(user.to_string(), now + wait);
+        return true;
+    }
+}
+
+fn add_cooldowns(started: &mut Vec<(CooldownKey, time::Duration)>, path: Option<&String>,
+        cooldown: &CooldownConf, user: &str, channel: &str) {
+    let scopes = [
+        (Scope::User(user.to_string()), cooldown.user_seconds),
+        (Scope::Channel(channel.to_string()), cooldown.channel_seconds),
+        (Scope::Global, cooldown.global_seconds),
+    ];
+    for &(ref scope, seconds) in &scopes {
+        if seconds > 0 {
+            started.push(((path.cloned(), scope.clone()), time::Duration::from_secs(seconds)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::config::{CooldownConf, RateLimitConf};
+    use super::RateLimiter;
+
+    fn limiter() -> RateLimiter {
+        let mut config = RateLimitConf::default();
+        config.commands.insert("slow".to_string(), CooldownConf {
+            user_seconds: 60,
+            ..CooldownConf::default()
+        });
+        return RateLimiter::new(&config);
+    }
+
+    #[test]
+    fn check_does_not_start_cooldowns() {
+        let mut limiter = limiter();
+        let chain = vec!["slow".to_string()];
+        assert!(limiter.check(&chain, "host:a@b", "#c").is_ok());
+        assert!(limiter.check(&chain, "host:a@b", "#c").is_ok());
+        limiter.start(&chain, "host:a@b", "#c");
+        assert!(limiter.check(&chain, "host:a@b", "#c").is_err());
+        assert!(limiter.check(&chain, "host:x@y", "#c").is_ok());
+        assert!(limiter.check(&["fast".to_string()], "host:a@b", "#c").is_ok());
+    }
+
+    #[test]
+    fn groups_share_cooldowns() {
+        let mut limiter = limiter();
+        limiter.start(&["slow one".to_string(), "slow".to_string()], "host:a@b", "#c");
+        assert!(limiter.check(&["slow two".to_string(), "slow".to_string()], "host:a@b", "#c")
+            .is_err());
+    }
+
+    #[test]
+    fn throttled_users_are_told_once() {
+        let mut limiter = limiter();
+        let wait = ::std::time::Duration::from_secs(60);
+        assert!(limiter.should_notify("host:a@b", wait));
+        assert!(!limiter.should_notify("host:a@b", wait));
+    }
+}
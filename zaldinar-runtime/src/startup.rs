@@ -4,7 +4,7 @@ use {fern, chrono};
 
 use generated_plugins_crate;
 use errors::ThrowInitError;
-use {plugins, interface, config, dispatch, irc, client};
+use {plugins, interface, config, dispatch, irc, client, events};
 #[cfg(feature = "binary-filewatch")]
 use filewatch;
 
@@ -61,6 +61,8 @@ pub fn prepare(mut plugins: client::PluginRegister,
     // Register built-in plugins
     plugins::register_plugins(&mut plugins);
     generated_plugins_crate::register(&mut plugins);
+    up!(plugins.check_registrations());
+    up!(plugins.load_plugin_configs(&config));
 
     let client = client::Client::new(plugins, config);
 
@@ -69,6 +71,11 @@ pub fn prepare(mut plugins: client::PluginRegister,
 
     let interface = up!(interface::IrcInterface::new(data_out, client.clone()));
 
+    // Load hooks run before any worker, scheduler or bus thread starts, so that plugins can set
+    // up their state before anything else of theirs runs. Jobs they schedule and events they
+    // publish wait until the threads are started.
+    interface.run_lifecycle_hooks(events::Lifecycle::Load);
+
     // Create dispatch, and start the worker threads for plugin execution
     let dispatch = dispatch::Dispatch::new(interface.clone(), client.clone(), data_in);
 
@@ -92,9 +99,11 @@ pub fn run_with_plugins(config: config::ClientConfiguration,
     // Load file watcher
     start_file_watch(&client, &interface);
 
-    // Send PASS, NICK and USER, the initial IRC commands. Because an IrcConnection hasn't been
-    // created to receive these yet, they will just go on hold and get sent as soon as the
+    // Send CAP, PASS, NICK and USER, the initial IRC commands. Because an IrcConnection hasn't
+    // been created to receive these yet, they will just go on hold and get sent as soon as the
     // IrcConnection connects.
+    // CAP LS first, so that the server holds registration until capability negotiation is done.
+    interface.send_command::<&str, &str>("CAP", &["LS"]);
     if let Some(ref pass) = client.password {
         interface.send_command::<&str, &str>("PASS", &[&pass]);
     }
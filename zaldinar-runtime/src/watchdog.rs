@@ -0,0 +1,98 @@
+use std::collections;
+use std::sync;
+use std::sync::atomic;
+use std::thread;
+use std::time;
+
+use core::duration;
+
+/// How often the watchdog checks on running listeners and commands, in milliseconds.
+const CHECK_INTERVAL_MILLIS: u64 = 1000;
+
+struct Running {
+    description: String,
+    started: time::Instant,
+    /// Whether a warning has been logged about this already.
+    reported: bool,
+}
+
+/// Keeps track of what each worker thread is running, and reports listeners and commands which
+/// run for longer than `watchdog_seconds` from `workers` in the configuration.
+///
+/// A stuck listener can't be stopped, but it does hold up one worker thread for good, so it is
+/// worth knowing about.
+pub struct Watchdog {
+    limit: time::Duration,
+    next_token: atomic::AtomicUsize,
+    running: sync::Mutex<collections::HashMap<usize, Running>>,
+    stopped: atomic::AtomicBool,
+}
+
+impl Watchdog {
+    pub fn new(limit: time::Duration) -> Watchdog {
+        return Watchdog {
+            limit: limit,
+            next_token: atomic::AtomicUsize::new(0),
+            running: sync::Mutex::new(collections::HashMap::new()),
+            stopped: atomic::AtomicBool::new(false),
+        };
+    }
+
+    /// Records that a worker has started running something. Returns a token to give to
+    /// `finish` once it is done.
+    pub fn start(&self, description: &str) -> usize {
+        let token = self.next_token.fetch_add(1, atomic::Ordering::SeqCst);
+        self.running.lock().unwrap().insert(token, Running {
+            description: description.to_string(),
+            started: time::Instant::now(),
+            reported: false,
+        });
+        return token;
+    }
+
+    /// Records that something has finished running, returning how long it took.
+    pub fn finish(&self, token: usize) -> time::Duration {
+        return match self.running.lock().unwrap().remove(&token) {
+            Some(running) => {
+                if running.reported {
+                    info!("{} finished after {}.", running.description,
+                        duration::format(running.started.elapsed()));
+                }
+                running.started.elapsed()
+            },
+            None => time::Duration::from_secs(0),
+        };
+    }
+
+    /// Logs a warning about each listener or command which has been running for too long. Each
+    /// is only reported once.
+    fn check(&self) {
+        let mut running = self.running.lock().unwrap();
+        for running in running.values_mut() {
+            let elapsed = running.started.elapsed();
+            if !running.reported && elapsed > self.limit {
+                warn!("{} has been running for {}, and may be stuck.", running.description,
+                    duration::format(elapsed));
+                running.reported = true;
+            }
+        }
+    }
+
+    /// Stops the watchdog thread.
+    pub fn stop(&self) {
+        self.stopped.store(true, atomic::Ordering::SeqCst);
+    }
+}
+
+pub fn start_watchdog_thread(watchdog: sync::Arc<Watchdog>) {
+    let r = thread::Builder::new().name("watchdog_thread".to_string()).spawn(move || {
+        while !watchdog.stopped.load(atomic::Ordering::SeqCst) {
+            thread::sleep(time::Duration::from_millis(CHECK_INTERVAL_MILLIS));
+            watchdog.check();
+        }
+    });
+    if let Err(e) = r {
+        error!("Failed to start the watchdog thread! Stuck plugins will not be reported! \
+            Error: {}", e);
+    }
+}